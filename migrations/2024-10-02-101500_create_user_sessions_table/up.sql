@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE user_sessions (
+   session_id uuid NOT NULL,
+   user_id uuid NOT NULL REFERENCES users (user_id),
+   user_agent TEXT,
+   ip_address TEXT,
+   created_at timestamptz NOT NULL,
+   last_seen_at timestamptz NOT NULL,
+   revoked_at timestamptz,
+   PRIMARY KEY(session_id)
+);
+
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
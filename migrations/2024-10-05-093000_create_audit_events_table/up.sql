@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TABLE audit_events (
+   audit_event_id uuid NOT NULL,
+   user_id uuid REFERENCES users (user_id),
+   action TEXT NOT NULL,
+   target TEXT,
+   metadata jsonb NOT NULL DEFAULT '{}'::jsonb,
+   ip_address TEXT,
+   created_at timestamptz NOT NULL,
+   PRIMARY KEY(audit_event_id)
+);
+
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX audit_events_user_id_idx ON audit_events (user_id);
+
+-- Audit events are append-only.
+CREATE OR REPLACE FUNCTION audit_events_are_immutable() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events rows cannot be modified or deleted';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_immutable
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE PROCEDURE audit_events_are_immutable();
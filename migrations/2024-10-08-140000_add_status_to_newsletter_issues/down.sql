@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS set_updated_at ON newsletter_issues;
+
+DELETE FROM newsletter_issues WHERE published_at IS NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at SET NOT NULL;
+
+ALTER TABLE newsletter_issues
+    DROP COLUMN updated_at,
+    DROP COLUMN created_by,
+    DROP COLUMN status;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'sent'
+        CHECK (status IN ('draft', 'scheduled', 'sending', 'sent', 'cancelled')),
+    ADD COLUMN created_by uuid NULL
+        REFERENCES users (user_id) ON DELETE SET NULL,
+    ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+
+-- Existing issues were sent straight away; new rows must pick a status.
+ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
+
+-- Drafts have not been published yet.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+SELECT diesel_manage_updated_at('newsletter_issues');
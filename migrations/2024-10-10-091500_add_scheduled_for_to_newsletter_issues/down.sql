@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX newsletter_issues_due_idx;
+
+UPDATE newsletter_issues SET status = 'draft' WHERE status = 'scheduled';
+
+ALTER TABLE newsletter_issues DROP CONSTRAINT scheduled_issues_have_a_time;
+ALTER TABLE newsletter_issues DROP COLUMN scheduled_for;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+
+ALTER TABLE newsletter_issues ADD CONSTRAINT scheduled_issues_have_a_time
+    CHECK (status <> 'scheduled' OR scheduled_for IS NOT NULL);
+
+CREATE INDEX newsletter_issues_due_idx
+    ON newsletter_issues (scheduled_for)
+    WHERE status = 'scheduled';
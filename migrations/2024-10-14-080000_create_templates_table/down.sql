@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE newsletter_issues DROP COLUMN template_id;
+DROP TABLE templates;
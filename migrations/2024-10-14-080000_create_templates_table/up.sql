@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TABLE templates (
+    template_id uuid PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    kind TEXT NOT NULL CHECK (kind IN ('issue', 'confirmation')),
+    subject TEXT NOT NULL,
+    html TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- There is exactly one confirmation email.
+CREATE UNIQUE INDEX templates_single_confirmation_idx
+    ON templates (kind)
+    WHERE kind = 'confirmation';
+
+SELECT diesel_manage_updated_at('templates');
+
+INSERT INTO templates (template_id, name, kind, subject, html, text)
+VALUES (
+    '5d1c6c39-2b4e-4a8e-9a53-0f8d2f6f3a11',
+    'Subscription confirmation',
+    'confirmation',
+    'Welcome!',
+    'Welcome to our newsletter! Click <a href="{{confirmation_url}}">here</a> to confirm your subscription.',
+    'Welcome to our newsletter! Visit {{confirmation_url}} to confirm your subscription.'
+);
+
+ALTER TABLE newsletter_issues ADD COLUMN template_id uuid NULL
+    REFERENCES templates (template_id) ON DELETE SET NULL;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE issue_deliveries;
+
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at TYPE TEXT USING published_at::text;
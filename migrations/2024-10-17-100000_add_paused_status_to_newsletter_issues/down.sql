@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+UPDATE newsletter_issues SET status = 'sending' WHERE status = 'paused';
+
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'sending', 'sent', 'cancelled'));
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE issue_opens;
+
+ALTER TABLE newsletter_issues DROP COLUMN track_opens;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE issue_opens(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    first_opened_at timestamptz NOT NULL,
+    last_opened_at timestamptz NOT NULL,
+    open_count INTEGER NOT NULL DEFAULT 1,
+    user_agent TEXT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
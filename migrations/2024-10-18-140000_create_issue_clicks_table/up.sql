@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE issue_clicks(
+    issue_click_id uuid PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    user_agent TEXT NULL,
+    clicked_at timestamptz NOT NULL
+);
+
+CREATE INDEX issue_clicks_newsletter_issue_id_idx ON issue_clicks (newsletter_issue_id);
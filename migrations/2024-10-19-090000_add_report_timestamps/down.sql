@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE subscriptions DROP COLUMN unsubscribed_at;
+ALTER TABLE issue_deliveries DROP COLUMN bounced_at;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE issue_deliveries ADD COLUMN bounced_at timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
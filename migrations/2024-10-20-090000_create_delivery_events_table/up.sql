@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE delivery_events(
+    delivery_event_id uuid PRIMARY KEY,
+    kind TEXT NOT NULL
+        CONSTRAINT delivery_events_kind_check CHECK (kind IN ('bounce', 'spam_complaint', 'delivery')),
+    email TEXT NOT NULL,
+    subscriber_id uuid NULL
+        REFERENCES subscriptions (id) ON DELETE SET NULL,
+    newsletter_issue_id uuid NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE SET NULL,
+    message_id TEXT NULL,
+    bounce_type TEXT NULL,
+    description TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL,
+    -- Postmark retries webhooks it did not get a 200 for.
+    CONSTRAINT delivery_events_message_id_kind_key UNIQUE (message_id, kind)
+);
+
+CREATE INDEX delivery_events_newsletter_issue_id_idx ON delivery_events (newsletter_issue_id);
+CREATE INDEX delivery_events_email_idx ON delivery_events (email);
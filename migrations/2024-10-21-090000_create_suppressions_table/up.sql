@@ -0,0 +1,26 @@
+-- Your SQL goes here
+CREATE TABLE suppressions(
+    -- Stored lowercased, addresses are compared case-insensitively.
+    email TEXT PRIMARY KEY,
+    reason TEXT NOT NULL
+        CONSTRAINT suppressions_reason_check CHECK (reason IN ('hard_bounce', 'spam_complaint', 'unsubscribed', 'manual')),
+    source TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+INSERT INTO suppressions (email, reason, source, created_at)
+SELECT DISTINCT ON (lower(e.email))
+    lower(e.email),
+    CASE e.kind WHEN 'spam_complaint' THEN 'spam_complaint' ELSE 'hard_bounce' END,
+    'postmark',
+    e.received_at
+FROM delivery_events e
+JOIN subscriptions s ON s.email = e.email
+WHERE s.status = 'suppressed' AND e.kind IN ('bounce', 'spam_complaint')
+ORDER BY lower(e.email), e.received_at;
+
+INSERT INTO suppressions (email, reason, source, created_at)
+SELECT lower(email), 'unsubscribed', 'unsubscribe_link', COALESCE(unsubscribed_at, now())
+FROM subscriptions
+WHERE status = 'unsubscribed'
+ON CONFLICT (email) DO NOTHING;
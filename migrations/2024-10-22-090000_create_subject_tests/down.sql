@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE issue_deliveries DROP COLUMN subject_variant_id;
+ALTER TABLE issue_delivery_queue DROP COLUMN subject_variant_id;
+DROP TABLE subject_tests;
+DROP TABLE subject_variants;
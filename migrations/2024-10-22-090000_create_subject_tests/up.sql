@@ -0,0 +1,35 @@
+-- Your SQL goes here
+-- An issue with a subject test is first sent to a sample of the list, split
+-- evenly between its subject variants. Once the wait is over the variant with
+-- the best open or click rate is sent to everybody else.
+CREATE TABLE subject_tests (
+    newsletter_issue_id uuid PRIMARY KEY
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    sample_percent INTEGER NOT NULL CHECK (sample_percent BETWEEN 1 AND 100),
+    wait_minutes INTEGER NOT NULL CHECK (wait_minutes >= 0),
+    metric TEXT NOT NULL CHECK (metric IN ('opens', 'clicks')),
+    winning_variant_id uuid NULL,
+    decided_at timestamptz NULL
+);
+
+CREATE TABLE subject_variants (
+    subject_variant_id uuid PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    subject TEXT NOT NULL,
+    UNIQUE (newsletter_issue_id, position)
+);
+
+ALTER TABLE subject_tests
+    ADD FOREIGN KEY (winning_variant_id) REFERENCES subject_variants (subject_variant_id);
+
+-- The variant a queued recipient will be sent. Recipients outside the sample
+-- wait with state 'held' and no variant until the winner is known.
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN subject_variant_id uuid NULL
+        REFERENCES subject_variants (subject_variant_id) ON DELETE CASCADE;
+
+ALTER TABLE issue_deliveries
+    ADD COLUMN subject_variant_id uuid NULL
+        REFERENCES subject_variants (subject_variant_id) ON DELETE SET NULL;
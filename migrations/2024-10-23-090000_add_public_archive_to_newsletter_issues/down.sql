@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE newsletter_issues DROP COLUMN is_public;
+ALTER TABLE newsletter_issues DROP COLUMN slug;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Issues marked public are listed at /issues and readable at /issues/{slug}.
+-- The slug is derived from the title when the issue is published.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
+ALTER TABLE newsletter_issues ADD COLUMN is_public BOOLEAN NOT NULL DEFAULT false;
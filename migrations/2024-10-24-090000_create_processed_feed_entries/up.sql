@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Entries of a polled feed that have been turned into issues, keyed by the
+-- feed they came from and their GUID so each is only picked up once.
+CREATE TABLE processed_feed_entries(
+    source TEXT NOT NULL,
+    guid TEXT NOT NULL,
+    newsletter_issue_id uuid NULL REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE SET NULL,
+    processed_at timestamptz NOT NULL,
+    PRIMARY KEY (source, guid)
+);
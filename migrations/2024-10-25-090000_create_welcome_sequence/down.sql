@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE sequence_deliveries;
+DROP TABLE sequence_enrollments;
+DROP TABLE sequence_steps;
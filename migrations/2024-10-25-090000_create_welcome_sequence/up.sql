@@ -0,0 +1,30 @@
+-- Your SQL goes here
+-- Emails sent to new subscribers a number of days after they confirm.
+CREATE TABLE sequence_steps(
+    sequence_step_id uuid PRIMARY KEY,
+    delay_days INTEGER NOT NULL CHECK (delay_days >= 0),
+    subject TEXT NOT NULL,
+    html TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+-- When each subscriber entered the sequence, i.e. first confirmed.
+CREATE TABLE sequence_enrollments(
+    subscriber_id uuid PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+    enrolled_at timestamptz NOT NULL
+);
+
+-- One row per subscriber and step, queued on enrollment. The outcome stays
+-- null until the worker has handled the row, so a step is never sent twice.
+CREATE TABLE sequence_deliveries(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    sequence_step_id uuid NOT NULL REFERENCES sequence_steps (sequence_step_id) ON DELETE CASCADE,
+    due_at timestamptz NOT NULL,
+    outcome TEXT NULL CHECK (outcome IN ('sent', 'failed', 'skipped')),
+    detail TEXT NULL,
+    attempted_at timestamptz NULL,
+    PRIMARY KEY (subscriber_id, sequence_step_id)
+);
+CREATE INDEX sequence_deliveries_pending_idx ON sequence_deliveries (due_at) WHERE outcome IS NULL;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Why the scheduler could not publish the issue at its scheduled time, shown
+-- to editors until the issue is scheduled or published again.
+ALTER TABLE newsletter_issues ADD COLUMN schedule_error TEXT NULL;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Feeds the poller has started following. Entries already in a feed when it
+-- is first polled are only remembered, not sent.
+CREATE TABLE followed_feeds(
+    source TEXT NOT NULL PRIMARY KEY,
+    started_at timestamptz NOT NULL
+);
+INSERT INTO followed_feeds (source, started_at)
+SELECT source, min(processed_at) FROM processed_feed_entries GROUP BY source;
@@ -0,0 +1,322 @@
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use lol_html::{element, errors::RewritingError, rewrite_str, RewriteStrSettings};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::{issues::IssueStatus, models::NewsletterIssue, rendering::{render_issue, render_title, Recipient, RenderedEmail}, utils::html_escape};
+
+/// Longest slug generated from a title, before a suffix is added to keep it
+/// unique.
+const MAX_SLUG_LENGTH: usize = 80;
+
+/// Turns a title into the last segment of its public URL, e.g.
+/// "Rust & Friends #3" becomes "rust-friends-3".
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LENGTH);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "issue".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Gives a published issue its slug, unless it already has one. Titles that
+/// are already taken get a numeric suffix. Returns the slug.
+pub fn assign_slug(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<String, anyhow::Error> {
+    use diesel::dsl::exists;
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let (issue_title, existing) = newsletter_issues
+        .filter(newsletter_issue_id.eq(issue_id))
+        .select((title, slug))
+        .first::<(String, Option<String>)>(conn)
+        .context("Failed to fetch issue")?;
+    if let Some(existing) = existing {
+        return Ok(existing);
+    }
+
+    let base = slugify(&issue_title);
+    let mut candidate = base.clone();
+    for n in 2.. {
+        let taken = diesel::select(exists(newsletter_issues.filter(slug.eq(&candidate))))
+            .get_result::<bool>(conn)
+            .context("Failed to check slug")?;
+        if !taken {
+            break;
+        }
+        candidate = format!("{}-{}", base, n);
+    }
+
+    diesel::update(newsletter_issues.filter(newsletter_issue_id.eq(issue_id)))
+        .set(slug.eq(&candidate))
+        .execute(conn)
+        .context("Failed to store slug")?;
+
+    Ok(candidate)
+}
+
+/// Where a public issue can be read, or `None` if it is not in the archive.
+pub fn public_issue_url(issue: &NewsletterIssue, base_url: &str) -> Option<String> {
+    match (&issue.slug, issue.is_public) {
+        (Some(slug), true) => Some(format!("{}/issues/{}", base_url, slug)),
+        _ => None,
+    }
+}
+
+/// Puts a link to the public copy of the issue at the top of both parts of
+/// an email.
+pub fn add_view_in_browser_link(mut email: RenderedEmail, url: &str) -> RenderedEmail {
+    let link = format!(
+        r#"<p style="text-align: center; font-size: 12px"><a href="{}">View in browser</a></p>"#,
+        html_escape(url)
+    );
+    let lowercase = email.html.to_ascii_lowercase();
+    let insert_at = lowercase
+        .find("<body")
+        .and_then(|start| lowercase[start..].find('>').map(|end| start + end + 1))
+        .unwrap_or(0);
+    email.html.insert_str(insert_at, &link);
+    email.text = format!("View in browser: {}\n\n{}", url, email.text);
+    email
+}
+
+/// Stands in for the subscriber when an issue is rendered for everybody:
+/// merge tags come out empty or with their defaults.
+fn anonymous_recipient() -> Recipient {
+    Recipient {
+        subscriber_id: None,
+        email: String::new(),
+        name: String::new(),
+        unsubscribe_url: String::new(),
+    }
+}
+
+/// The title of an issue as the public archive lists it.
+pub fn public_issue_title(issue: &NewsletterIssue) -> String {
+    render_title(&issue.title, &anonymous_recipient())
+}
+
+/// The title and HTML body of an issue as the public archive shows them.
+///
+/// Merge tags are filled in for an anonymous reader and links that pointed
+/// at a subscriber's unsubscribe URL are removed. Tracking is only ever added
+/// to the copies the worker sends, so there is none to strip here. Only the
+/// contents of `<body>` are kept, ready to be placed in a page.
+pub fn render_public_issue(issue: &NewsletterIssue) -> Result<RenderedEmail, anyhow::Error> {
+    let mut rendered = render_issue(issue, None, &anonymous_recipient())?;
+    rendered.html = strip_personal_links(body_content(&rendered.html))?;
+    Ok(rendered)
+}
+
+fn strip_personal_links(html: &str) -> Result<String, RewritingError> {
+    rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: vec![element!("a", |el| {
+            if el.get_attribute("href").is_none_or(|href| href.trim().is_empty()) {
+                el.remove();
+            }
+            Ok(())
+        })],
+        ..RewriteStrSettings::new()
+    })
+}
+
+fn body_content(html: &str) -> &str {
+    let lowercase = html.to_ascii_lowercase();
+    let start = lowercase
+        .find("<body")
+        .and_then(|start| lowercase[start..].find('>').map(|end| start + end + 1))
+        .unwrap_or(0);
+    let end = lowercase.rfind("</body>").filter(|end| *end >= start).unwrap_or(html.len());
+    &html[start..end]
+}
+
+/// Issues that went out and are marked public, most recent first. Feeds
+/// only ask for their latest few.
+#[tracing::instrument(name = "Get public issues", skip(pool))]
+pub async fn get_public_issues(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    limit: Option<i64>,
+) -> Result<Vec<NewsletterIssue>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let issues = web::block(move || {
+        current_span.in_scope(|| {
+            let mut query = newsletter_issues
+                .filter(is_public.eq(true))
+                .filter(slug.is_not_null())
+                .filter(published_at.is_not_null())
+                .filter(status.ne(IssueStatus::Cancelled.as_str()))
+                .order(published_at.desc())
+                .into_boxed();
+            if let Some(limit) = limit {
+                query = query.limit(limit);
+            }
+
+            query
+                .load::<NewsletterIssue>(&mut conn)
+                .context("Failed to fetch public issues")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(issues)
+}
+
+/// When the archive last changed. Issues that have since been made private or
+/// cancelled count too: their `updated_at` is when they left the archive.
+#[tracing::instrument(name = "Get archive changed at", skip(pool))]
+pub async fn get_archive_changed_at(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let (last_published, last_updated) = web::block(move || {
+        current_span.in_scope(|| {
+            newsletter_issues
+                .filter(published_at.is_not_null())
+                .select((diesel::dsl::max(published_at), diesel::dsl::max(updated_at)))
+                .first::<(Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(&mut conn)
+                .context("Failed to fetch when the archive changed")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(last_published.max(last_updated))
+}
+
+#[tracing::instrument(name = "Get public issue", skip(pool))]
+pub async fn get_public_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_slug: String,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let issue = web::block(move || {
+        current_span.in_scope(|| {
+            newsletter_issues
+                .filter(slug.eq(issue_slug))
+                .filter(is_public.eq(true))
+                .filter(published_at.is_not_null())
+                .filter(status.ne(IssueStatus::Cancelled.as_str()))
+                .first::<NewsletterIssue>(&mut conn)
+                .optional()
+                .context("Failed to fetch public issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(issue)
+}
+
+/// Adds a published issue to the archive or takes it out. Returns `false` if
+/// there is no such issue or it has not been published.
+#[tracing::instrument(name = "Set issue visibility", skip(pool))]
+pub async fn set_issue_public(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    public: bool,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let rows_affected = diesel::update(
+                    newsletter_issues
+                        .filter(newsletter_issue_id.eq(issue_id))
+                        .filter(published_at.is_not_null())
+                )
+                // Feeds use this to tell readers that the archive changed.
+                .set((is_public.eq(public), updated_at.eq(Utc::now())))
+                .execute(conn)
+                .context("Failed to update issue visibility")?;
+                // Issues published before the archive existed have no slug yet.
+                if rows_affected > 0 {
+                    assign_slug(conn, issue_id)?;
+                }
+                Ok::<_, anyhow::Error>(rows_affected)
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rendering::RenderedEmail;
+
+    use super::{add_view_in_browser_link, body_content, slugify, strip_personal_links};
+
+    #[test]
+    fn slugs_are_lowercase_words_joined_by_dashes() {
+        assert_eq!(slugify("Rust & Friends #3"), "rust-friends-3");
+        assert_eq!(slugify("  Hello, World!  "), "hello-world");
+        assert_eq!(slugify("Ünïcode only: ✨"), "n-code-only");
+        assert_eq!(slugify("✨✨"), "issue");
+        assert!(slugify(&"long title ".repeat(20)).len() <= 80);
+        assert!(!slugify(&"long title ".repeat(20)).ends_with('-'));
+    }
+
+    #[test]
+    fn the_view_in_browser_link_goes_at_the_top() {
+        let email = RenderedEmail {
+            subject: "Subject".to_string(),
+            html: "<html><body class=\"x\"><p>Hi</p></body></html>".to_string(),
+            text: "Hi".to_string(),
+        };
+        let email = add_view_in_browser_link(email, "https://example.com/issues/a?b&c");
+        assert!(email.html.starts_with(r#"<html><body class="x"><p style="text-align: center; font-size: 12px"><a href="https://example.com/issues/a?b&amp;c">View in browser</a></p><p>Hi</p>"#));
+        assert_eq!(email.text, "View in browser: https://example.com/issues/a?b&c\n\nHi");
+
+        let fragment = RenderedEmail { subject: String::new(), html: "<p>Hi</p>".to_string(), text: String::new() };
+        assert!(add_view_in_browser_link(fragment, "u").html.ends_with("View in browser</a></p><p>Hi</p>"));
+    }
+
+    #[test]
+    fn only_the_body_is_kept_and_unsubscribe_links_are_dropped() {
+        assert_eq!(body_content("<html><head><title>T</title></head><BODY><p>Hi</p></BODY></html>"), "<p>Hi</p>");
+        assert_eq!(body_content("<p>Hi</p>"), "<p>Hi</p>");
+        assert_eq!(
+            strip_personal_links(r#"<p>Hi <a href="https://example.com">there</a></p><a href="">Unsubscribe</a>"#).unwrap(),
+            r#"<p>Hi <a href="https://example.com">there</a></p>"#
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::models::{AuditEvent, AuditEventAdd};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Logout,
+    PasswordChanged,
+    SessionRevoked,
+    AllSessionsRevoked,
+    NewsletterPublished,
+    NewsletterPaused,
+    NewsletterResumed,
+    NewsletterCancelled,
+    NewsletterVisibilityChanged,
+    SuppressionAdded,
+    SuppressionRemoved,
+    ConfigChanged,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 14] = [
+        AuditAction::Login,
+        AuditAction::LoginFailed,
+        AuditAction::Logout,
+        AuditAction::PasswordChanged,
+        AuditAction::SessionRevoked,
+        AuditAction::AllSessionsRevoked,
+        AuditAction::NewsletterPublished,
+        AuditAction::NewsletterPaused,
+        AuditAction::NewsletterResumed,
+        AuditAction::NewsletterCancelled,
+        AuditAction::NewsletterVisibilityChanged,
+        AuditAction::SuppressionAdded,
+        AuditAction::SuppressionRemoved,
+        AuditAction::ConfigChanged,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::SessionRevoked => "session_revoked",
+            AuditAction::AllSessionsRevoked => "all_sessions_revoked",
+            AuditAction::NewsletterPublished => "newsletter_published",
+            AuditAction::NewsletterPaused => "newsletter_paused",
+            AuditAction::NewsletterResumed => "newsletter_resumed",
+            AuditAction::NewsletterCancelled => "newsletter_cancelled",
+            AuditAction::NewsletterVisibilityChanged => "newsletter_visibility_changed",
+            AuditAction::SuppressionAdded => "suppression_added",
+            AuditAction::SuppressionRemoved => "suppression_removed",
+            AuditAction::ConfigChanged => "config_changed",
+        }
+    }
+}
+
+impl TryFrom<&str> for AuditAction {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        AuditAction::ALL
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known audit action.", s))
+    }
+}
+
+pub struct NewAuditEvent {
+    pub user_id: Option<Uuid>,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub metadata: serde_json::Value,
+    pub ip_address: Option<String>,
+}
+
+impl NewAuditEvent {
+    pub fn new(user_id: Option<Uuid>, action: AuditAction) -> Self {
+        Self {
+            user_id,
+            action,
+            target: None,
+            metadata: serde_json::json!({}),
+            ip_address: None,
+        }
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn ip_address(mut self, ip_address: Option<String>) -> Self {
+        self.ip_address = ip_address;
+        self
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    pub username: Option<String>,
+    pub action: Option<AuditAction>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Record audit event", skip_all, fields(action = event.action.as_str()))]
+pub async fn record_audit_event(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    event: NewAuditEvent,
+) -> Result<(), anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || current_span.in_scope(|| insert_audit_event(&mut conn, event)))
+        .await
+        .context("Failed due to threadpool error")??;
+
+    Ok(())
+}
+
+/// Blocking version of `record_audit_event`, for recording an action in the
+/// same transaction as the change itself.
+pub fn insert_audit_event(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    event: NewAuditEvent,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::audit_events::dsl::*;
+
+    let row = AuditEventAdd {
+        audit_event_id: Uuid::new_v4(),
+        user_id: event.user_id,
+        action: event.action.as_str().to_string(),
+        target: event.target,
+        metadata: event.metadata,
+        ip_address: event.ip_address,
+        created_at: Utc::now(),
+    };
+
+    diesel::insert_into(audit_events)
+        .values(row)
+        .execute(conn)
+        .context("Failed to insert audit event")?;
+
+    Ok(())
+}
+
+/// Returns matching events, most recent first.
+#[tracing::instrument(name = "Get audit events", skip(pool))]
+pub async fn get_audit_events(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    filter: AuditFilter,
+    limit: Option<i64>,
+) -> Result<Vec<AuditEvent>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{audit_events, users};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let events = web::block(move || {
+        current_span.in_scope(|| {
+            let mut query = audit_events::table
+                .left_join(users::table)
+                .select((
+                    audit_events::audit_event_id,
+                    audit_events::user_id,
+                    users::username.nullable(),
+                    audit_events::action,
+                    audit_events::target,
+                    audit_events::metadata,
+                    audit_events::ip_address,
+                    audit_events::created_at,
+                ))
+                .order(audit_events::created_at.desc())
+                .into_boxed();
+
+            if let Some(uname) = filter.username {
+                query = query.filter(users::username.eq(uname));
+            }
+            if let Some(a) = filter.action {
+                query = query.filter(audit_events::action.eq(a.as_str()));
+            }
+            if let Some(from) = filter.from {
+                query = query.filter(audit_events::created_at.ge(from));
+            }
+            if let Some(to) = filter.to {
+                query = query.filter(audit_events::created_at.le(to));
+            }
+            if let Some(limit) = limit {
+                query = query.limit(limit);
+            }
+
+            query
+                .load::<AuditEvent>(&mut conn)
+                .context("Failed to fetch audit events")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(events)
+}
+
+impl AuditEvent {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.audit_event_id,
+            "user_id": self.user_id,
+            "username": self.username,
+            "action": self.action,
+            "target": self.target,
+            "metadata": self.metadata,
+            "ip_address": self.ip_address,
+            "created_at": self.created_at.to_rfc3339(),
+        })
+    }
+}
@@ -7,6 +7,7 @@ use r2d2::Pool;
 use secrecy::{ExposeSecret, Secret};
 use uuid::Uuid;
 
+use crate::configuration::PasswordHashSettings;
 use crate::models::VerificationInfo;
 
 
@@ -26,10 +27,11 @@ pub struct Credentials {
 }
 
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool, hash_settings))]
 pub async fn validate_credentials(
     credentials: Credentials,
     pool: &Pool<ConnectionManager<PgConnection>>,
+    hash_settings: &PasswordHashSettings,
 ) -> Result<uuid::Uuid, AuthError>{
     let mut user_id: Option<Uuid> = None;
     let mut expected_password_hash = Secret::new(
@@ -40,13 +42,16 @@ pub async fn validate_credentials(
     );
 
     if let Some((stored_password_hash, stored_user_id))
-        = get_stored_credentials(&credentials.username, &pool)
+        = get_stored_credentials(&credentials.username, pool)
             .await?
     {
         user_id = Some(stored_user_id);
         expected_password_hash = stored_password_hash;
     }
 
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
+
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || {
         current_span.in_scope(|| {
@@ -59,24 +64,55 @@ pub async fn validate_credentials(
     .await
     .context("Failed to spawn blocking task.")??;
 
-    user_id.ok_or_else(
+    let user_id = user_id.ok_or_else(
         || AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username."))
-    )
+    )?;
+
+    // The password has just been verified, so this is our only chance to
+    // re-hash it if the stored hash predates the current Argon2 settings.
+    // Failing to upgrade must not prevent the user from logging in.
+    match password_hash_needs_upgrade(&stored_password_hash, hash_settings) {
+        Ok(true) => {
+            if let Err(e) = change_password(user_id, password, pool, hash_settings).await {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to upgrade password hash",
+                );
+            }
+        },
+
+        Ok(false) => {},
+
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to inspect stored password hash",
+            );
+        }
+    }
+
+    Ok(user_id)
 }
 
 #[tracing::instrument(
     name = "Set new password",
-    skip(uid, password, pool)
+    skip(uid, password, pool, hash_settings)
 )]
 pub async fn change_password(
     uid: Uuid,
     password: Secret<String>,
-    pool: &Pool<ConnectionManager<PgConnection>>
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    hash_settings: &PasswordHashSettings,
 ) -> Result<(), anyhow::Error> {
+    let params = hash_settings.params()
+        .context("Invalid Argon2 parameters in configuration")?;
+
     let current_span = tracing::Span::current();
     let password_hash = web::block(move || {
         current_span.in_scope(|| {
-            compute_password_hash(password)
+            compute_password_hash(password, params)
                 .context("Failed to compute new password hash")
         })
     })
@@ -152,14 +188,41 @@ pub async fn get_stored_credentials(uname: &str, pool: &Pool<ConnectionManager<P
     Ok(Some((Secret::new(result.password), result.user_id)))
 }
 
+/// Returns `true` if the stored hash was produced with an older algorithm,
+/// an older Argon2 version or weaker cost parameters than the ones currently
+/// configured.
+pub fn password_hash_needs_upgrade(
+    stored_password_hash: &Secret<String>,
+    hash_settings: &PasswordHashSettings,
+) -> Result<bool, anyhow::Error> {
+    let stored_password_hash = PasswordHash::new(stored_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    if stored_password_hash.algorithm != Algorithm::Argon2id.ident()
+        || stored_password_hash.version != Some(Version::V0x13.into())
+    {
+        return Ok(true);
+    }
+
+    let stored_params = Params::try_from(&stored_password_hash)
+        .context("Failed to read Argon2 parameters from stored hash")?;
+    let current_params = hash_settings.params()
+        .context("Invalid Argon2 parameters in configuration")?;
+
+    Ok(stored_params.m_cost() < current_params.m_cost()
+        || stored_params.t_cost() < current_params.t_cost()
+        || stored_params.p_cost() < current_params.p_cost())
+}
+
 fn compute_password_hash(
-    password: Secret<String>
+    password: Secret<String>,
+    params: Params,
 ) -> Result<Secret<String>, anyhow::Error> {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
             Version::V0x13,
-            Params::new(15000, 2, 1, None).unwrap(),
+            params,
         )
         .hash_password(password.expose_secret().as_bytes(), &salt)?
         .to_string();
@@ -1,3 +1,4 @@
+use argon2::Params;
 use config::{Config, ConfigError};
 use secrecy::ExposeSecret;
 use secrecy::Secret;
@@ -12,9 +13,77 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub password_hash: PasswordHashSettings,
+    pub session: SessionSettings,
+    pub tracking: TrackingSettings,
+    pub postmark_webhook: PostmarkWebhookSettings,
+    /// Leave out to run without a feed poller.
+    pub feed_poller: Option<FeedPollerSettings>,
     pub redis_uri: Secret<String>
 }
 
+/// An RSS or Atom feed whose new entries are turned into issues.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FeedPollerSettings {
+    /// An `http://` or `https://` URL, or the path of a local file.
+    pub source: String,
+    pub mode: FeedPollerMode,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_seconds: u64,
+    /// Layout the issues are sent in. Without one they go out as is.
+    pub template_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedPollerMode {
+    /// Entries become drafts for an editor to review and publish.
+    Draft,
+    /// Entries are published as soon as they are found.
+    Publish,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TrackingSettings {
+    /// Allows issues to carry an open-tracking pixel. Each issue still has to
+    /// opt in.
+    pub open_tracking: bool,
+    /// Sends links in issues through the click tracker.
+    pub click_tracking: bool,
+}
+
+/// Basic auth credentials Postmark has to present when calling our webhook;
+/// they go into the webhook URL configured on the Postmark side.
+#[derive(Deserialize, Clone)]
+pub struct PostmarkWebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SessionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_seconds: f64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub absolute_timeout_seconds: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PasswordHashSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_cost: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub time_cost: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+impl PasswordHashSettings {
+    pub fn params(&self) -> Result<Params, argon2::Error> {
+        Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
     pub base_url: String,
@@ -0,0 +1,147 @@
+use std::rc::Rc;
+
+use actix_web::{dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform}, error::PayloadError, http::{header::CONTENT_TYPE, Method}, web, HttpMessage, HttpResponse};
+use actix_session::SessionExt;
+use futures_util::{future::{ready, LocalBoxFuture, Ready}, stream, FutureExt, StreamExt};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Deserialize;
+use tracing::Instrument;
+
+use crate::{session_state::TypedSession, utils::e500};
+
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+#[derive(Clone, Debug)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        Self(
+            std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                .map(char::from)
+                .take(32)
+                .collect()
+        )
+    }
+
+    fn matches(&self, candidate: &str) -> bool {
+        let expected = self.0.as_bytes();
+        let candidate = candidate.as_bytes();
+
+        // Compare in constant time so the token cannot be guessed byte by byte.
+        expected.len() == candidate.len()
+            && expected
+                .iter()
+                .zip(candidate)
+                .fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+impl std::fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Deserialize)]
+struct CsrfFormField {
+    csrf_token: Option<String>,
+}
+
+#[derive(Default)]
+pub struct CsrfMiddlewareFactory;
+
+impl<S> Transform<S, ServiceRequest> for CsrfMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let service = Rc::new(service);
+        ready(Ok(CsrfMiddleware{ service }))
+    }
+}
+
+pub struct CsrfMiddleware<S>{
+    service: Rc<S>
+}
+
+impl<S> Service<ServiceRequest> for CsrfMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static,
+{
+    type Response = S::Response;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    #[tracing::instrument(
+        "Checking CSRF token",
+        skip(self, req)
+    )]
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        let current_span = tracing::Span::current();
+        async move {
+            let session = TypedSession::from(req.get_session());
+
+            let token = match session.get_csrf_token().map_err(e500)? {
+                Some(token) => CsrfToken(token),
+                None => {
+                    let token = CsrfToken::generate();
+                    session.insert_csrf_token(token.to_string()).map_err(e500)?;
+                    token
+                }
+            };
+
+            if req.method() == Method::POST {
+                let submitted = submitted_token(&mut req).await?;
+                if !submitted.is_some_and(|t| token.matches(&t)) {
+                    tracing::warn!("Rejected a request with a missing or invalid CSRF token");
+                    return Ok(req.into_response(
+                        HttpResponse::Forbidden().body("Invalid CSRF token")
+                    ));
+                }
+            }
+
+            req.extensions_mut().insert(token);
+            service.call(req).await
+        }.instrument(current_span).boxed_local()
+    }
+}
+
+/// Looks for the token in the `X-CSRF-Token` header first, then in the
+/// `csrf_token` field of an urlencoded form. The body is put back on the
+/// request so the handler can still extract the form.
+async fn submitted_token(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    if let Some(header) = req.headers().get(CSRF_HEADER) {
+        return Ok(header.to_str().ok().map(|h| h.to_string()));
+    }
+
+    let is_form = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Ok(None);
+    }
+
+    let body = req.extract::<web::Bytes>().await?;
+    let field = serde_urlencoded::from_bytes::<CsrfFormField>(&body)
+        .ok()
+        .and_then(|f| f.csrf_token);
+
+    let body = stream::once(async move { Ok::<_, PayloadError>(body) }).boxed_local();
+    req.set_payload(Payload::from(body));
+
+    Ok(field)
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{models::DeliveryEventAdd, suppressions::{suppress, SuppressionReason}};
+
+/// Metadata key the delivery worker tags issue emails with, so that bounces
+/// and deliveries can be traced back to the issue.
+pub const ISSUE_METADATA_KEY: &str = "newsletter_issue_id";
+
+/// Bounce types after which Postmark deactivates the address; anything else,
+/// like a full mailbox, may well go through next time.
+const HARD_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+/// Status given to subscribers we must stop mailing.
+pub const SUPPRESSED_STATUS: &str = "suppressed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryEventKind {
+    Bounce,
+    SpamComplaint,
+    Delivery,
+}
+
+impl DeliveryEventKind {
+    pub const ALL: [DeliveryEventKind; 3] = [
+        DeliveryEventKind::Bounce,
+        DeliveryEventKind::SpamComplaint,
+        DeliveryEventKind::Delivery,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryEventKind::Bounce => "bounce",
+            DeliveryEventKind::SpamComplaint => "spam_complaint",
+            DeliveryEventKind::Delivery => "delivery",
+        }
+    }
+}
+
+impl TryFrom<&str> for DeliveryEventKind {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        DeliveryEventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known delivery event kind.", s))
+    }
+}
+
+/// A webhook payload as Postmark sends it. Record types we do not act on, like
+/// opens or link clicks, are accepted and ignored.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "RecordType")]
+pub enum PostmarkEvent {
+    Bounce(PostmarkBounce),
+    SpamComplaint(PostmarkBounce),
+    Delivery(PostmarkDelivery),
+    #[serde(other)]
+    Other,
+}
+
+/// Spam complaints come in the same shape as bounces.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkBounce {
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    #[serde(rename = "Type")]
+    pub bounce_type: String,
+    pub email: String,
+    pub bounced_at: DateTime<Utc>,
+    pub description: Option<String>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkDelivery {
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    pub recipient: String,
+    pub delivered_at: DateTime<Utc>,
+    pub details: Option<String>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// What a provider told us happened to an email after it left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryEvent {
+    pub kind: DeliveryEventKind,
+    pub email: String,
+    pub message_id: Option<String>,
+    pub bounce_type: Option<String>,
+    pub description: Option<String>,
+    /// Only known for emails the delivery worker sent.
+    pub newsletter_issue_id: Option<Uuid>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl DeliveryEvent {
+    /// `None` for record types we ignore.
+    pub fn from_postmark(event: PostmarkEvent) -> Option<Self> {
+        let issue_id = |metadata: &Option<HashMap<String, String>>| {
+            metadata
+                .as_ref()
+                .and_then(|m| m.get(ISSUE_METADATA_KEY))
+                .and_then(|id| Uuid::parse_str(id).ok())
+        };
+        let from_bounce = |kind, bounce: PostmarkBounce| Self {
+            kind,
+            newsletter_issue_id: issue_id(&bounce.metadata),
+            email: bounce.email,
+            message_id: bounce.message_id,
+            bounce_type: Some(bounce.bounce_type),
+            description: bounce.description,
+            occurred_at: bounce.bounced_at,
+        };
+
+        match event {
+            PostmarkEvent::Bounce(bounce) => Some(from_bounce(DeliveryEventKind::Bounce, bounce)),
+            PostmarkEvent::SpamComplaint(bounce) => Some(from_bounce(DeliveryEventKind::SpamComplaint, bounce)),
+            PostmarkEvent::Delivery(delivery) => Some(Self {
+                kind: DeliveryEventKind::Delivery,
+                newsletter_issue_id: issue_id(&delivery.metadata),
+                email: delivery.recipient,
+                message_id: delivery.message_id,
+                bounce_type: None,
+                description: delivery.details,
+                occurred_at: delivery.delivered_at,
+            }),
+            PostmarkEvent::Other => None,
+        }
+    }
+
+    /// Hard bounces and complaints mean the address must not be mailed again.
+    pub fn suppression_reason(&self) -> Option<SuppressionReason> {
+        match self.kind {
+            DeliveryEventKind::SpamComplaint => Some(SuppressionReason::SpamComplaint),
+            DeliveryEventKind::Bounce => self
+                .bounce_type
+                .as_deref()
+                .is_some_and(|t| HARD_BOUNCE_TYPES.contains(&t))
+                .then_some(SuppressionReason::HardBounce),
+            DeliveryEventKind::Delivery => None,
+        }
+    }
+}
+
+/// Stores the event against the subscriber and issue it is about, marks a
+/// bounced delivery and adds the address to the suppression list if the event
+/// calls for it.
+/// Returns `false` for an event that was already recorded.
+#[tracing::instrument(name = "Record delivery event", skip(pool))]
+pub async fn record_delivery_event(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    event: DeliveryEvent,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{delivery_events, issue_deliveries, newsletter_issues, subscriptions};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let subscriber_id = subscriptions::table
+                    .filter(subscriptions::email.eq(&event.email))
+                    .select(subscriptions::id)
+                    .first::<Uuid>(conn)
+                    .optional()
+                    .context("Failed to look up subscriber")?;
+                // Metadata comes from outside; only link issues that exist.
+                let newsletter_issue_id = match event.newsletter_issue_id {
+                    Some(issue_id) => newsletter_issues::table
+                        .filter(newsletter_issues::newsletter_issue_id.eq(issue_id))
+                        .select(newsletter_issues::newsletter_issue_id)
+                        .first::<Uuid>(conn)
+                        .optional()
+                        .context("Failed to look up issue")?,
+                    None => None,
+                };
+
+                let rows_affected = diesel::insert_into(delivery_events::table)
+                    .values(DeliveryEventAdd {
+                        delivery_event_id: Uuid::new_v4(),
+                        kind: event.kind.as_str().to_string(),
+                        email: event.email.clone(),
+                        subscriber_id,
+                        newsletter_issue_id,
+                        message_id: event.message_id.clone(),
+                        bounce_type: event.bounce_type.clone(),
+                        description: event.description.clone(),
+                        occurred_at: event.occurred_at,
+                        received_at: Utc::now(),
+                    })
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .context("Failed to insert delivery event")?;
+                if rows_affected == 0 {
+                    return Ok(false);
+                }
+
+                if let (DeliveryEventKind::Bounce, Some(issue_id)) = (event.kind, newsletter_issue_id) {
+                    diesel::update(
+                        issue_deliveries::table
+                            .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+                            .filter(issue_deliveries::subscriber_email.eq(&event.email))
+                            .filter(issue_deliveries::bounced_at.is_null())
+                    )
+                    .set(issue_deliveries::bounced_at.eq(event.occurred_at))
+                    .execute(conn)
+                    .context("Failed to mark delivery as bounced")?;
+                }
+
+                if let Some(reason) = event.suppression_reason() {
+                    if suppress(conn, &event.email, reason, "postmark")? {
+                        tracing::info!(reason = reason.as_str(), "Suppressed address");
+                    }
+                    // Subscribers who already left keep their status, so the
+                    // unsubscribe still counts in issue reports.
+                    diesel::update(
+                        subscriptions::table
+                            .filter(subscriptions::email.eq(&event.email))
+                            .filter(subscriptions::status.eq_any(["confirmed", "pending_confirmation"]))
+                    )
+                    .set(subscriptions::status.eq(SUPPRESSED_STATUS))
+                    .execute(conn)
+                    .context("Failed to suppress subscriber")?;
+                }
+
+                Ok::<_, anyhow::Error>(true)
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::suppressions::SuppressionReason;
+
+    use super::{DeliveryEvent, DeliveryEventKind, PostmarkEvent};
+
+    fn parse(payload: serde_json::Value) -> Option<DeliveryEvent> {
+        DeliveryEvent::from_postmark(serde_json::from_value::<PostmarkEvent>(payload).unwrap())
+    }
+
+    fn bounce(bounce_type: &str) -> serde_json::Value {
+        serde_json::json!({
+            "RecordType": "Bounce",
+            "ID": 4323372036854775807_i64,
+            "Type": bounce_type,
+            "TypeCode": 1,
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": "john@example.com",
+            "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+            "Description": "The server was unable to deliver your message.",
+            "Metadata": { "newsletter_issue_id": "6b9b2f3a-3c5e-4d5c-9a7e-2b1f0d3c4e5f" },
+            "Inactive": true
+        })
+    }
+
+    #[test]
+    fn hard_bounces_suppress_the_address() {
+        let event = parse(bounce("HardBounce")).unwrap();
+        assert_eq!(event.kind, DeliveryEventKind::Bounce);
+        assert_eq!(event.email, "john@example.com");
+        assert_eq!(event.newsletter_issue_id.unwrap().to_string(), "6b9b2f3a-3c5e-4d5c-9a7e-2b1f0d3c4e5f");
+        assert_eq!(event.suppression_reason(), Some(SuppressionReason::HardBounce));
+    }
+
+    #[test]
+    fn soft_bounces_do_not_suppress_the_address() {
+        assert_eq!(parse(bounce("SoftBounce")).unwrap().suppression_reason(), None);
+    }
+
+    #[test]
+    fn complaints_and_deliveries_are_parsed_and_other_records_ignored() {
+        let complaint = parse(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "MessageID": "00000000-0000-0000-0000-000000000000",
+            "Type": "SpamComplaint",
+            "Email": "john@example.com",
+            "BouncedAt": "2019-11-05T16:33:54Z",
+            "Metadata": null
+        })).unwrap();
+        assert_eq!(complaint.kind, DeliveryEventKind::SpamComplaint);
+        assert_eq!(complaint.newsletter_issue_id, None);
+        assert_eq!(complaint.suppression_reason(), Some(SuppressionReason::SpamComplaint));
+
+        let delivery = parse(serde_json::json!({
+            "RecordType": "Delivery",
+            "MessageID": "00000000-0000-0000-0000-000000000000",
+            "Recipient": "john@example.com",
+            "DeliveredAt": "2014-08-01T13:28:10.2735393-04:00",
+            "Details": "Test delivery webhook details"
+        })).unwrap();
+        assert_eq!(delivery.kind, DeliveryEventKind::Delivery);
+        assert_eq!(delivery.email, "john@example.com");
+        assert_eq!(delivery.suppression_reason(), None);
+
+        assert_eq!(parse(serde_json::json!({ "RecordType": "Open", "Recipient": "john@example.com" })), None);
+    }
+}
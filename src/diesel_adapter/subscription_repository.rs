@@ -3,6 +3,9 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::PgConnection;
 use diesel::prelude::*;
 use anyhow::Context;
+use chrono::Utc;
+use diesel::dsl::case_when;
+use diesel::sql_types::{Nullable, Timestamptz};
 
 use crate::domain::new_subscriber::NewSubscriber;
 use crate::models::SubscriptionToken;
@@ -10,6 +13,8 @@ use crate::routes::subscribe::{insert_subscriber, InsertSubscriberError};
 use crate::traits::SubscriptionRepository;
 use crate::schema::subscriptions;
 use crate::schema::subscription_tokens;
+use crate::suppressions::{get_suppression, suppress, SuppressionReason};
+use crate::welcome_sequence::enroll_in_welcome_sequence;
 
 #[derive(Clone)]
 pub struct DieselSubscriptionRepository{
@@ -42,10 +47,13 @@ impl SubscriptionRepository for DieselSubscriptionRepository {
 
         let mut conn = self.pool.get().unwrap();
         web::block(move || {
-            diesel::update(subscriptions::dsl::subscriptions)
-                .filter(subscriptions::dsl::id.eq(result.subscriber_id))
-                .set(subscriptions::dsl::status.eq("confirmed"))
-                .execute(&mut conn)
+            conn.transaction(|conn| {
+                diesel::update(subscriptions::dsl::subscriptions)
+                    .filter(subscriptions::dsl::id.eq(result.subscriber_id))
+                    .set(subscriptions::dsl::status.eq("confirmed"))
+                    .execute(conn)?;
+                enroll_in_welcome_sequence(conn, result.subscriber_id, Utc::now())
+            })
         })
         .await
         .unwrap()
@@ -53,4 +61,45 @@ impl SubscriptionRepository for DieselSubscriptionRepository {
 
         Ok(())
     }
+
+    async fn unsubscribe_subscriber(&self, subscription_token: &str) -> Result<bool, anyhow::Error> {
+        let mut conn = self.pool.get().context("Failed to get DB connection from pool")?;
+        let subscription_token = subscription_token.to_string();
+        let now = Utc::now();
+
+        let unsubscribed = web::block(move || {
+            conn.transaction(|conn| {
+                let emails = diesel::update(subscriptions::table)
+                    .filter(subscriptions::id.eq_any(
+                        subscription_tokens::table
+                            .filter(subscription_tokens::subscription_token.eq(subscription_token))
+                            .select(subscription_tokens::subscriber_id)
+                    ))
+                    .set((
+                        subscriptions::status.eq("unsubscribed"),
+                        // Following the link again keeps the original date, which
+                        // the issue reports attribute the unsubscribe by.
+                        subscriptions::unsubscribed_at.eq(
+                            case_when(subscriptions::status.eq("unsubscribed"), subscriptions::unsubscribed_at)
+                                .otherwise(Some(now).into_sql::<Nullable<Timestamptz>>())
+                        ),
+                    ))
+                    .returning(subscriptions::email)
+                    .get_results::<String>(conn)
+                    .context("Failed to update subscription status")?;
+                for email in &emails {
+                    suppress(conn, email, SuppressionReason::Unsubscribed, "unsubscribe_link")?;
+                }
+                Ok::<_, anyhow::Error>(!emails.is_empty())
+            })
+        })
+        .await
+        .context("Failed due to threadpool error")??;
+
+        Ok(unsubscribed)
+    }
+
+    async fn is_suppressed(&self, email: &str) -> Result<bool, anyhow::Error> {
+        Ok(get_suppression(&self.pool, email.to_string()).await?.is_some())
+    }
 }
@@ -54,8 +54,8 @@ mod tests {
     impl quickcheck::Arbitrary for ValidateEmailFixture {
         fn arbitrary(g: &mut quickcheck::Gen) -> Self {
             let mut rand_slice: [u8; 32] = [0; 32];
-            for i in 0..32 {
-                rand_slice[i] = u8::arbitrary(g);
+            for byte in rand_slice.iter_mut() {
+                *byte = u8::arbitrary(g);
             }
             let mut seed = StdRng::from_seed(rand_slice);
             let email = SafeEmail().fake_with_rng(&mut seed);
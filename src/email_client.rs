@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
@@ -25,6 +28,23 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send_email_with_metadata(recipient, subject, html_content, text_content, &[]).await
+    }
+
+    /// Postmark hands `metadata` back in the webhooks it sends about the
+    /// email, see `routes::webhooks`.
+    #[tracing::instrument(
+        "Sending email with metadata to subscriber",
+        skip(self, subject, html_content, text_content)
+    )]
+    pub async fn send_email_with_metadata(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        metadata: &[(&str, String)],
     ) -> Result<(), reqwest::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
@@ -33,6 +53,7 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            metadata: metadata.iter().map(|(key, value)| (*key, value.as_str())).collect(),
         };
         self.http_client
             .post(url)
@@ -75,30 +96,36 @@ pub struct SendEmailRequest<'a> {
     pub subject: &'a str,
     pub html_body: &'a str,
     pub text_body: &'a str,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<&'a str, &'a str>,
 }
 
 #[derive(Clone)]
 pub struct SubscriberConfirmationEmailer {
     application_base_url: actix_web::web::Data<ApplicationBaseUrl>,
     email_client: actix_web::web::Data<EmailClient>,
+    pool: actix_web::web::Data<Pool<ConnectionManager<PgConnection>>>,
 }
 
 impl SubscriberConfirmationEmailer {
     pub fn new(
         application_base_url: actix_web::web::Data<ApplicationBaseUrl>,
         email_client: actix_web::web::Data<EmailClient>,
+        pool: actix_web::web::Data<Pool<ConnectionManager<PgConnection>>>,
     ) -> Self {
         Self {
             application_base_url,
             email_client,
+            pool,
         }
     }
 }
 
 impl EmailSender for SubscriberConfirmationEmailer {
-    async fn send_confirmation(&self, subscriber: &NewSubscriber, confirmation_token: &String) -> Result<(), reqwest::Error> {
+    async fn send_confirmation(&self, subscriber: &NewSubscriber, confirmation_token: &str) -> Result<(), anyhow::Error> {
         send_confirmation_mail(
             &self.email_client,
+            &self.pool,
             subscriber,
             &self.application_base_url.0,
             confirmation_token,
@@ -120,7 +147,7 @@ mod tests {
     };
     use secrecy::Secret;
     use wiremock::{
-        matchers::{any, header, header_exists, method, path},
+        matchers::{any, body_partial_json, header, header_exists, method, path},
         Mock, MockServer, ResponseTemplate,
     };
 
@@ -201,6 +228,26 @@ mod tests {
         assert_ok!(outcome)
     }
 
+    #[actix_web::test]
+    async fn send_email_with_metadata_passes_it_on() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .and(body_partial_json(serde_json::json!({ "Metadata": { "newsletter_issue_id": "42" } })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_metadata(&email(), &subject(), &content(), &content(), &[("newsletter_issue_id", "42".to_string())])
+            .await;
+        assert_ok!(outcome)
+    }
+
     #[actix_web::test]
     async fn send_email_fails_if_the_server_returns_500() {
         let mock_server = MockServer::start().await;
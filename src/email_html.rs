@@ -0,0 +1,433 @@
+use std::{borrow::Cow, cell::RefCell, collections::{BTreeSet, HashSet}};
+
+use lol_html::{doc_text, element, errors::{AttributeNameError, RewritingError}, html_content::Element, rewrite_str, text, ElementContentHandlers, HandlerResult, RewriteStrSettings, Selector};
+use reqwest::Url;
+
+use crate::utils::html_escape;
+
+/// Gmail truncates messages above this size behind a "View entire message" link.
+const GMAIL_CLIP_BYTES: usize = 102 * 1024;
+
+/// Removed together with everything inside them.
+const UNSAFE_ELEMENTS: [&str; 18] = [
+    "script", "noscript", "iframe", "frame", "frameset", "object", "embed", "applet", "form",
+    "input", "button", "select", "textarea", "base", "svg", "math", "template", "title",
+];
+
+/// Dropped without a word: email clients supply their own document.
+const DOCUMENT_ELEMENTS: [&str; 4] = ["html", "head", "body", "style"];
+
+const URL_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// Holds the element's own `style` while rules from `<style>` blocks are
+/// inlined, so that it can be put back last and keep precedence.
+const ORIGINAL_STYLE: &str = "data-original-style";
+
+#[derive(Debug, Default)]
+pub struct PreparedHtml {
+    /// The HTML to send: unsafe markup removed and CSS inlined.
+    pub html: String,
+    /// Problems that stop the issue from being published.
+    pub errors: Vec<String>,
+    /// Things the editor should know about; they do not block publishing.
+    pub warnings: Vec<String>,
+}
+
+/// Gets the HTML body of an issue ready for email clients. Messages are
+/// HTML-escaped, ready to be shown as flash messages.
+pub fn prepare_html(html: &str) -> PreparedHtml {
+    let mut prepared = PreparedHtml::default();
+    let policy = html_policy();
+
+    let css = match inspect(html, &policy, &mut prepared.warnings) {
+        Ok(css) => css,
+        Err(e) => {
+            prepared.html = html.to_string();
+            prepared.errors.push(html_escape(&format!("The HTML could not be processed: {}", e)));
+            return prepared;
+        }
+    };
+    let sanitized = policy.clean(html).to_string();
+    if !html.trim().is_empty() && !has_content(&sanitized) {
+        prepared.errors.push("The HTML has no content left once unsafe markup is removed.".to_string());
+    }
+    if let Err(e) = check_images_and_links(&sanitized, &mut prepared.warnings) {
+        prepared.errors.push(html_escape(&format!("The HTML could not be processed: {}", e)));
+    }
+
+    prepared.html = match inline_css(&sanitized, &css, &mut prepared.warnings) {
+        Ok(inlined) => inlined,
+        Err(e) => {
+            prepared.errors.push(html_escape(&format!("The CSS could not be inlined: {}", e)));
+            sanitized
+        }
+    };
+
+    if prepared.html.len() > GMAIL_CLIP_BYTES {
+        prepared.warnings.push(format!(
+            "The HTML is {} KB. Gmail clips messages larger than {} KB.",
+            prepared.html.len().div_ceil(1024),
+            GMAIL_CLIP_BYTES / 1024,
+        ));
+    }
+
+    prepared
+}
+
+/// What survives sanitizing: ammonia's defaults plus the tables and
+/// presentational attributes email layouts are built from. Anything else is
+/// dropped, including `<style>` blocks, whose rules are inlined afterwards.
+fn html_policy() -> ammonia::Builder<'static> {
+    let mut policy = ammonia::Builder::default();
+    policy
+        .add_tags(["font"])
+        .add_generic_attributes(["align", "bgcolor", "class", "dir", "height", "id", "style", "valign", "width"])
+        .add_tag_attributes("table", ["border", "cellpadding", "cellspacing"])
+        .add_tag_attributes("font", ["color", "face", "size"])
+        .add_clean_content_tags(UNSAFE_ELEMENTS)
+        .link_rel(None);
+    policy
+}
+
+/// Collects the contents of `<style>` blocks and tells the editor what
+/// `policy` is going to remove.
+fn inspect(html: &str, policy: &ammonia::Builder, warnings: &mut Vec<String>) -> Result<String, RewritingError> {
+    let tags = policy.clone_tags();
+    let generic_attributes = policy.clone_generic_attributes();
+    let tag_attributes = policy.clone_tag_attributes();
+    let url_schemes = policy.clone_url_schemes();
+    let removed = RefCell::new(BTreeSet::new());
+    let css = RefCell::new(String::new());
+
+    rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: vec![
+            element!("*", |el| {
+                let tag = el.tag_name();
+                if DOCUMENT_ELEMENTS.contains(&tag.as_str()) {
+                    return Ok(());
+                }
+                if !tags.contains(tag.as_str()) {
+                    removed.borrow_mut().insert(format!("<{}>", tag));
+                    return Ok(());
+                }
+                for attribute in el.attributes() {
+                    let name = attribute.name();
+                    let allowed = generic_attributes.contains(name.as_str())
+                        || tag_attributes.get(tag.as_str()).is_some_and(|names| names.contains(name.as_str()));
+                    if !allowed {
+                        removed.borrow_mut().insert(format!("{} attributes", name));
+                    } else if URL_ATTRIBUTES.contains(&name.as_str()) && has_disallowed_scheme(&attribute.value(), &url_schemes) {
+                        removed.borrow_mut().insert("unsafe links".to_string());
+                    }
+                }
+                Ok(())
+            }),
+            text!("style", |t| {
+                css.borrow_mut().push_str(t.as_str());
+                Ok(())
+            }),
+        ],
+        ..RewriteStrSettings::new()
+    })?;
+
+    let removed = removed.into_inner();
+    if !removed.is_empty() {
+        let removed: Vec<String> = removed.into_iter().collect();
+        warnings.push(html_escape(&format!("Removed unsafe markup: {}.", removed.join(", "))));
+    }
+
+    Ok(css.into_inner())
+}
+
+fn check_images_and_links(html: &str, warnings: &mut Vec<String>) -> Result<(), RewritingError> {
+    let problems = RefCell::new(Vec::new());
+
+    rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: vec![
+            element!("img", |el| {
+                let src = el.get_attribute("src").unwrap_or_default();
+                if !el.has_attribute("alt") {
+                    problems.borrow_mut().push(format!("An image has no alt text: {}.", html_escape(&src)));
+                }
+                if is_relative(&src) {
+                    problems.borrow_mut().push(format!(
+                        "The image {} has a relative address and will not load in an email.",
+                        html_escape(&src)
+                    ));
+                }
+                Ok(())
+            }),
+            element!("a[href]", |el| {
+                let href = el.get_attribute("href").unwrap_or_default();
+                if is_relative(&href) {
+                    problems.borrow_mut().push(format!(
+                        "The link {} is relative and will not work in an email.",
+                        html_escape(&href)
+                    ));
+                }
+                Ok(())
+            }),
+        ],
+        ..RewriteStrSettings::new()
+    })?;
+
+    warnings.extend(problems.into_inner());
+    Ok(())
+}
+
+/// Copies the rules of `<style>` blocks into the `style` attribute of the
+/// elements they match, since many email clients ignore stylesheets. Rules are
+/// applied in source order without regard to specificity, and an element's
+/// own `style` still wins. Rules that cannot be inlined, like `@media` queries
+/// or `:hover`, are kept in a `<style>` block at the top.
+fn inline_css(html: &str, css: &str, warnings: &mut Vec<String>) -> Result<String, RewritingError> {
+    if css.trim().is_empty() {
+        return Ok(html.to_string());
+    }
+
+    let (rules, leftover) = parse_css(css);
+
+    let mut handlers = vec![element!("*[style]", |el| {
+        let style = el.get_attribute("style").unwrap_or_default();
+        el.remove_attribute("style");
+        el.set_attribute(ORIGINAL_STYLE, &style)?;
+        Ok(())
+    })];
+    for (selector, declarations) in rules {
+        handlers.push((
+            Cow::Owned(selector),
+            ElementContentHandlers::default().element(move |el: &mut Element| -> HandlerResult {
+                append_style(el, &declarations)?;
+                Ok(())
+            }),
+        ));
+    }
+    // Selectors match the element as it was parsed, hence `[style]` here too.
+    handlers.push(element!("*[style]", |el| {
+        let style = el.get_attribute(ORIGINAL_STYLE).unwrap_or_default();
+        el.remove_attribute(ORIGINAL_STYLE);
+        append_style(el, &style)?;
+        Ok(())
+    }));
+
+    let mut inlined = rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: handlers,
+        ..RewriteStrSettings::new()
+    })?;
+
+    if !leftover.is_empty() {
+        warnings.push(html_escape(&format!(
+            "Some CSS could not be inlined and was kept in a <style> block: {}",
+            leftover.join(" ")
+        )));
+        inlined.insert_str(0, &format!("<style>{}</style>", leftover.join("\n")));
+    }
+
+    Ok(inlined)
+}
+
+fn append_style(el: &mut Element, declarations: &str) -> Result<(), AttributeNameError> {
+    let declarations = declarations.trim().trim_end_matches(';').trim();
+    if declarations.is_empty() {
+        return Ok(());
+    }
+    let style = match el.get_attribute("style") {
+        Some(existing) if !existing.trim().is_empty() => {
+            format!("{}; {}", existing.trim().trim_end_matches(';'), declarations)
+        }
+        _ => declarations.to_string(),
+    };
+    el.set_attribute("style", &style)
+}
+
+/// Splits a stylesheet into rules that can be inlined, one per selector, and
+/// the rules that have to stay in a stylesheet.
+fn parse_css(css: &str) -> (Vec<(Selector, String)>, Vec<String>) {
+    let css = strip_comments(css);
+    let mut rules = Vec::new();
+    let mut leftover = Vec::new();
+    let mut rest = css.trim();
+
+    while !rest.is_empty() {
+        if rest.starts_with('@') {
+            // At-rules either end at the first `;` or span a nested block.
+            let end = match (rest.find(';'), rest.find('{')) {
+                (Some(semicolon), Some(brace)) if semicolon < brace => semicolon + 1,
+                (Some(semicolon), None) => semicolon + 1,
+                (_, Some(brace)) => matching_brace(rest, brace).map_or(rest.len(), |i| i + 1),
+                (None, None) => rest.len(),
+            };
+            leftover.push(rest[..end].trim().to_string());
+            rest = rest[end..].trim_start();
+            continue;
+        }
+
+        let Some(open) = rest.find('{') else { break };
+        let close = rest[open..].find('}').map_or(rest.len(), |i| open + i);
+        let selectors = &rest[..open];
+        let declarations = rest[open + 1..close].trim();
+        for selector in selectors.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match selector.parse::<Selector>() {
+                Ok(parsed) if !selector.contains(':') => rules.push((parsed, declarations.to_string())),
+                _ => leftover.push(format!("{} {{ {} }}", selector, declarations)),
+            }
+        }
+        rest = rest.get(close + 1..).unwrap_or_default().trim_start();
+    }
+
+    (rules, leftover)
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = rest[start + 2..].find("*/").map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    out.push_str(rest);
+    out
+}
+
+fn matching_brace(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn has_content(html: &str) -> bool {
+    let found = RefCell::new(false);
+    let _ = rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: vec![element!("img", |_| {
+            *found.borrow_mut() = true;
+            Ok(())
+        })],
+        document_content_handlers: vec![doc_text!(|t| {
+            if !t.as_str().trim().is_empty() {
+                *found.borrow_mut() = true;
+            }
+            Ok(())
+        })],
+        ..RewriteStrSettings::new()
+    });
+    found.into_inner()
+}
+
+fn has_disallowed_scheme(url: &str, url_schemes: &HashSet<&str>) -> bool {
+    Url::parse(url.trim()).is_ok_and(|url| !url_schemes.contains(url.scheme()))
+}
+
+/// Anything but an absolute URL, a fragment or a merge tag like
+/// `{{unsubscribe_url}}` has nothing to resolve against in an inbox.
+fn is_relative(url: &str) -> bool {
+    let url = url.trim();
+    !(url.is_empty() || url.starts_with('#') || url.starts_with("{{") || Url::parse(url).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_html::prepare_html;
+
+    #[test]
+    fn unsafe_markup_is_removed() {
+        let prepared = prepare_html(
+            r#"<p onclick="steal()">Hi</p><script>alert(1)</script><form><input name="q"></form><a href="javascript:alert(1)">x</a>"#,
+        );
+        assert_eq!(prepared.html, "<p>Hi</p><a>x</a>");
+        assert_eq!(
+            prepared.warnings,
+            vec!["Removed unsafe markup: &lt;form&gt;, &lt;input&gt;, &lt;script&gt;, onclick attributes, unsafe links."]
+        );
+        assert!(prepared.errors.is_empty());
+    }
+
+    #[test]
+    fn script_hidden_in_other_markup_is_removed() {
+        let cases = [
+            r#"<p>Hi</p><svg><a xlink:href="javascript:alert(1)"><text>x</text></a></svg>"#,
+            r#"<p>Hi</p><svg><animate attributeName="href" values="javascript:alert(1)"></animate></svg>"#,
+            r#"<meta http-equiv="refresh" content="0;url=javascript:alert(1)"><p>Hi</p>"#,
+            r#"<p>Hi</p><img srcset="javascript:alert(1)" alt="">"#,
+            r#"<p>Hi</p><a href=" JaVaScRiPt:alert(1)">x</a><img src="data:text/html,<script>alert(1)</script>" alt="">"#,
+        ];
+        for html in cases {
+            let prepared = prepare_html(html);
+            assert!(!prepared.html.contains("javascript"), "{} became {}", html, prepared.html);
+            assert!(!prepared.html.contains("data:"), "{} became {}", html, prepared.html);
+            assert!(prepared.html.starts_with("<p>Hi</p>"), "{} became {}", html, prepared.html);
+            assert!(prepared.warnings[0].starts_with("Removed unsafe markup"), "{} was not reported", html);
+        }
+    }
+
+    #[test]
+    fn email_layouts_survive() {
+        let html = r##"<table width="600" cellpadding="0" align="center"><tbody><tr><td bgcolor="#ffffff" valign="top"><a href="https://example.com" style="color: red">Hi</a></td></tr></tbody></table>"##;
+        let prepared = prepare_html(html);
+        assert_eq!(prepared.html, html);
+        assert!(prepared.warnings.is_empty());
+    }
+
+    #[test]
+    fn styles_are_inlined_and_inline_styles_win() {
+        let prepared = prepare_html(
+            "<style>p { color: red; } .note { font-weight: bold }</style>\
+             <p class=\"note\" style=\"color: blue\">Hi</p>",
+        );
+        assert_eq!(
+            prepared.html,
+            r#"<p class="note" style="color: red; font-weight: bold; color: blue">Hi</p>"#
+        );
+        assert!(prepared.warnings.is_empty());
+    }
+
+    #[test]
+    fn rules_that_cannot_be_inlined_are_kept() {
+        let prepared = prepare_html(
+            "<html><head><style>a:hover { color: red } @media (max-width: 600px) { p { margin: 0 } }</style></head>\
+             <body><p>Hi</p></body></html>",
+        );
+        assert_eq!(
+            prepared.html,
+            "<style>a:hover { color: red }\n@media (max-width: 600px) { p { margin: 0 } }</style><p>Hi</p>"
+        );
+        assert_eq!(prepared.warnings.len(), 1);
+    }
+
+    #[test]
+    fn missing_alt_text_and_relative_urls_are_reported() {
+        let prepared = prepare_html(
+            r#"<img src="/logo.png"><a href="https://example.com">ok</a><a href="/archive">x</a><a href="{{unsubscribe_url}}">y</a>"#,
+        );
+        assert_eq!(
+            prepared.warnings,
+            vec![
+                "An image has no alt text: /logo.png.",
+                "The image /logo.png has a relative address and will not load in an email.",
+                "The link /archive is relative and will not work in an email.",
+            ]
+        );
+    }
+
+    #[test]
+    fn oversized_bodies_are_reported() {
+        let prepared = prepare_html(&format!("<p>{}</p>", "a".repeat(110 * 1024)));
+        assert_eq!(prepared.warnings, vec!["The HTML is 111 KB. Gmail clips messages larger than 102 KB."]);
+    }
+
+    #[test]
+    fn bodies_with_nothing_but_unsafe_markup_are_rejected() {
+        let prepared = prepare_html("<script>alert(1)</script>");
+        assert_eq!(prepared.errors.len(), 1);
+    }
+}
@@ -0,0 +1,473 @@
+use std::{collections::HashSet, time::Duration};
+
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use feed_rs::model::{Entry, Text};
+use lol_html::{element, html_content::ContentType, rewrite_str, RewriteStrSettings};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{FeedPollerMode, FeedPollerSettings, Settings},
+    email_html::prepare_html,
+    issues::{create_draft, IssueContent},
+    models::ProcessedFeedEntry,
+    rendering::escape_merge_tags,
+    routes::admin::delivery::post::insert_issue_and_enqueue_tasks,
+    startup::get_connection_pool,
+    utils::{html_escape, html_unescape},
+};
+
+/// Stands in for paragraph and line breaks while the whitespace of an
+/// entry's HTML is collapsed.
+const PARAGRAPH_BREAK: char = '\u{1e}';
+const LINE_BREAK: char = '\u{1f}';
+
+pub async fn run_feed_poller_until_stopped(
+    configuration: Settings
+) -> Result<(), anyhow::Error> {
+    let Some(settings) = configuration.feed_poller else {
+        tracing::info!("No feed to poll");
+        return std::future::pending().await;
+    };
+    let connection_pool = get_connection_pool(&configuration.database);
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    loop {
+        if let Err(e) = poll_feed(&connection_pool, &http_client, &settings).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to poll feed",
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(settings.interval_seconds)).await;
+    }
+}
+
+/// An entry of a polled feed, ready to become an issue.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FeedItem {
+    pub guid: String,
+    pub title: String,
+    pub link: Option<String>,
+    /// The entry's content, or its summary when there is no content.
+    pub html: String,
+    pub published: Option<DateTime<Utc>>,
+}
+
+impl FeedItem {
+    fn from_entry(entry: Entry) -> Self {
+        let link = entry
+            .links
+            .iter()
+            .find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+            .map(|link| link.href.clone());
+        let html = match (entry.content.and_then(|content| content.body), entry.summary) {
+            (Some(body), _) => body,
+            (None, Some(summary)) => text_to_html(&summary),
+            (None, None) => String::new(),
+        };
+        Self {
+            guid: entry.id,
+            title: entry.title.map(|title| title.content.trim().to_string()).unwrap_or_default(),
+            link,
+            html,
+            published: entry.published.or(entry.updated),
+        }
+    }
+
+    /// The issue the entry turns into: its content followed by a link to the
+    /// original. Nothing in the feed is taken for a merge tag; the HTML is
+    /// escaped once prepared, so that the braces of its CSS are inlined first.
+    pub fn issue_content(&self, template_id: &str) -> Result<IssueContent, String> {
+        let title = if self.title.is_empty() { "New post" } else { &self.title };
+        let mut html = self.html.clone();
+        let mut text = html_to_text(&self.html);
+        if let Some(link) = &self.link {
+            html.push_str(&format!(r#"<p><a href="{}">Read it on the web</a></p>"#, html_escape(link)));
+            text.push_str(&format!("\n\nRead it on the web: {}", link));
+        }
+
+        let prepared = prepare_html(&html);
+        if let Some(error) = prepared.errors.first() {
+            return Err(html_unescape(error));
+        }
+        IssueContent::parse(
+            escape_merge_tags(title),
+            escape_merge_tags(text.trim()),
+            escape_merge_tags(&prepared.html),
+            String::new(),
+            template_id,
+            false,
+        )
+    }
+}
+
+fn text_to_html(text: &Text) -> String {
+    if text.content_type.to_string().starts_with("text/html") {
+        text.content.clone()
+    } else {
+        format!("<p>{}</p>", html_escape(text.content.trim()))
+    }
+}
+
+/// Parses an RSS or Atom document. Entries are returned oldest first, the
+/// order they should go out in.
+pub fn parse_feed(body: &[u8]) -> Result<Vec<FeedItem>, feed_rs::parser::ParseFeedError> {
+    let feed = feed_rs::parser::parse(body)?;
+    let mut items: Vec<FeedItem> = feed.entries.into_iter().map(FeedItem::from_entry).collect();
+    // Feeds list their newest entries first; entries without a date keep
+    // their place relative to each other.
+    items.reverse();
+    items.sort_by_key(|item| item.published);
+    Ok(items)
+}
+
+/// A plain-text rendition of an entry's HTML: one paragraph per block
+/// element, with scripts, styles and markup dropped.
+pub fn html_to_text(html: &str) -> String {
+    let stripped = rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: vec![
+            element!("script, style, head", |el| {
+                el.remove();
+                Ok(())
+            }),
+            element!("br", |el| {
+                el.after(&LINE_BREAK.to_string(), ContentType::Text);
+                Ok(())
+            }),
+            element!("p, div, h1, h2, h3, h4, h5, h6, li, blockquote, pre, tr, table, ul, ol", |el| {
+                el.before(&PARAGRAPH_BREAK.to_string(), ContentType::Text);
+                el.after(&PARAGRAPH_BREAK.to_string(), ContentType::Text);
+                Ok(())
+            }),
+            element!("*", |el| {
+                el.remove_and_keep_content();
+                Ok(())
+            }),
+        ],
+        ..RewriteStrSettings::new()
+    })
+    .unwrap_or_else(|_| html.to_string());
+
+    let collapsed = html_unescape(&stripped)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    collapsed
+        .split(PARAGRAPH_BREAK)
+        .map(|paragraph| {
+            paragraph
+                .split(LINE_BREAK)
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .map(|paragraph| paragraph.trim().to_string())
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+async fn fetch_feed(http_client: &reqwest::Client, source: &str) -> Result<Vec<u8>, anyhow::Error> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let body = http_client
+            .get(source)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to fetch feed")?
+            .bytes()
+            .await
+            .context("Failed to read feed")?;
+        Ok(body.to_vec())
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source).to_string();
+        web::block(move || std::fs::read(path))
+            .await
+            .context("Failed due to threadpool error")?
+            .context("Failed to read feed file")
+    }
+}
+
+/// Reads the feed and turns every entry that has not been seen before into
+/// a draft or a published issue. Returns the ids of the new issues.
+///
+/// The first time a feed is polled its entries are only remembered, so that
+/// enabling the poller does not send out the feed's whole history.
+#[tracing::instrument(skip(pool, http_client, settings), fields(source = %settings.source))]
+pub async fn poll_feed(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    http_client: &reqwest::Client,
+    settings: &FeedPollerSettings,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let body = fetch_feed(http_client, &settings.source).await?;
+    let items = parse_feed(&body).context("Failed to parse feed")?;
+    if start_following(pool, settings.source.clone()).await? {
+        for item in &items {
+            claim_entry(pool, settings.source.clone(), item.guid.clone()).await?;
+        }
+        tracing::info!(entries = items.len(), "Started following feed");
+        return Ok(Vec::new());
+    }
+
+    let seen = get_processed_guids(pool, settings.source.clone()).await?;
+    let template_id = settings.template_id.clone().unwrap_or_default();
+    let mut issue_ids = Vec::new();
+    for item in items.into_iter().filter(|item| !seen.contains(&item.guid)) {
+        // Left unclaimed, so that the next poll tries again.
+        let content = match item.issue_content(&template_id) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!(guid = %item.guid, error.message = %e, "Skipping feed entry for now");
+                continue;
+            }
+        };
+        // Another poller running against the same feed may have got here first.
+        if !claim_entry(pool, settings.source.clone(), item.guid.clone()).await? {
+            continue;
+        }
+        let created = match settings.mode {
+            FeedPollerMode::Draft => create_draft(pool, None, content).await,
+            FeedPollerMode::Publish => insert_issue_and_enqueue_tasks(pool, None, content, None).await,
+        };
+        let issue_id = match created {
+            Ok(issue_id) => issue_id,
+            Err(e) => {
+                // Let the next poll try again.
+                release_entry(pool, settings.source.clone(), item.guid).await?;
+                return Err(e);
+            }
+        };
+        link_entry(pool, settings.source.clone(), item.guid, issue_id).await?;
+        issue_ids.push(issue_id);
+    }
+
+    Ok(issue_ids)
+}
+
+/// Records that the feed is being followed. Returns `false` if it already
+/// was, even if it has had no entries so far.
+async fn start_following(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    feed_source: String,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::followed_feeds::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::insert_into(followed_feeds)
+                .values((source.eq(feed_source), started_at.eq(Utc::now())))
+                .on_conflict_do_nothing()
+                .execute(&mut conn)
+                .context("Failed to record followed feed")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+async fn get_processed_guids(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    feed_source: String,
+) -> Result<HashSet<String>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::processed_feed_entries::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let guids = web::block(move || {
+        current_span.in_scope(|| {
+            processed_feed_entries
+                .filter(source.eq(feed_source))
+                .select(guid)
+                .load::<String>(&mut conn)
+                .context("Failed to fetch processed feed entries")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(guids.into_iter().collect())
+}
+
+/// Records an entry as processed. Returns `false` if it already was.
+async fn claim_entry(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    feed_source: String,
+    entry_guid: String,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::processed_feed_entries::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::insert_into(processed_feed_entries)
+                .values(ProcessedFeedEntry {
+                    source: feed_source,
+                    guid: entry_guid,
+                    newsletter_issue_id: None,
+                    processed_at: Utc::now(),
+                })
+                .on_conflict_do_nothing()
+                .execute(&mut conn)
+                .context("Failed to record feed entry")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+async fn release_entry(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    feed_source: String,
+    entry_guid: String,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::processed_feed_entries::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            diesel::delete(processed_feed_entries.find((feed_source, entry_guid)))
+                .execute(&mut conn)
+                .context("Failed to forget feed entry")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(())
+}
+
+async fn link_entry(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    feed_source: String,
+    entry_guid: String,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::processed_feed_entries::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(processed_feed_entries.find((feed_source, entry_guid)))
+                .set(newsletter_issue_id.eq(issue_id))
+                .execute(&mut conn)
+                .context("Failed to link feed entry to its issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{html_to_text, parse_feed, FeedItem};
+    use crate::rendering::{render_issue, Recipient};
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0">
+  <channel>
+    <title>Blog</title>
+    <link>https://blog.example.com</link>
+    <description>Posts</description>
+    <item>
+      <title>Second {post}</title>
+      <link>https://blog.example.com/second</link>
+      <guid>second</guid>
+      <pubDate>Tue, 22 Oct 2024 09:00:00 GMT</pubDate>
+      <description>&lt;p&gt;Newer &amp;amp; better&lt;/p&gt;</description>
+    </item>
+    <item>
+      <title>First post</title>
+      <link>https://blog.example.com/first</link>
+      <guid>first</guid>
+      <pubDate>Mon, 21 Oct 2024 09:00:00 GMT</pubDate>
+      <description>&lt;p&gt;Hello&lt;/p&gt;</description>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    fn entries_come_out_oldest_first() {
+        let items = parse_feed(RSS.as_bytes()).unwrap();
+        let guids: Vec<_> = items.iter().map(|item| item.guid.as_str()).collect();
+        assert_eq!(guids, ["first", "second"]);
+        assert_eq!(items[1].title, "Second {post}");
+        assert_eq!(items[1].link.as_deref(), Some("https://blog.example.com/second"));
+        assert_eq!(items[1].html, "<p>Newer &amp; better</p>");
+    }
+
+    #[test]
+    fn braces_in_entries_are_kept() {
+        let items = parse_feed(RSS.as_bytes()).unwrap();
+        let content = items[1].issue_content("").unwrap();
+        assert_eq!(content.title, "Second {post}");
+        assert!(content.html.contains(r#"<p><a href="https://blog.example.com/second">Read it on the web</a></p>"#));
+        assert_eq!(content.text, "Newer & better\n\nRead it on the web: https://blog.example.com/second");
+
+        let styled = FeedItem {
+            guid: "styled".to_string(),
+            title: "Styled".to_string(),
+            link: None,
+            html: "<style>p{color:red}</style><p>Hi</p>".to_string(),
+            published: None,
+        };
+        assert_eq!(styled.issue_content("").unwrap().html, r#"<p style="color:red">Hi</p>"#);
+    }
+
+    #[test]
+    fn merge_tags_in_entries_are_sent_as_written() {
+        let item = FeedItem {
+            guid: "templating".to_string(),
+            title: "Greeting {{ name }}".to_string(),
+            link: None,
+            html: "<p>Write {{ name }} or {{ user }} {% if x %}</p>".to_string(),
+            published: None,
+        };
+        let issue = item.issue_content("").unwrap().to_unsaved_issue();
+        let recipient = Recipient {
+            subscriber_id: None,
+            email: "ursula@example.com".to_string(),
+            name: "Ursula".to_string(),
+            unsubscribe_url: String::new(),
+        };
+        let rendered = render_issue(&issue, None, &recipient).unwrap();
+        assert_eq!(rendered.subject, "Greeting {{ name }}");
+        assert_eq!(rendered.html, "<p>Write {{ name }} or {{ user }} {% if x %}</p>");
+        assert_eq!(rendered.text, "Write {{ name }} or {{ user }} {% if x %}");
+    }
+
+    #[test]
+    fn html_is_turned_into_paragraphs_of_text() {
+        assert_eq!(
+            html_to_text("<h1>Title</h1>\n<p>Some\n  <b>bold</b> text<br>and a break</p><script>x()</script><ul><li>One</li><li>Two &lt;3</li></ul>"),
+            "Title\n\nSome bold text\nand a break\n\nOne\n\nTwo <3"
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{archive::render_public_issue, models::NewsletterIssue};
+
+/// Title of the feeds, as shown by feed readers.
+pub const FEED_TITLE: &str = "Newsletter";
+
+/// Most recent issues included in a feed. Older ones stay in the archive.
+pub const FEED_LENGTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl FeedFormat {
+    pub const ALL: [FeedFormat; 3] = [FeedFormat::Rss, FeedFormat::Atom, FeedFormat::Json];
+
+    /// Path the feed is served at.
+    pub fn path(&self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "/feed.rss",
+            FeedFormat::Atom => "/feed.atom",
+            FeedFormat::Json => "/feed.json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+}
+
+/// One public issue as it appears in a feed.
+#[derive(Debug)]
+pub struct FeedEntry {
+    pub id: Uuid,
+    pub title: String,
+    pub url: String,
+    pub published_at: DateTime<Utc>,
+    pub html: String,
+}
+
+impl FeedEntry {
+    /// Builds the entry for an issue of the public archive, or `None` for
+    /// issues that have not gone out or have no public page.
+    pub fn from_issue(issue: &NewsletterIssue, base_url: &str) -> Result<Option<Self>, anyhow::Error> {
+        let (Some(slug), Some(published_at)) = (&issue.slug, issue.published_at) else {
+            return Ok(None);
+        };
+        let rendered = render_public_issue(issue)?;
+        Ok(Some(Self {
+            id: issue.newsletter_issue_id,
+            title: rendered.subject,
+            url: format!("{}/issues/{}", base_url, slug),
+            published_at,
+            html: rendered.html,
+        }))
+    }
+
+    fn guid(&self) -> String {
+        format!("urn:uuid:{}", self.id)
+    }
+}
+
+/// A feed body along with what clients need to cache it.
+#[derive(Debug)]
+pub struct RenderedFeed {
+    pub body: String,
+    /// Strong validator derived from the body, without the quotes.
+    pub etag: String,
+}
+
+/// Renders `entries`, most recent first, in the given format.
+pub fn render_feed(format: FeedFormat, entries: &[FeedEntry], base_url: &str) -> RenderedFeed {
+    let body = match format {
+        FeedFormat::Rss => render_rss(entries, base_url),
+        FeedFormat::Atom => render_atom(entries, base_url),
+        FeedFormat::Json => render_json(entries, base_url),
+    };
+    let etag = format!("{:x}", Sha256::digest(body.as_bytes()));
+    RenderedFeed { body, etag }
+}
+
+/// Escapes text for use in XML content and attribute values. Characters XML
+/// cannot carry at all, such as most control characters, are dropped.
+pub fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn render_rss(entries: &[FeedEntry], base_url: &str) -> String {
+    let mut items = String::new();
+    for entry in entries {
+        items.push_str(&format!(
+            r#"
+    <item>
+      <title>{}</title>
+      <link>{}</link>
+      <guid isPermaLink="false">{}</guid>
+      <pubDate>{}</pubDate>
+      <description>{}</description>
+    </item>"#,
+            xml_escape(&entry.title),
+            xml_escape(&entry.url),
+            entry.guid(),
+            entry.published_at.to_rfc2822(),
+            xml_escape(&entry.html),
+        ));
+    }
+    let last_build_date = entries
+        .first()
+        .map(|entry| format!("\n    <lastBuildDate>{}</lastBuildDate>", entry.published_at.to_rfc2822()))
+        .unwrap_or_default();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>{title}</title>
+    <link>{base_url}/issues</link>
+    <description>Past issues of the {title}</description>
+    <atom:link href="{base_url}{self_path}" rel="self" type="application/rss+xml"/>{last_build_date}{items}
+  </channel>
+</rss>
+"#,
+        title = xml_escape(FEED_TITLE),
+        base_url = xml_escape(base_url),
+        self_path = FeedFormat::Rss.path(),
+    )
+}
+
+fn render_atom(entries: &[FeedEntry], base_url: &str) -> String {
+    let mut items = String::new();
+    for entry in entries {
+        let published = entry.published_at.to_rfc3339_opts(SecondsFormat::Secs, true);
+        items.push_str(&format!(
+            r#"
+  <entry>
+    <title>{}</title>
+    <link rel="alternate" type="text/html" href="{}"/>
+    <id>{}</id>
+    <published>{published}</published>
+    <updated>{published}</updated>
+    <content type="html">{}</content>
+  </entry>"#,
+            xml_escape(&entry.title),
+            xml_escape(&entry.url),
+            entry.guid(),
+            xml_escape(&entry.html),
+        ));
+    }
+    // A feed without entries still needs a stable `updated`, or its ETag
+    // would change on every request.
+    let updated = entries
+        .first()
+        .map(|entry| entry.published_at)
+        .unwrap_or(DateTime::UNIX_EPOCH)
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>{title}</title>
+  <link rel="alternate" type="text/html" href="{base_url}/issues"/>
+  <link rel="self" type="application/atom+xml" href="{base_url}{self_path}"/>
+  <id>{base_url}/issues</id>
+  <updated>{updated}</updated>{items}
+</feed>
+"#,
+        title = xml_escape(FEED_TITLE),
+        base_url = xml_escape(base_url),
+        self_path = FeedFormat::Atom.path(),
+    )
+}
+
+fn render_json(entries: &[FeedEntry], base_url: &str) -> String {
+    let items: Vec<_> = entries
+        .iter()
+        .map(|entry| json!({
+            "id": entry.guid(),
+            "url": entry.url,
+            "title": entry.title,
+            "content_html": entry.html,
+            "date_published": entry.published_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }))
+        .collect();
+    let feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": FEED_TITLE,
+        "home_page_url": format!("{}/issues", base_url),
+        "feed_url": format!("{}{}", base_url, FeedFormat::Json.path()),
+        "items": items,
+    });
+    serde_json::to_string_pretty(&feed).expect("JSON values always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{render_feed, xml_escape, FeedEntry, FeedFormat};
+
+    fn entry() -> FeedEntry {
+        FeedEntry {
+            id: Uuid::nil(),
+            title: "Rust & <Friends>".to_string(),
+            url: "https://example.com/issues/rust-friends".to_string(),
+            published_at: Utc.with_ymd_and_hms(2024, 10, 23, 9, 30, 0).unwrap(),
+            html: r#"<p>Hi <a href="https://example.com/?a=1&amp;b=2">there</a></p>"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn text_is_escaped_and_invalid_characters_are_dropped() {
+        assert_eq!(xml_escape(r#"<a href="x">Tom & Jerry's</a>"#), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;");
+        assert_eq!(xml_escape("bell\u{7}\tand\nline"), "bell\tand\nline");
+    }
+
+    #[test]
+    fn rss_items_carry_the_escaped_body_and_an_rfc_2822_date() {
+        let feed = render_feed(FeedFormat::Rss, &[entry()], "https://example.com");
+        assert!(feed.body.contains("<title>Rust &amp; &lt;Friends&gt;</title>"));
+        assert!(feed.body.contains("<pubDate>Wed, 23 Oct 2024 09:30:00 +0000</pubDate>"));
+        assert!(feed.body.contains(r#"<guid isPermaLink="false">urn:uuid:00000000-0000-0000-0000-000000000000</guid>"#));
+        assert!(feed.body.contains(
+            "<description>&lt;p&gt;Hi &lt;a href=&quot;https://example.com/?a=1&amp;amp;b=2&quot;&gt;there&lt;/a&gt;&lt;/p&gt;</description>"
+        ));
+    }
+
+    #[test]
+    fn atom_entries_use_rfc_3339_dates() {
+        let feed = render_feed(FeedFormat::Atom, &[entry()], "https://example.com");
+        assert!(feed.body.contains("<published>2024-10-23T09:30:00Z</published>"));
+        assert!(feed.body.contains("<updated>2024-10-23T09:30:00Z</updated>\n  <entry>"));
+        assert!(feed.body.contains(r#"<link rel="alternate" type="text/html" href="https://example.com/issues/rust-friends"/>"#));
+
+        let empty = render_feed(FeedFormat::Atom, &[], "https://example.com");
+        assert!(empty.body.contains("<updated>1970-01-01T00:00:00Z</updated>"));
+    }
+
+    #[test]
+    fn the_etag_follows_the_content() {
+        let a = render_feed(FeedFormat::Json, &[entry()], "https://example.com");
+        let b = render_feed(FeedFormat::Json, &[entry()], "https://example.com");
+        let c = render_feed(FeedFormat::Json, &[], "https://example.com");
+        assert_eq!(a.etag, b.etag);
+        assert_ne!(a.etag, c.etag);
+
+        let json: serde_json::Value = serde_json::from_str(&a.body).unwrap();
+        assert_eq!(json["items"][0]["title"], "Rust & <Friends>");
+        assert_eq!(json["items"][0]["date_published"], "2024-10-23T09:30:00Z");
+    }
+}
@@ -1,9 +1,7 @@
-use std::i16;
-
 use actix_web::{body::to_bytes, http::StatusCode, web, HttpResponse};
 use anyhow::Context;
 use chrono::Utc;
-use diesel::{insert_into, r2d2::ConnectionManager, PgConnection};
+use diesel::{r2d2::ConnectionManager, PgConnection};
 use r2d2::Pool;
 use uuid::Uuid;
 
@@ -47,10 +45,8 @@ pub async fn get_saved_response(
         )?;
 
         let mut response = HttpResponse::build(status_code);
-        for header in r.response_headers.unwrap() {
-            if let Some(SavedHeader { name, value }) = header {
-                response.append_header((name, value));
-            }
+        for SavedHeader { name, value } in r.response_headers.unwrap().into_iter().flatten() {
+            response.append_header((name, value));
         }
 
         Ok(Some(response.body(r.response_body.unwrap())))
@@ -4,6 +4,7 @@ use actix_web::{dev::{Transform, Service, ServiceRequest, ServiceResponse, forwa
 use futures_util::future::{LocalBoxFuture, Either};
 
 
+#[derive(Default)]
 pub struct IpChecker {
     pub allows: HashSet<String>
 }
@@ -15,12 +16,6 @@ impl IpChecker {
     }
 }
 
-impl Default for IpChecker {
-    fn default() -> Self {
-        Self { allows: HashSet::new() }
-    }
-}
-
 impl<S> Transform<S, ServiceRequest> for IpChecker
 where
     S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>,
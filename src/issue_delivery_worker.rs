@@ -1,11 +1,12 @@
 use std::time::Duration;
 
 use actix_web::web;
+use chrono::Utc;
 use diesel::{r2d2::ConnectionManager, Connection, PgConnection};
 use r2d2::{Pool, PooledConnection};
 use uuid::Uuid;
 
-use crate::{configuration::Settings, domain::subscriber_email::SubscriberEmail, email_client::EmailClient, models::{IssueDeliveryQueue, NewsletterIssue}, startup::get_connection_pool};
+use crate::{configuration::Settings, delivery_events::ISSUE_METADATA_KEY, domain::subscriber_email::SubscriberEmail, email_client::EmailClient, issues::IssueStatus, models::{IssueDelivery, IssueDeliveryQueue, NewsletterIssue, Template}, rendering::{load_recipient, render_issue_email, Recipient, RenderedEmail}, startup::get_connection_pool, subject_tests::{find_subject_test, find_subject_variant, sample_recipients}, suppressions::{find_suppression, lower}, templates::find_template, tracking::Tracking, welcome_sequence::try_send_sequence_step};
 
 pub async fn run_worker_until_stopped(
     configuration: Settings
@@ -26,25 +27,36 @@ pub async fn run_worker_until_stopped(
         timeout,
     );
 
-    worker_loop(connection_pool, email_client).await
+    let tracking = Tracking::new(configuration.application.hmac_secret, &configuration.tracking);
+
+    worker_loop(connection_pool, email_client, configuration.application.base_url, tracking).await
 }
 
-async fn worker_loop(pool: Pool<ConnectionManager<PgConnection>>, email_client: EmailClient) -> Result<(), anyhow::Error>{
+/// Every this many issue deliveries, a due welcome sequence step is sent
+/// first, so that a long issue run does not hold welcome emails back.
+const ISSUE_TASKS_PER_SEQUENCE_TURN: u32 = 10;
+
+async fn worker_loop(pool: Pool<ConnectionManager<PgConnection>>, email_client: EmailClient, base_url: String, tracking: Tracking) -> Result<(), anyhow::Error>{
+    let mut issue_tasks_since_sequence_turn = 0;
 
     loop{
         let mut conn = pool.get()?;
         let current_span = tracing::Span::current();
         let client_clone = email_client.clone();
+        let base_url = base_url.clone();
+        let tracking = tracking.clone();
+        let sequence_turn = issue_tasks_since_sequence_turn >= ISSUE_TASKS_PER_SEQUENCE_TURN;
 
         let transaction = web::block(move ||{
             current_span.in_scope(||{
-                conn.transaction(|conn| {
-                    try_execute_task(conn, &client_clone)
-                })
+                try_execute_next_task(&mut conn, &client_clone, &base_url, &tracking, sequence_turn)
             })
         })
         .await?;
 
+        if sequence_turn {
+            issue_tasks_since_sequence_turn = 0;
+        }
         match transaction{
             Ok(ExecutionOutcome::EmptyQueue) => {
                 tokio::time::sleep(Duration::from_secs(10)).await;
@@ -54,9 +66,36 @@ async fn worker_loop(pool: Pool<ConnectionManager<PgConnection>>, email_client:
                 tokio::time::sleep(Duration::from_secs(1)).await;
             },
 
-            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::TaskCompleted) => {
+                issue_tasks_since_sequence_turn += 1;
+            }
+        }
+    }
+}
+
+/// Sends the next email, each in its own transaction. Issue deliveries come
+/// first and welcome sequence steps go out when none is waiting, unless it
+/// is the sequence's turn to go first.
+pub fn try_execute_next_task(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    email_client: &EmailClient,
+    base_url: &str,
+    tracking: &Tracking,
+    sequence_turn: bool,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let send_sequence_step = |conn: &mut PooledConnection<ConnectionManager<PgConnection>>| {
+        conn.transaction(|conn| try_send_sequence_step(conn, email_client, base_url, Utc::now()))
+    };
+
+    if sequence_turn {
+        if let ExecutionOutcome::TaskCompleted = send_sequence_step(conn)? {
+            return Ok(ExecutionOutcome::TaskCompleted);
         }
     }
+    match conn.transaction(|conn| try_execute_task(conn, email_client, base_url, tracking))? {
+        ExecutionOutcome::EmptyQueue if !sequence_turn => send_sequence_step(conn),
+        outcome => Ok(outcome),
+    }
 }
 
 pub enum ExecutionOutcome{
@@ -64,6 +103,40 @@ pub enum ExecutionOutcome{
     EmptyQueue
 }
 
+/// What happened to a single recipient of an issue, see `issue_deliveries`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    Sent,
+    Failed,
+    Skipped,
+}
+
+impl DeliveryOutcome {
+    pub const ALL: [DeliveryOutcome; 3] = [
+        DeliveryOutcome::Sent,
+        DeliveryOutcome::Failed,
+        DeliveryOutcome::Skipped,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Sent => "sent",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+impl TryFrom<&str> for DeliveryOutcome {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        DeliveryOutcome::ALL
+            .into_iter()
+            .find(|outcome| outcome.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known delivery outcome.", s))
+    }
+}
+
 #[tracing::instrument(
     skip_all,
     fields(
@@ -73,7 +146,9 @@ pub enum ExecutionOutcome{
 )]
 pub fn try_execute_task(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    email_client: &EmailClient
+    email_client: &EmailClient,
+    base_url: &str,
+    tracking: &Tracking,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(conn)?;
     if task.is_none(){
@@ -81,32 +156,71 @@ pub fn try_execute_task(
     }
     
 
-    if let Some((issue_id, email)) = task{
+    if let Some((issue_id, email, subject_variant_id)) = task{
         tracing::Span::current()
             .record("newsletter_issue_id", tracing::field::display(issue_id))
             .record("subscriber_email", tracing::field::display(email.clone()));
 
-        match SubscriberEmail::parse(email.clone()){
+        let (outcome, detail) = match SubscriberEmail::parse(email.clone()){
+            // The address may have bounced or complained since it was queued.
+            Ok(_) if find_suppression(conn, &email)?.is_some() => {
+                tracing::info!("Skipping a suppressed address");
+                (DeliveryOutcome::Skipped, Some("suppressed".to_string()))
+            },
+
             Ok(email) => {
-                let issue = get_issue(conn, issue_id)?;
-
-                let rt = tokio::runtime::Handle::current();
-                let test = rt.block_on(async {
-                    email_client.send_email(
-                            &email,
-                            &issue.title,
-                            &issue.html,
-                            &issue.text,
-                    ).await
-                });
-
-                if let Err(e) = test{
-                    tracing::error!(
-                        error.cause_chain = ?e,
-                        error.message = %e,
-                        "Failed to deliver issue to a confirmed subscriber. \
-                         Skipping.",
-                    );
+                let mut issue = get_issue(conn, issue_id)?;
+                if let Some(variant_id) = subject_variant_id {
+                    issue.title = find_subject_variant(conn, variant_id)?.subject;
+                }
+                let template = match issue.template_id {
+                    Some(template_id) => find_template(conn, template_id)?,
+                    None => None,
+                };
+                let recipient = load_recipient(conn, &email.inner(), base_url)?
+                    .unwrap_or_else(|| Recipient {
+                        subscriber_id: None,
+                        email: email.inner(),
+                        name: String::new(),
+                        unsubscribe_url: String::new(),
+                    });
+
+                match prepare_email(&issue, template.as_ref(), &recipient, base_url, tracking) {
+                    Ok(rendered) => {
+                        let rt = tokio::runtime::Handle::current();
+                        let test = rt.block_on(async {
+                            email_client.send_email_with_metadata(
+                                    &email,
+                                    &rendered.subject,
+                                    &rendered.html,
+                                    &rendered.text,
+                                    &[(ISSUE_METADATA_KEY, issue_id.to_string())],
+                            ).await
+                        });
+
+                        match test {
+                            Ok(()) => (DeliveryOutcome::Sent, None),
+                            Err(e) => {
+                                tracing::error!(
+                                    error.cause_chain = ?e,
+                                    error.message = %e,
+                                    "Failed to deliver issue to a confirmed subscriber. \
+                                     Skipping.",
+                                );
+                                (DeliveryOutcome::Failed, Some(e.to_string()))
+                            }
+                        }
+                    },
+
+                    Err(e) => {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to render issue for a confirmed subscriber. \
+                             Skipping.",
+                        );
+                        (DeliveryOutcome::Failed, Some(e.to_string()))
+                    }
                 }
             },
 
@@ -117,15 +231,34 @@ pub fn try_execute_task(
                     "Skipping a confirmed subscriber. \
                      Their stored contact details are invalid",
                 );
+                (DeliveryOutcome::Skipped, Some(e))
             }
-        }
+        };
 
+        record_delivery(conn, issue_id, &email, subject_variant_id, outcome, detail)?;
         delete_task(conn, issue_id, &email)?;
+        mark_issue_as_sent_if_delivered(conn, issue_id)?;
     }
 
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+/// Renders the issue for one recipient, see `render_issue_email`, with
+/// tracking added for stored subscribers.
+fn prepare_email(
+    issue: &NewsletterIssue,
+    template: Option<&Template>,
+    recipient: &Recipient,
+    base_url: &str,
+    tracking: &Tracking,
+) -> Result<RenderedEmail, anyhow::Error> {
+    let rendered = render_issue_email(issue, template, recipient, base_url)?;
+    match recipient.subscriber_id {
+        Some(subscriber_id) => Ok(tracking.add_tracking(rendered, issue, subscriber_id, base_url)?),
+        None => Ok(rendered),
+    }
+}
+
 fn get_issue(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     issue_id_val: Uuid
@@ -134,18 +267,27 @@ fn get_issue(
     use crate::schema::newsletter_issues::dsl::*;
 
     Ok(newsletter_issues
-        .select((newsletter_issue_id, title, text, html, published_at))
         .filter(newsletter_issue_id.eq(issue_id_val))
         .first::<NewsletterIssue>(conn)?)
 }
 
+/// Picks a queued delivery of an issue that is still sending; rows of paused
+/// issues stay where they are, as do recipients held back for the winner of a
+/// subject test.
 #[tracing::instrument(skip_all)]
-fn dequeue_task(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> Result<Option<(Uuid, String)>, anyhow::Error>{
+fn dequeue_task(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> Result<Option<(Uuid, String, Option<Uuid>)>, anyhow::Error>{
     use diesel::prelude::*;
     use crate::schema::issue_delivery_queue::dsl::*;
+    use crate::schema::newsletter_issues;
+
+    // A subquery rather than a join, so that only the queue row is locked.
+    let sending_issues = newsletter_issues::table
+        .filter(newsletter_issues::status.eq(IssueStatus::Sending.as_str()))
+        .select(newsletter_issues::newsletter_issue_id);
 
     let r: Option<IssueDeliveryQueue> = issue_delivery_queue
-        .select((newsletter_issue_id, subscriber_email))
+        .filter(newsletter_issue_id.eq_any(sending_issues))
+        .filter(state.is_null())
         .for_update()
         .skip_locked()
         .limit(1)
@@ -155,7 +297,8 @@ fn dequeue_task(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) ->
     if let Some(r) = r{
         Ok(Some((
             r.newsletter_issue_id,
-            r.subscriber_email
+            r.subscriber_email,
+            r.subject_variant_id
         )))
     } else {
         Ok(None)
@@ -177,3 +320,109 @@ fn delete_task(conn: &mut PooledConnection<ConnectionManager<PgConnection>>,issu
     Ok(())
 }
 
+#[tracing::instrument(skip(conn))]
+fn record_delivery(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    email: &str,
+    subject_variant_id: Option<Uuid>,
+    outcome: DeliveryOutcome,
+    detail: Option<String>,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::issue_deliveries;
+
+    let delivery = IssueDelivery {
+        newsletter_issue_id: issue_id,
+        subscriber_email: email.to_string(),
+        outcome: outcome.as_str().to_string(),
+        detail,
+        attempted_at: Utc::now(),
+        subject_variant_id,
+    };
+
+    diesel::insert_into(issue_deliveries::table)
+        .values(&delivery)
+        .on_conflict((issue_deliveries::newsletter_issue_id, issue_deliveries::subscriber_email))
+        .do_update()
+        .set((
+            issue_deliveries::outcome.eq(&delivery.outcome),
+            issue_deliveries::detail.eq(&delivery.detail),
+            issue_deliveries::attempted_at.eq(delivery.attempted_at),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Flips a `sending` issue to `sent` once nothing is left in its delivery queue.
+#[tracing::instrument(skip(conn))]
+pub fn mark_issue_as_sent_if_delivered(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid
+) -> Result<(), anyhow::Error> {
+    use diesel::dsl::{exists, not};
+    use diesel::prelude::*;
+    use crate::schema::{issue_delivery_queue, newsletter_issues};
+
+    diesel::update(
+        newsletter_issues::table
+            .filter(newsletter_issues::newsletter_issue_id.eq(issue_id))
+            .filter(newsletter_issues::status.eq(IssueStatus::Sending.as_str()))
+            .filter(not(exists(
+                issue_delivery_queue::table
+                    .filter(issue_delivery_queue::newsletter_issue_id.eq(issue_id))
+            )))
+    )
+    .set(newsletter_issues::status.eq(IssueStatus::Sent.as_str()))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Queues one delivery task per confirmed subscriber whose address is not
+/// suppressed. For an issue with a subject test, only the sample can be sent
+/// straight away, see `sample_recipients`.
+#[tracing::instrument(skip_all)]
+pub fn enqueue_delivery_tasks(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    newsletter_issue_id_val: Uuid
+) -> Result<(), anyhow::Error> {
+    use diesel::dsl::{exists, not};
+    use diesel::prelude::*;
+
+    let confirmed_emails: Vec<String> = {
+        use crate::schema::subscriptions::dsl::*;
+        use crate::schema::suppressions;
+
+        subscriptions.filter(status.eq("confirmed"))
+            .filter(not(exists(
+                suppressions::table.filter(suppressions::email.eq(lower(email)))
+            )))
+            .select(email)
+            .load(conn)?
+    };
+
+    let new_entries: Vec<IssueDeliveryQueue> = match find_subject_test(conn, newsletter_issue_id_val)? {
+        Some((test, variants)) => sample_recipients(&test, &variants, confirmed_emails, &mut rand::thread_rng()),
+        None => confirmed_emails
+            .into_iter()
+            .map(|subscriber_email| IssueDeliveryQueue {
+                newsletter_issue_id: newsletter_issue_id_val,
+                subscriber_email,
+                state: None,
+                subject_variant_id: None,
+            })
+            .collect(),
+    };
+
+    {
+        use crate::schema::issue_delivery_queue::dsl::*;
+
+        diesel::insert_into(issue_delivery_queue)
+            .values(&new_entries)
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
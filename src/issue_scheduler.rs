@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::{archive::assign_slug, configuration::Settings, email_html::prepare_html, issue_delivery_worker::{enqueue_delivery_tasks, mark_issue_as_sent_if_delivered}, issues::IssueStatus, startup::get_connection_pool, subject_tests::pick_subject_test_winners, utils::html_unescape};
+
+pub async fn run_scheduler_until_stopped(
+    configuration: Settings
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(connection_pool).await
+}
+
+async fn scheduler_loop(pool: Pool<ConnectionManager<PgConnection>>) -> Result<(), anyhow::Error> {
+    loop {
+        let mut conn = pool.get()?;
+        let current_span = tracing::Span::current();
+
+        let outcome = web::block(move || {
+            current_span.in_scope(|| publish_due_issues(&mut conn))
+        })
+        .await?;
+
+        if let Err(e) = outcome {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to publish scheduled issues",
+            );
+        }
+
+        let mut conn = pool.get()?;
+        let current_span = tracing::Span::current();
+
+        let outcome = web::block(move || {
+            current_span.in_scope(|| pick_subject_test_winners(&mut conn))
+        })
+        .await?;
+
+        if let Err(e) = outcome {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to pick the winners of subject tests",
+            );
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+/// Publishes every scheduled issue whose time has come and returns their ids.
+///
+/// Each issue is published in a transaction of its own, so one that fails
+/// does not hold back the others. Its reason is stored on the issue for the
+/// editor to see, see `newsletter_issues.schedule_error`.
+#[tracing::instrument(skip_all)]
+pub fn publish_due_issues(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let due: Vec<Uuid> = newsletter_issues
+        .select(newsletter_issue_id)
+        .filter(status.eq(IssueStatus::Scheduled.as_str()))
+        .filter(scheduled_for.le(Utc::now()))
+        .load(conn)?;
+
+    let mut published = Vec::new();
+    for issue_id in due {
+        match conn.transaction(|conn| publish_due_issue(conn, issue_id)) {
+            Ok(true) => {
+                tracing::info!(newsletter_issue_id = %issue_id, "Published a scheduled issue");
+                published.push(issue_id);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                tracing::error!(
+                    newsletter_issue_id = %issue_id,
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to publish a scheduled issue. It will be retried",
+                );
+                let reason = format!("Publishing failed and will be retried: {}", e);
+                if let Err(e) = record_schedule_error(conn, issue_id, &reason) {
+                    tracing::error!(
+                        newsletter_issue_id = %issue_id,
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to record why a scheduled issue was not published",
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(published)
+}
+
+/// Publishes one due issue. The issue is locked with `SKIP LOCKED` and moved
+/// to `sending` in the same transaction that enqueues its delivery tasks, so
+/// it fires exactly once even with several schedulers running, and an editor
+/// rescheduling or cancelling at the last moment either wins or finds it
+/// already published.
+///
+/// The HTML is prepared as on any other publish. An issue whose HTML was
+/// edited into something that cannot be sent goes back to being a draft.
+/// Returns `false` if the issue was not published.
+fn publish_due_issue(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let now = Utc::now();
+    let issue_html: Option<String> = newsletter_issues
+        .select(html)
+        .filter(newsletter_issue_id.eq(issue_id))
+        .filter(status.eq(IssueStatus::Scheduled.as_str()))
+        .filter(scheduled_for.le(now))
+        .for_update()
+        .skip_locked()
+        .first(conn)
+        .optional()?;
+    let Some(issue_html) = issue_html else {
+        return Ok(false);
+    };
+
+    let prepared = prepare_html(&issue_html);
+    if !prepared.errors.is_empty() {
+        let errors: Vec<String> = prepared.errors.iter().map(|e| html_unescape(e)).collect();
+        diesel::update(newsletter_issues.filter(newsletter_issue_id.eq(issue_id)))
+            .set((
+                status.eq(IssueStatus::Draft.as_str()),
+                scheduled_for.eq(None::<DateTime<Utc>>),
+                schedule_error.eq(errors.join(" ")),
+            ))
+            .execute(conn)?;
+        tracing::error!(
+            newsletter_issue_id = %issue_id,
+            errors = ?errors,
+            "A scheduled issue cannot be sent. It is a draft again",
+        );
+        return Ok(false);
+    }
+
+    diesel::update(newsletter_issues.filter(newsletter_issue_id.eq(issue_id)))
+        .set((
+            status.eq(IssueStatus::Sending.as_str()),
+            published_at.eq(now),
+            html.eq(prepared.html),
+            schedule_error.eq(None::<String>),
+        ))
+        .execute(conn)?;
+
+    assign_slug(conn, issue_id)?;
+    enqueue_delivery_tasks(conn, issue_id)?;
+    mark_issue_as_sent_if_delivered(conn, issue_id)?;
+
+    Ok(true)
+}
+
+fn record_schedule_error(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    reason: &str,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    diesel::update(
+        newsletter_issues
+            .filter(newsletter_issue_id.eq(issue_id))
+            .filter(status.eq(IssueStatus::Scheduled.as_str()))
+    )
+    .set(schedule_error.eq(reason))
+    .execute(conn)?;
+
+    Ok(())
+}
@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{issue_delivery_worker::{mark_issue_as_sent_if_delivered, DeliveryOutcome}, markdown::render_markdown, models::NewsletterIssue, rendering::check_merge_tags, subject_tests::{save_subject_test, SubjectTestContent}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    Sending,
+    /// Published, but the worker leaves its queued deliveries alone until it
+    /// is resumed.
+    Paused,
+    Sent,
+    Cancelled,
+}
+
+impl IssueStatus {
+    pub const ALL: [IssueStatus; 6] = [
+        IssueStatus::Draft,
+        IssueStatus::Scheduled,
+        IssueStatus::Sending,
+        IssueStatus::Paused,
+        IssueStatus::Sent,
+        IssueStatus::Cancelled,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Sending => "sending",
+            IssueStatus::Paused => "paused",
+            IssueStatus::Sent => "sent",
+            IssueStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl TryFrom<&str> for IssueStatus {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        IssueStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known issue status.", s))
+    }
+}
+
+const EDITABLE_STATUSES: [&str; 2] = ["draft", "scheduled"];
+
+#[derive(Debug)]
+pub struct IssueContent {
+    pub title: String,
+    pub text: String,
+    pub html: String,
+    pub markdown: Option<String>,
+    pub template_id: Option<Uuid>,
+    pub track_opens: bool,
+    /// Whether the issue is listed in the public archive once published.
+    pub is_public: bool,
+    /// Set separately, see `SubjectTestContent::parse`.
+    pub subject_test: Option<SubjectTestContent>,
+}
+
+impl IssueContent {
+    /// Builds issue content from what an editor submitted. When a Markdown
+    /// body is given, the HTML and plain-text parts are generated from it and
+    /// the Markdown is kept so the issue can be edited later. An empty
+    /// `template_id` means the issue is sent without a layout.
+    ///
+    /// Merge tags are checked here so mistakes are reported on the form
+    /// instead of when the worker is sending.
+    pub fn parse(
+        title: String,
+        text: String,
+        html: String,
+        markdown: String,
+        template_id: &str,
+        track_opens: bool,
+    ) -> Result<Self, String> {
+        if title.trim().is_empty() {
+            return Err("The title cannot be empty.".to_string());
+        }
+        let template_id = match template_id.trim() {
+            "" => None,
+            id => Some(Uuid::parse_str(id).map_err(|_| "The template is not valid.".to_string())?),
+        };
+        let content = if markdown.trim().is_empty() {
+            Self { title, text, html, markdown: None, template_id, track_opens, is_public: false, subject_test: None }
+        } else {
+            let rendered = render_markdown(&markdown);
+            Self {
+                title,
+                text: rendered.text,
+                html: rendered.html,
+                markdown: Some(markdown),
+                template_id,
+                track_opens,
+                is_public: false,
+                subject_test: None,
+            }
+        };
+        check_merge_tags(&content.title, &content.html, &content.text)?;
+
+        Ok(content)
+    }
+
+    pub fn has_body(&self) -> bool {
+        !self.text.trim().is_empty() && !self.html.trim().is_empty()
+    }
+
+    /// An unsaved draft holding this content, for rendering it before it is
+    /// stored.
+    pub fn to_unsaved_issue(&self) -> NewsletterIssue {
+        NewsletterIssue {
+            newsletter_issue_id: Uuid::nil(),
+            title: self.title.clone(),
+            text: self.text.clone(),
+            html: self.html.clone(),
+            markdown: self.markdown.clone(),
+            published_at: None,
+            status: IssueStatus::Draft.as_str().to_string(),
+            created_by: None,
+            updated_at: Utc::now(),
+            scheduled_for: None,
+            template_id: self.template_id,
+            track_opens: self.track_opens,
+            slug: None,
+            is_public: self.is_public,
+            schedule_error: None,
+        }
+    }
+}
+
+impl NewsletterIssue {
+    /// The database only accepts known statuses, see the check constraint on
+    /// `newsletter_issues.status`, so an error here means the code is behind
+    /// the schema.
+    pub fn status(&self) -> Result<IssueStatus, String> {
+        IssueStatus::try_from(self.status.as_str())
+    }
+
+    /// Drafts and scheduled issues can still be changed; anything else has
+    /// already been handed to the delivery worker.
+    pub fn is_editable(&self) -> bool {
+        EDITABLE_STATUSES.contains(&self.status.as_str())
+    }
+}
+
+#[tracing::instrument(name = "Create draft issue", skip(pool, content))]
+pub async fn create_draft(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    uid: Option<Uuid>,
+    content: IssueContent,
+) -> Result<Uuid, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let issue = NewsletterIssue {
+        newsletter_issue_id: Uuid::new_v4(),
+        title: content.title,
+        text: content.text,
+        html: content.html,
+        markdown: content.markdown,
+        published_at: None,
+        status: IssueStatus::Draft.as_str().to_string(),
+        created_by: uid,
+        updated_at: Utc::now(),
+        scheduled_for: None,
+        template_id: content.template_id,
+        track_opens: content.track_opens,
+        slug: None,
+        is_public: content.is_public,
+        schedule_error: None,
+    };
+    let issue_id = issue.newsletter_issue_id;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                diesel::insert_into(newsletter_issues)
+                    .values(issue)
+                    .execute(conn)
+                    .context("Failed to insert draft issue")?;
+                save_subject_test(conn, issue_id, content.subject_test.as_ref())
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(issue_id)
+}
+
+#[tracing::instrument(name = "Get issue", skip(pool))]
+pub async fn get_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let issue = web::block(move || {
+        current_span.in_scope(|| {
+            newsletter_issues
+                .filter(newsletter_issue_id.eq(issue_id))
+                .first::<NewsletterIssue>(&mut conn)
+                .optional()
+                .context("Failed to fetch issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(issue)
+}
+
+/// Returns the issues with the given status, or every issue. Unpublished
+/// issues come first, then the most recently published ones.
+#[tracing::instrument(name = "Get issues", skip(pool))]
+pub async fn get_issues(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_status: Option<IssueStatus>,
+) -> Result<Vec<NewsletterIssue>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let issues = web::block(move || {
+        current_span.in_scope(|| {
+            let mut query = newsletter_issues
+                .order((published_at.desc().nulls_first(), updated_at.desc()))
+                .into_boxed();
+            if let Some(s) = issue_status {
+                query = query.filter(status.eq(s.as_str()));
+            }
+            query
+                .load::<NewsletterIssue>(&mut conn)
+                .context("Failed to fetch issues")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(issues)
+}
+
+/// How far the delivery worker has got with an issue.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeliveryProgress {
+    pub sent: i64,
+    pub failed: i64,
+    pub skipped: i64,
+    /// Rows still waiting in `issue_delivery_queue`.
+    pub remaining: i64,
+    pub first_attempt: Option<DateTime<Utc>>,
+    pub last_attempt: Option<DateTime<Utc>>,
+}
+
+impl DeliveryProgress {
+    pub fn recipients(&self) -> i64 {
+        self.attempted() + self.remaining
+    }
+
+    pub fn attempted(&self) -> i64 {
+        self.sent + self.failed + self.skipped
+    }
+
+    /// Time left at the pace the worker has kept so far. `None` until there
+    /// are enough attempts to measure a pace.
+    pub fn eta(&self) -> Option<TimeDelta> {
+        if self.remaining == 0 {
+            return Some(TimeDelta::zero());
+        }
+        let (first, last) = (self.first_attempt?, self.last_attempt?);
+        let elapsed = last - first;
+        if self.attempted() < 2 || elapsed <= TimeDelta::zero() {
+            return None;
+        }
+        Some(elapsed * (self.remaining as i32) / ((self.attempted() - 1) as i32))
+    }
+}
+
+/// Delivery progress of every issue that has been handed to the worker,
+/// keyed by issue.
+#[tracing::instrument(name = "Get delivery progress", skip(pool))]
+pub async fn get_delivery_progress(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<HashMap<Uuid, DeliveryProgress>, anyhow::Error> {
+    use diesel::dsl::count_star;
+    use diesel::prelude::*;
+    use crate::schema::{issue_deliveries, issue_delivery_queue};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let (deliveries, queued) = web::block(move || {
+        current_span.in_scope(|| -> Result<_, anyhow::Error> {
+            let deliveries = issue_deliveries::table
+                .group_by((issue_deliveries::newsletter_issue_id, issue_deliveries::outcome))
+                .select((
+                    issue_deliveries::newsletter_issue_id,
+                    issue_deliveries::outcome,
+                    count_star(),
+                    diesel::dsl::min(issue_deliveries::attempted_at),
+                    diesel::dsl::max(issue_deliveries::attempted_at),
+                ))
+                .load::<(Uuid, String, i64, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(&mut conn)
+                .context("Failed to fetch delivery outcomes")?;
+            let queued = issue_delivery_queue::table
+                .group_by(issue_delivery_queue::newsletter_issue_id)
+                .select((issue_delivery_queue::newsletter_issue_id, count_star()))
+                .load::<(Uuid, i64)>(&mut conn)
+                .context("Failed to fetch queued deliveries")?;
+            Ok((deliveries, queued))
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    let mut progress: HashMap<Uuid, DeliveryProgress> = HashMap::new();
+    for (issue_id, outcome, count, first, last) in deliveries {
+        let entry = progress.entry(issue_id).or_default();
+        match DeliveryOutcome::try_from(outcome.as_str()) {
+            Ok(DeliveryOutcome::Sent) => entry.sent = count,
+            Ok(DeliveryOutcome::Failed) => entry.failed = count,
+            Ok(DeliveryOutcome::Skipped) => entry.skipped = count,
+            Err(e) => anyhow::bail!(e),
+        }
+        entry.first_attempt = entry.first_attempt.into_iter().chain(first).min();
+        entry.last_attempt = entry.last_attempt.into_iter().chain(last).max();
+    }
+    for (issue_id, count) in queued {
+        progress.entry(issue_id).or_default().remaining = count;
+    }
+
+    Ok(progress)
+}
+
+/// Overwrites the content of a draft or scheduled issue.
+/// Returns `false` if there is no such issue or it has already been published.
+#[tracing::instrument(name = "Update draft issue", skip(pool, content))]
+pub async fn update_draft(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    content: IssueContent,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let rows_affected = diesel::update(
+                    newsletter_issues
+                        .filter(newsletter_issue_id.eq(issue_id))
+                        .filter(status.eq_any(EDITABLE_STATUSES))
+                )
+                .set((
+                    title.eq(content.title),
+                    text.eq(content.text),
+                    html.eq(content.html),
+                    markdown.eq(content.markdown),
+                    template_id.eq(content.template_id),
+                    track_opens.eq(content.track_opens),
+                    is_public.eq(content.is_public),
+                ))
+                .execute(conn)
+                .context("Failed to update draft issue")?;
+                if rows_affected > 0 {
+                    save_subject_test(conn, issue_id, content.subject_test.as_ref())?;
+                }
+                Ok::<_, anyhow::Error>(rows_affected)
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Returns `false` if there is no such issue or it is no longer a draft.
+#[tracing::instrument(name = "Delete draft issue", skip(pool))]
+pub async fn delete_draft(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::delete(
+                newsletter_issues
+                    .filter(newsletter_issue_id.eq(issue_id))
+                    .filter(status.eq(IssueStatus::Draft.as_str()))
+            )
+            .execute(&mut conn)
+            .context("Failed to delete draft issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Schedules a draft, or moves an already scheduled issue to a new time,
+/// clearing the reason a previous schedule failed.
+/// Returns `false` if there is no such issue or it has already been published.
+#[tracing::instrument(name = "Schedule issue", skip(pool))]
+pub async fn schedule_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    publish_at: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(
+                newsletter_issues
+                    .filter(newsletter_issue_id.eq(issue_id))
+                    .filter(status.eq_any(EDITABLE_STATUSES))
+            )
+            .set((
+                status.eq(IssueStatus::Scheduled.as_str()),
+                scheduled_for.eq(publish_at),
+                schedule_error.eq(None::<String>),
+            ))
+            .execute(&mut conn)
+            .context("Failed to schedule issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Turns a scheduled issue back into a draft.
+/// Returns `false` if the issue is not scheduled, e.g. because it has just fired.
+#[tracing::instrument(name = "Unschedule issue", skip(pool))]
+pub async fn unschedule_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(
+                newsletter_issues
+                    .filter(newsletter_issue_id.eq(issue_id))
+                    .filter(status.eq(IssueStatus::Scheduled.as_str()))
+            )
+            .set((
+                status.eq(IssueStatus::Draft.as_str()),
+                scheduled_for.eq(None::<DateTime<Utc>>),
+            ))
+            .execute(&mut conn)
+            .context("Failed to unschedule issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Stops the worker from picking up further deliveries of a sending issue.
+/// Returns `false` if the issue is not sending.
+#[tracing::instrument(name = "Pause issue", skip(pool))]
+pub async fn pause_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(
+                newsletter_issues
+                    .filter(newsletter_issue_id.eq(issue_id))
+                    .filter(status.eq(IssueStatus::Sending.as_str()))
+            )
+            .set(status.eq(IssueStatus::Paused.as_str()))
+            .execute(&mut conn)
+            .context("Failed to pause issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Hands a paused issue back to the worker. Returns `false` if the issue is
+/// not paused.
+#[tracing::instrument(name = "Resume issue", skip(pool))]
+pub async fn resume_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::newsletter_issues::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let rows_affected = diesel::update(
+                    newsletter_issues
+                        .filter(newsletter_issue_id.eq(issue_id))
+                        .filter(status.eq(IssueStatus::Paused.as_str()))
+                )
+                .set(status.eq(IssueStatus::Sending.as_str()))
+                .execute(conn)
+                .context("Failed to resume issue")?;
+                // The last delivery may have finished just as the issue was
+                // paused, leaving nothing for the worker to pick up.
+                mark_issue_as_sent_if_delivered(conn, issue_id)?;
+                Ok::<_, anyhow::Error>(rows_affected)
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Drops the deliveries still queued for a sending or paused issue and marks
+/// it as cancelled. Returns how many recipients had already been sent the
+/// issue, or `None` if it was not being sent.
+#[tracing::instrument(name = "Cancel issue", skip(pool))]
+pub async fn cancel_issue(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Option<i64>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{issue_deliveries, issue_delivery_queue, newsletter_issues};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let sent = web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let rows_affected = diesel::update(
+                    newsletter_issues::table
+                        .filter(newsletter_issues::newsletter_issue_id.eq(issue_id))
+                        .filter(newsletter_issues::status.eq_any([
+                            IssueStatus::Sending.as_str(),
+                            IssueStatus::Paused.as_str(),
+                        ]))
+                )
+                .set(newsletter_issues::status.eq(IssueStatus::Cancelled.as_str()))
+                .execute(conn)
+                .context("Failed to cancel issue")?;
+                if rows_affected == 0 {
+                    return Ok(None);
+                }
+
+                // Waits for a delivery the worker is in the middle of, so the
+                // count below includes it.
+                diesel::delete(
+                    issue_delivery_queue::table
+                        .filter(issue_delivery_queue::newsletter_issue_id.eq(issue_id))
+                )
+                .execute(conn)
+                .context("Failed to remove queued deliveries")?;
+
+                let sent = issue_deliveries::table
+                    .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+                    .filter(issue_deliveries::outcome.eq(DeliveryOutcome::Sent.as_str()))
+                    .count()
+                    .get_result::<i64>(conn)
+                    .context("Failed to count sent deliveries")?;
+                Ok::<_, anyhow::Error>(Some(sent))
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(sent)
+}
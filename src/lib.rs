@@ -1,6 +1,11 @@
+pub mod archive;
 pub mod configuration;
+pub mod delivery_events;
 pub mod domain;
 pub mod email_client;
+pub mod email_html;
+pub mod feed_poller;
+pub mod feeds;
 pub mod models;
 mod routes;
 pub mod schema;
@@ -8,10 +13,23 @@ pub mod startup;
 pub mod telemetry;
 pub mod authentication;
 pub mod session_state;
+pub mod session_registry;
+pub mod csrf;
+pub mod audit;
+pub mod issues;
+pub mod markdown;
+pub mod rendering;
+pub mod reports;
+pub mod subject_tests;
+pub mod suppressions;
+pub mod templates;
+pub mod tracking;
 pub mod utils;
+pub mod welcome_sequence;
 pub mod ipchecker;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod issue_scheduler;
 pub mod traits;
 pub mod diesel_adapter;
 pub mod services;
@@ -1,6 +1,8 @@
 use std::fmt::{Debug, Display};
 
+use newsletter::feed_poller::run_feed_poller_until_stopped;
 use newsletter::issue_delivery_worker::run_worker_until_stopped;
+use newsletter::issue_scheduler::run_scheduler_until_stopped;
 use newsletter::startup::Application;
 use newsletter::configuration::get_configuration;
 use newsletter::telemetry::{get_subscriber, init_subscriber};
@@ -15,11 +17,15 @@ async fn main() -> anyhow::Result<()>{
     let application = Application::build(config.clone()).await?;
 
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(config));
+    let worker_task = tokio::spawn(run_worker_until_stopped(config.clone()));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(config.clone()));
+    let feed_poller_task = tokio::spawn(run_feed_poller_until_stopped(config));
 
     tokio::select! {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker", o),
+        o = scheduler_task => report_exit("Issue scheduler", o),
+        o = feed_poller_task => report_exit("Feed poller", o),
     };
 
     Ok(())
@@ -0,0 +1,216 @@
+use pulldown_cmark::{html, Event, HeadingLevel, LinkType, Parser, Tag, TagEnd};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+/// Renders an issue written in Markdown to sanitized HTML and to a plain-text
+/// alternative for clients that do not display HTML.
+pub fn render_markdown(markdown: &str) -> RenderedMarkdown {
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new(markdown));
+
+    RenderedMarkdown {
+        html: ammonia::clean(&unsafe_html),
+        text: render_plain_text(markdown),
+    }
+}
+
+/// Headings are underlined, links are numbered and listed as footnotes at the
+/// bottom, lists and block quotes keep their markers, and raw HTML is dropped.
+fn render_plain_text(markdown: &str) -> String {
+    let mut w = TextWriter::default();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut links: Vec<String> = Vec::new();
+    let mut pending_link: Option<String> = None;
+    let mut heading: Option<(HeadingLevel, String)> = None;
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                heading = Some((level, String::new()));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, title)) = heading.take() {
+                    let underline = if level == HeadingLevel::H1 { "=" } else { "-" };
+                    w.write(&title);
+                    w.newline();
+                    w.write(&underline.repeat(title.graphemes(true).count()));
+                    w.blank_line();
+                }
+            }
+            Event::End(TagEnd::Paragraph) => w.blank_line(),
+            Event::Start(Tag::BlockQuote(_)) => {
+                w.start_line();
+                w.prefixes.push("> ".to_string());
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                w.prefixes.pop();
+                w.blank_line();
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                w.start_line();
+                w.prefixes.push("    ".to_string());
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                w.prefixes.pop();
+                w.blank_line();
+            }
+            Event::Start(Tag::List(first)) => {
+                w.start_line();
+                lists.push(first);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    w.blank_line();
+                } else {
+                    w.start_line();
+                }
+            }
+            Event::Start(Tag::Item) => {
+                w.start_line();
+                let marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                w.write(&marker);
+                w.prefixes.push(" ".repeat(marker.len()));
+            }
+            Event::End(TagEnd::Item) => {
+                w.prefixes.pop();
+                w.start_line();
+            }
+            // Autolinks already show their address.
+            Event::Start(Tag::Link { link_type, dest_url, .. })
+                if !matches!(link_type, LinkType::Autolink | LinkType::Email) =>
+            {
+                pending_link = Some(dest_url.to_string());
+            }
+            Event::End(TagEnd::Link) => {
+                if let Some(url) = pending_link.take() {
+                    links.push(url);
+                    let marker = format!(" [{}]", links.len());
+                    match heading.as_mut() {
+                        Some((_, title)) => title.push_str(&marker),
+                        None => w.write(&marker),
+                    }
+                }
+            }
+            Event::Start(Tag::Image { .. }) => w.write("[image: "),
+            Event::End(TagEnd::Image) => w.write("]"),
+            Event::Text(text) | Event::Code(text) => match heading.as_mut() {
+                Some((_, title)) => title.push_str(&text),
+                None => w.write(&text),
+            },
+            Event::SoftBreak | Event::HardBreak => match heading.as_mut() {
+                Some((_, title)) => title.push(' '),
+                None => w.newline(),
+            },
+            Event::Rule => {
+                w.start_line();
+                w.write("----------");
+                w.blank_line();
+            }
+            _ => {}
+        }
+    }
+
+    let mut text = w.out.trim_end().to_string();
+    if !links.is_empty() {
+        text.push_str("\n\nLinks:");
+        for (i, url) in links.iter().enumerate() {
+            text.push_str(&format!("\n[{}] {}", i + 1, url));
+        }
+    }
+    text
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    /// Written at the start of every line, e.g. `> ` inside a block quote.
+    prefixes: Vec<String>,
+    at_line_start: bool,
+}
+
+impl TextWriter {
+    fn write(&mut self, s: &str) {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if self.at_line_start || self.out.is_empty() {
+                self.out.push_str(&self.prefixes.concat());
+                self.at_line_start = false;
+            }
+            self.out.push_str(line);
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn start_line(&mut self) {
+        if !self.at_line_start && !self.out.is_empty() {
+            self.newline();
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.start_line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::render_markdown;
+
+    #[test]
+    fn headings_are_underlined_in_plain_text() {
+        let rendered = render_markdown("# Title\n\n## Section\n\nBody");
+        assert_eq!(rendered.text, "Title\n=====\n\nSection\n-------\n\nBody");
+    }
+
+    #[test]
+    fn links_become_numbered_footnotes() {
+        let rendered = render_markdown(
+            "Read [the post](https://example.com/post) and [the docs](https://example.com/docs).",
+        );
+        assert_eq!(
+            rendered.text,
+            "Read the post [1] and the docs [2].\n\n\
+             Links:\n[1] https://example.com/post\n[2] https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn lists_and_quotes_keep_their_markers() {
+        let rendered = render_markdown("- one\n- two\n\n1. first\n2. second\n\n> quoted");
+        assert_eq!(rendered.text, "- one\n- two\n\n1. first\n2. second\n\n> quoted");
+    }
+
+    #[test]
+    fn html_is_sanitized() {
+        let rendered = render_markdown(
+            "Hello <script>alert(1)</script><a href=\"https://example.com\" onclick=\"steal()\">there</a>",
+        );
+        assert!(!rendered.html.contains("<script"));
+        assert!(!rendered.html.contains("onclick"));
+        assert!(rendered.html.contains("https://example.com"));
+    }
+}
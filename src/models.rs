@@ -1,9 +1,23 @@
+use crate::schema::audit_events;
+use crate::schema::delivery_events;
 use crate::schema::idempotency;
+use crate::schema::issue_clicks;
+use crate::schema::issue_deliveries;
 use crate::schema::issue_delivery_queue;
+use crate::schema::issue_opens;
 use crate::schema::newsletter_issues;
+use crate::schema::processed_feed_entries;
+use crate::schema::sequence_deliveries;
+use crate::schema::sequence_enrollments;
+use crate::schema::sequence_steps;
 use crate::schema::sql_types::HeaderPair;
+use crate::schema::subject_tests;
+use crate::schema::subject_variants;
 use crate::schema::subscription_tokens;
 use crate::schema::subscriptions;
+use crate::schema::suppressions;
+use crate::schema::templates;
+use crate::schema::user_sessions;
 use chrono::{DateTime, Utc};
 use diesel::deserialize::FromSql;
 use diesel::deserialize::FromSqlRow;
@@ -26,7 +40,18 @@ pub struct NewsletterIssue{
     pub title: String,
     pub text: String,
     pub html: String,
-    pub published_at: String
+    pub published_at: Option<DateTime<Utc>>,
+    pub status: String,
+    pub created_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub markdown: Option<String>,
+    pub template_id: Option<Uuid>,
+    pub track_opens: bool,
+    pub slug: Option<String>,
+    pub is_public: bool,
+    /// Why the scheduler could not publish the issue, see `publish_due_issues`.
+    pub schedule_error: Option<String>,
 }
 
 #[derive(Insertable, Queryable)]
@@ -34,6 +59,115 @@ pub struct NewsletterIssue{
 pub struct IssueDeliveryQueue{
     pub newsletter_issue_id: Uuid,
     pub subscriber_email: String,
+    pub state: Option<String>,
+    pub subject_variant_id: Option<Uuid>,
+}
+
+#[derive(Insertable, Queryable)]
+#[diesel(table_name = issue_deliveries)]
+pub struct IssueDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_email: String,
+    pub outcome: String,
+    pub detail: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+    pub subject_variant_id: Option<Uuid>,
+}
+
+#[derive(Insertable, Queryable)]
+#[diesel(table_name = subject_tests)]
+pub struct SubjectTest {
+    pub newsletter_issue_id: Uuid,
+    pub sample_percent: i32,
+    pub wait_minutes: i32,
+    pub metric: String,
+    pub winning_variant_id: Option<Uuid>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable, Queryable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = subject_variants)]
+pub struct SubjectVariant {
+    pub subject_variant_id: Uuid,
+    pub newsletter_issue_id: Uuid,
+    pub position: i32,
+    pub subject: String,
+}
+
+#[derive(Insertable, Queryable, Debug)]
+#[diesel(table_name = processed_feed_entries)]
+pub struct ProcessedFeedEntry {
+    pub source: String,
+    pub guid: String,
+    pub newsletter_issue_id: Option<Uuid>,
+    pub processed_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Queryable, Debug, Clone)]
+#[diesel(table_name = sequence_steps)]
+pub struct SequenceStep {
+    pub sequence_step_id: Uuid,
+    pub delay_days: i32,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Queryable, Debug)]
+#[diesel(table_name = sequence_enrollments)]
+pub struct SequenceEnrollment {
+    pub subscriber_id: Uuid,
+    pub enrolled_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Queryable, Debug)]
+#[diesel(table_name = sequence_deliveries)]
+pub struct SequenceDelivery {
+    pub subscriber_id: Uuid,
+    pub sequence_step_id: Uuid,
+    pub due_at: DateTime<Utc>,
+    pub outcome: Option<String>,
+    pub detail: Option<String>,
+    pub attempted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = issue_opens)]
+pub struct IssueOpenAdd {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub first_opened_at: DateTime<Utc>,
+    pub last_opened_at: DateTime<Utc>,
+    pub open_count: i32,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = issue_clicks)]
+pub struct IssueClickAdd {
+    pub issue_click_id: Uuid,
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub url: String,
+    pub user_agent: Option<String>,
+    pub clicked_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = delivery_events)]
+pub struct DeliveryEventAdd {
+    pub delivery_event_id: Uuid,
+    pub kind: String,
+    pub email: String,
+    pub subscriber_id: Option<Uuid>,
+    pub newsletter_issue_id: Option<Uuid>,
+    pub message_id: Option<String>,
+    pub bounce_type: Option<String>,
+    pub description: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
 }
 
 #[derive(Queryable)]
@@ -71,6 +205,15 @@ pub struct SubscriptionAdd {
     pub status: String,
 }
 
+#[derive(Insertable, Queryable)]
+#[diesel(table_name = suppressions)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = subscription_tokens)]
 pub struct SubscriptionTokensAdd {
@@ -96,8 +239,65 @@ pub struct VerificationInfo{
     pub password: String
 }
 
+#[derive(Insertable)]
+#[diesel(table_name = user_sessions)]
+pub struct UserSessionAdd {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Queryable)]
+pub struct UserSession {
+    pub session_id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Queryable)]
+#[diesel(table_name = templates)]
+pub struct Template {
+    pub template_id: Uuid,
+    pub name: String,
+    pub kind: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEventAdd {
+    pub audit_event_id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub action: String,
+    pub target: Option<String>,
+    pub metadata: serde_json::Value,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Queryable)]
+pub struct AuditEvent {
+    pub audit_event_id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub metadata: serde_json::Value,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(FromSqlRow, AsExpression, Debug)]
-#[sql_type = "HeaderPair"]
+#[diesel(sql_type = HeaderPair)]
 pub struct SavedHeader{
     pub name: String,
     pub value: Vec<u8>
@@ -0,0 +1,457 @@
+use actix_web::web;
+use anyhow::Context;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use minijinja::{context, AutoEscape, Environment, UndefinedBehavior, Value};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::{archive::{add_view_in_browser_link, public_issue_url}, models::{NewsletterIssue, SequenceStep, Template}, utils::html_escape};
+
+/// The subscriber an email is rendered for.
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    /// `None` for stand-ins that are not stored subscribers.
+    pub subscriber_id: Option<Uuid>,
+    pub email: String,
+    pub name: String,
+    pub unsubscribe_url: String,
+}
+
+impl Recipient {
+    /// Stand-in used by previews and test sends. Its unsubscribe link has the
+    /// right shape but does not belong to anyone.
+    pub fn sample(base_url: &str) -> Self {
+        Self {
+            subscriber_id: None,
+            email: "subscriber@example.com".to_string(),
+            name: "Sample Subscriber".to_string(),
+            unsubscribe_url: unsubscribe_url(base_url, "preview"),
+        }
+    }
+}
+
+pub fn unsubscribe_url(base_url: &str, subscription_token: &str) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?subscription_token={}",
+        base_url, subscription_token
+    )
+}
+
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Merge tags editors can use in the title and body of an issue, e.g.
+/// `{{name|default('there')}}`. Single quotes survive Markdown rendering,
+/// double quotes are turned into entities. Only these tags are filled in,
+/// everything else, including other braces, is sent as written; a quoted
+/// string like `{{ '{{' }}` comes out as is.
+pub const MERGE_TAGS: [&str; 3] = ["name", "email", "unsubscribe_url"];
+
+/// Produces the issue as `recipient` will receive it, see `render_issue_email`.
+///
+/// Merge tags in the issue are filled in first. Without a template the result
+/// is sent as is. With one, it is placed in the layout's `{{content}}` slot;
+/// values from the issue and the subscriber are HTML-escaped in the HTML part,
+/// the issue body is not.
+pub fn render_issue(
+    issue: &NewsletterIssue,
+    template: Option<&Template>,
+    recipient: &Recipient,
+) -> Result<RenderedEmail, minijinja::Error> {
+    let personalised = fill_merge_tags(&issue.title, &issue.html, &issue.text, recipient);
+    let Some(template) = template else {
+        return Ok(personalised);
+    };
+
+    let ctx = context! {
+        issue => context! { title => &personalised.subject },
+        subscriber => context! { name => &recipient.name, email => &recipient.email },
+        unsubscribe_url => trusted_url(&recipient.unsubscribe_url),
+    };
+
+    render_template(
+        template,
+        context! { content => Value::from_safe_string(personalised.html), ..ctx.clone() },
+        context! { content => personalised.text, ..ctx.clone() },
+        ctx,
+    )
+}
+
+/// Renders the email a subscriber receives, short of tracking: the issue as
+/// `render_issue` produces it, with a link to its public copy if it is in the
+/// archive. The delivery worker, test sends and the admin preview all go
+/// through here.
+pub fn render_issue_email(
+    issue: &NewsletterIssue,
+    template: Option<&Template>,
+    recipient: &Recipient,
+    base_url: &str,
+) -> Result<RenderedEmail, minijinja::Error> {
+    let mut rendered = render_issue(issue, template, recipient)?;
+    if let Some(url) = public_issue_url(issue, base_url) {
+        rendered = add_view_in_browser_link(rendered, &url);
+    }
+    Ok(rendered)
+}
+
+/// Whether `source` fills in the merge tag `tag` anywhere.
+pub fn uses_merge_tag(source: &str, tag: &str) -> bool {
+    parse_merge_tags(source)
+        .iter()
+        .any(|segment| matches!(segment, Segment::Tag { name, .. } if *name == tag))
+}
+
+/// Fills in the merge tags of a title on its own, for listings that show
+/// nothing else of the issue.
+pub fn render_title(title: &str, recipient: &Recipient) -> String {
+    fill_in(title, recipient, false)
+}
+
+/// Fills in the merge tags of a welcome sequence step. Steps are sent as
+/// written, without a template.
+pub fn render_sequence_step(step: &SequenceStep, recipient: &Recipient) -> RenderedEmail {
+    fill_merge_tags(&step.subject, &step.html, &step.text, recipient)
+}
+
+/// Rejects merge tags that would come out wrong at send time: unclosed tags,
+/// filters other than `default` and unknown tag names.
+pub fn check_merge_tags(title: &str, html: &str, text: &str) -> Result<(), String> {
+    for (part, source) in [("title", title), ("HTML", html), ("plain text", text)] {
+        let mut unknown = Vec::new();
+        for segment in parse_merge_tags(source) {
+            match segment {
+                Segment::Invalid { reason, .. } => {
+                    return Err(format!("The {} contains an invalid merge tag: {}", part, reason));
+                }
+                Segment::Tag { name, .. } if !MERGE_TAGS.contains(&name) && !unknown.contains(&name) => {
+                    unknown.push(name);
+                }
+                _ => {}
+            }
+        }
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(format!(
+                "The {} uses unknown merge tags: {}. Available tags are {}.",
+                part,
+                unknown.join(", "),
+                MERGE_TAGS.join(", "),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn fill_merge_tags(title: &str, html: &str, text: &str, recipient: &Recipient) -> RenderedEmail {
+    RenderedEmail {
+        subject: fill_in(title, recipient, false),
+        html: fill_in(html, recipient, true),
+        text: fill_in(text, recipient, false),
+    }
+}
+
+/// Missing details fall back to the tag's default. Unknown and invalid tags
+/// were rejected when publishing and are left as written.
+fn fill_in(source: &str, recipient: &Recipient, escape: bool) -> String {
+    let mut filled = String::with_capacity(source.len());
+    for segment in parse_merge_tags(source) {
+        match segment {
+            Segment::Text(text) | Segment::Invalid { raw: text, .. } => filled.push_str(text),
+            Segment::Tag { raw, name, default } => {
+                let (value, trusted) = match name {
+                    "name" => (recipient.name.as_str(), false),
+                    "email" => (recipient.email.as_str(), false),
+                    // A link we build ourselves, there is nothing to escape.
+                    "unsubscribe_url" => (recipient.unsubscribe_url.as_str(), true),
+                    _ => {
+                        filled.push_str(raw);
+                        continue;
+                    }
+                };
+                let value = if value.is_empty() { default.unwrap_or_default() } else { value };
+                if escape && !trusted {
+                    filled.push_str(&html_escape(value));
+                } else {
+                    filled.push_str(value);
+                }
+            }
+        }
+    }
+    filled
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Tag { raw: &'a str, name: &'a str, default: Option<&'a str> },
+    Invalid { raw: &'a str, reason: String },
+}
+
+/// Makes text that is not meant to hold merge tags, like a polled blog post,
+/// come out exactly as written.
+pub fn escape_merge_tags(s: &str) -> String {
+    s.replace("{{", "{{ '{{' }}").replace("{%", "{{ '{%' }}")
+}
+
+/// Splits `source` into text and `{{ tag }}` or `{{ tag|default('...') }}`
+/// merge tags. `{{ '...' }}` is text in quotes. Braces around anything else,
+/// like `{{ a b }}`, are text.
+fn parse_merge_tags(source: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = source;
+    while let Some(open) = rest.find("{{") {
+        let inner = &rest[open + 2..];
+        let trimmed = inner.trim_start();
+        if let Some((value, len)) = quoted_string(trimmed) {
+            segments.push(Segment::Text(&rest[..open]));
+            segments.push(Segment::Text(value));
+            rest = &trimmed[len..];
+            continue;
+        }
+        let name_len = trimmed
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(trimmed.len());
+        let name = &trimmed[..name_len];
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            segments.push(Segment::Text(&rest[..open + 2]));
+            rest = inner;
+            continue;
+        }
+
+        let Some(close) = inner.find("}}") else {
+            segments.push(Segment::Text(&rest[..open]));
+            segments.push(Segment::Invalid {
+                raw: &rest[open..],
+                reason: format!("{{{{{} is not closed with }}}}.", name),
+            });
+            return segments;
+        };
+        let end = open + 2 + close + 2;
+        let raw = &rest[open..end];
+        let leading_space = inner.len() - trimmed.len();
+        let after_name = trimmed[name_len..close - leading_space].trim();
+        segments.push(Segment::Text(&rest[..open]));
+        segments.push(if after_name.is_empty() {
+            Segment::Tag { raw, name, default: None }
+        } else if let Some(filter) = after_name.strip_prefix('|') {
+            match parse_default(filter.trim()) {
+                Some(default) => Segment::Tag { raw, name, default: Some(default) },
+                None => Segment::Invalid {
+                    raw,
+                    reason: format!("{} only supports default('...'), not {}.", raw, filter.trim()),
+                },
+            }
+        } else {
+            Segment::Text(raw)
+        });
+        rest = &rest[end..];
+    }
+    segments.push(Segment::Text(rest));
+    segments.retain(|segment| *segment != Segment::Text(""));
+    segments
+}
+
+/// `'...' }}` or `"..." }}` at the start of `s`: the string and the length
+/// up to and including the braces.
+fn quoted_string(s: &str) -> Option<(&str, usize)> {
+    let quote = s.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let end = s[1..].find(quote)? + 1;
+    let after = &s[end + 1..];
+    let closing = after.trim_start().strip_prefix("}}")?;
+    Some((&s[1..end], s.len() - closing.len()))
+}
+
+/// The value in `default('...')` or `default("...")`.
+fn parse_default(filter: &str) -> Option<&str> {
+    let argument = filter.strip_prefix("default")?.trim_start().strip_prefix('(')?.strip_suffix(')')?.trim();
+    ['\'', '"'].into_iter().find_map(|quote| {
+        let value = argument.strip_prefix(quote)?.strip_suffix(quote)?;
+        (!value.contains(quote)).then_some(value)
+    })
+}
+
+pub fn render_confirmation(
+    template: &Template,
+    recipient: &Recipient,
+    confirmation_url: &str,
+) -> Result<RenderedEmail, minijinja::Error> {
+    let ctx = context! {
+        subscriber => context! { name => &recipient.name, email => &recipient.email },
+        confirmation_url => trusted_url(confirmation_url),
+    };
+
+    render_template(template, ctx.clone(), ctx.clone(), ctx)
+}
+
+/// Links we build ourselves from the base URL and a token, so there is nothing
+/// to escape; the HTML escaper would otherwise turn every `/` into an entity.
+fn trusted_url(url: &str) -> Value {
+    Value::from_safe_string(url.to_string())
+}
+
+fn render_template(
+    template: &Template,
+    html_ctx: Value,
+    text_ctx: Value,
+    subject_ctx: Value,
+) -> Result<RenderedEmail, minijinja::Error> {
+    // A misspelt slot should be an error, not a silently blank space.
+    let env = environment(UndefinedBehavior::Strict);
+
+    Ok(RenderedEmail {
+        subject: env
+            .template_from_named_str("subject.txt", &template.subject)?
+            .render(subject_ctx)?,
+        html: env
+            .template_from_named_str("body.html", &template.html)?
+            .render(html_ctx)?,
+        text: env
+            .template_from_named_str("body.txt", &template.text)?
+            .render(text_ctx)?,
+    })
+}
+
+/// Values are HTML-escaped in `.html` templates only.
+fn environment(undefined_behavior: UndefinedBehavior) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(undefined_behavior);
+    env.set_auto_escape_callback(|name| {
+        if name.ends_with(".html") {
+            AutoEscape::Html
+        } else {
+            AutoEscape::None
+        }
+    });
+    env
+}
+
+#[tracing::instrument(name = "Find recipient", skip(pool))]
+pub async fn find_recipient(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    subscriber_email: String,
+    base_url: String,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let recipient = web::block(move || {
+        current_span.in_scope(|| load_recipient(&mut conn, &subscriber_email, &base_url))
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(recipient)
+}
+
+/// Blocking lookup for callers that already hold a connection, like the
+/// delivery worker.
+pub fn load_recipient(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    subscriber_email: &str,
+    base_url: &str,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{subscription_tokens, subscriptions};
+
+    let row = subscriptions::table
+        .left_join(subscription_tokens::table)
+        .select((
+            subscriptions::id,
+            subscriptions::email,
+            subscriptions::name,
+            subscription_tokens::subscription_token.nullable(),
+        ))
+        .filter(subscriptions::email.eq(subscriber_email))
+        .first::<(Uuid, String, String, Option<String>)>(conn)
+        .optional()
+        .context("Failed to fetch subscriber")?;
+
+    Ok(row.map(|(id, email, name, token)| Recipient {
+        subscriber_id: Some(id),
+        email,
+        name,
+        unsubscribe_url: token
+            .map(|t| unsubscribe_url(base_url, &t))
+            .unwrap_or_default(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rendering::{check_merge_tags, escape_merge_tags, fill_merge_tags, Recipient};
+
+    fn recipient(name: &str) -> Recipient {
+        Recipient {
+            subscriber_id: None,
+            email: "ursula@example.com".to_string(),
+            name: name.to_string(),
+            unsubscribe_url: "https://example.com/unsubscribe?a=1&b=2".to_string(),
+        }
+    }
+
+    #[test]
+    fn known_tags_are_filled_in() {
+        let rendered = fill_merge_tags(
+            "Hi {{ name|default('there') }}",
+            r#"<p>Hi {{name}}, <a href="{{unsubscribe_url}}">unsubscribe</a> {{ email }}</p>"#,
+            "Hi {{ name | default(\"there\") }}",
+            &recipient("<Ursula>"),
+        );
+        assert_eq!(rendered.subject, "Hi <Ursula>");
+        assert_eq!(
+            rendered.html,
+            r#"<p>Hi &lt;Ursula&gt;, <a href="https://example.com/unsubscribe?a=1&b=2">unsubscribe</a> ursula@example.com</p>"#
+        );
+        assert_eq!(rendered.text, "Hi <Ursula>");
+
+        let rendered = fill_merge_tags("Hi {{ name|default('there') }}", "", "{{name}}.", &recipient(""));
+        assert_eq!(rendered.subject, "Hi there");
+        assert_eq!(rendered.text, ".");
+    }
+
+    #[test]
+    fn other_braces_are_left_as_written() {
+        let cases = [
+            "<p>Use {% raw %}{{ name }}{% endraw %}</p>",
+            "<style>p{color:red}</style><p>{{ literally braces }}</p>",
+            "{# not a comment #} {{ 1 + 1 }} {{}} }} {",
+        ];
+        for html in cases {
+            assert!(check_merge_tags("T", html, "x").is_ok(), "{} was rejected", html);
+        }
+
+        let rendered = fill_merge_tags("T", cases[1], cases[2], &recipient("Ursula"));
+        assert_eq!(rendered.html, cases[1]);
+        assert_eq!(rendered.text, cases[2]);
+        let rendered = fill_merge_tags("T", cases[0], "", &recipient("Ursula"));
+        assert_eq!(rendered.html, "<p>Use {% raw %}Ursula{% endraw %}</p>");
+    }
+
+    #[test]
+    fn escaped_text_comes_out_as_written() {
+        let text = "{{ name }} {{user}} {% if x %} {{{ '{{' }} {";
+        let escaped = escape_merge_tags(text);
+        assert!(check_merge_tags(&escaped, &escaped, &escaped).is_ok());
+        let rendered = fill_merge_tags(&escaped, &escaped, &escaped, &recipient("Ursula"));
+        assert_eq!(rendered.subject, text);
+        assert_eq!(rendered.html, text);
+    }
+
+    #[test]
+    fn broken_and_unknown_tags_are_rejected() {
+        let cases = [
+            ("Hi {{nmae}} {{ nmae }} {{ emial }}", "The plain text uses unknown merge tags: emial, nmae."),
+            ("Hi {{name", "The plain text contains an invalid merge tag: {{name is not closed with }}."),
+            ("Hi {{name|upper}}", "The plain text contains an invalid merge tag: {{name|upper}} only supports default('...'), not upper."),
+            ("Hi {{name|default(there)}}", "invalid merge tag"),
+        ];
+        for (text, error) in cases {
+            let e = check_merge_tags("T", "", text).unwrap_err();
+            assert!(e.contains(error), "{} gave {}", text, e);
+        }
+    }
+}
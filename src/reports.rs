@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::issue_delivery_worker::DeliveryOutcome;
+
+/// How far after publishing the open time series goes.
+pub const OPEN_SERIES_HOURS: usize = 72;
+
+/// How many links the report ranks.
+const TOP_LINKS: i64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+/// Engagement with a published issue, put together from its delivery, open
+/// and click records.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IssueReport {
+    pub recipients: i64,
+    pub delivered: i64,
+    /// Failed and skipped deliveries.
+    pub failed: i64,
+    pub bounced: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    pub unsubscribes: i64,
+    pub top_links: Vec<LinkClicks>,
+    /// Subscribers who first opened the issue in each hour after it was
+    /// published. Empty for issues that have not been published.
+    pub opens_by_hour: Vec<i64>,
+}
+
+/// One row of the per-recipient export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipientActivity {
+    pub email: String,
+    /// A delivery outcome, or `queued` while the worker has yet to get to it.
+    pub delivery: String,
+    pub attempted_at: Option<DateTime<Utc>>,
+    pub bounced_at: Option<DateTime<Utc>>,
+    pub first_opened_at: Option<DateTime<Utc>>,
+    pub opens: i32,
+    pub clicks: i64,
+    pub unsubscribed: bool,
+}
+
+#[tracing::instrument(name = "Get issue report", skip(pool))]
+pub async fn get_issue_report(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    published_at: Option<DateTime<Utc>>,
+) -> Result<IssueReport, anyhow::Error> {
+    use diesel::dsl::{count_distinct, count_star};
+    use diesel::prelude::*;
+    use crate::schema::{issue_clicks, issue_deliveries, issue_delivery_queue, issue_opens};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            let mut report = IssueReport::default();
+
+            let outcomes = issue_deliveries::table
+                .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+                .group_by(issue_deliveries::outcome)
+                .select((issue_deliveries::outcome, count_star()))
+                .load::<(String, i64)>(&mut conn)
+                .context("Failed to fetch delivery outcomes")?;
+            for (outcome, count) in outcomes {
+                match DeliveryOutcome::try_from(outcome.as_str()) {
+                    Ok(DeliveryOutcome::Sent) => report.delivered += count,
+                    Ok(DeliveryOutcome::Failed | DeliveryOutcome::Skipped) => report.failed += count,
+                    Err(e) => anyhow::bail!(e),
+                }
+                report.recipients += count;
+            }
+            report.recipients += issue_delivery_queue::table
+                .filter(issue_delivery_queue::newsletter_issue_id.eq(issue_id))
+                .count()
+                .get_result::<i64>(&mut conn)
+                .context("Failed to count queued deliveries")?;
+            report.bounced = issue_deliveries::table
+                .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+                .filter(issue_deliveries::bounced_at.is_not_null())
+                .count()
+                .get_result(&mut conn)
+                .context("Failed to count bounces")?;
+
+            let first_opens = issue_opens::table
+                .filter(issue_opens::newsletter_issue_id.eq(issue_id))
+                .select(issue_opens::first_opened_at)
+                .load::<DateTime<Utc>>(&mut conn)
+                .context("Failed to fetch opens")?;
+            report.unique_opens = first_opens.len() as i64;
+            if let Some(published_at) = published_at {
+                report.opens_by_hour = vec![0; OPEN_SERIES_HOURS];
+                for opened_at in first_opens {
+                    let hour = (opened_at - published_at).num_hours().max(0) as usize;
+                    if let Some(bucket) = report.opens_by_hour.get_mut(hour) {
+                        *bucket += 1;
+                    }
+                }
+            }
+
+            report.unique_clicks = issue_clicks::table
+                .filter(issue_clicks::newsletter_issue_id.eq(issue_id))
+                .select(count_distinct(issue_clicks::subscriber_id))
+                .get_result(&mut conn)
+                .context("Failed to count clicks")?;
+            report.top_links = issue_clicks::table
+                .filter(issue_clicks::newsletter_issue_id.eq(issue_id))
+                .group_by(issue_clicks::url)
+                .select((issue_clicks::url, count_star(), count_distinct(issue_clicks::subscriber_id)))
+                .order((count_distinct(issue_clicks::subscriber_id).desc(), count_star().desc(), issue_clicks::url.asc()))
+                .limit(TOP_LINKS)
+                .load::<(String, i64, i64)>(&mut conn)
+                .context("Failed to fetch top links")?
+                .into_iter()
+                .map(|(url, clicks, unique_clicks)| LinkClicks { url, clicks, unique_clicks })
+                .collect();
+
+            report.unsubscribes = attributed_unsubscribes(&mut conn, issue_id)?.len() as i64;
+
+            Ok(report)
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")?
+}
+
+/// Everyone the issue was queued for, ordered by email address.
+#[tracing::instrument(name = "Get recipient activity", skip(pool))]
+pub async fn get_recipient_activity(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Vec<RecipientActivity>, anyhow::Error> {
+    use diesel::dsl::count_star;
+    use diesel::prelude::*;
+    use crate::schema::{issue_clicks, issue_deliveries, issue_delivery_queue, issue_opens, subscriptions};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            let deliveries = issue_deliveries::table
+                .left_join(subscriptions::table.on(subscriptions::email.eq(issue_deliveries::subscriber_email)))
+                .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+                .select((
+                    issue_deliveries::subscriber_email,
+                    issue_deliveries::outcome,
+                    issue_deliveries::attempted_at.nullable(),
+                    issue_deliveries::bounced_at,
+                    subscriptions::id.nullable(),
+                ))
+                .load::<(String, String, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<Uuid>)>(&mut conn)
+                .context("Failed to fetch deliveries")?;
+            let queued = issue_delivery_queue::table
+                .left_join(subscriptions::table.on(subscriptions::email.eq(issue_delivery_queue::subscriber_email)))
+                .filter(issue_delivery_queue::newsletter_issue_id.eq(issue_id))
+                .select((issue_delivery_queue::subscriber_email, subscriptions::id.nullable()))
+                .load::<(String, Option<Uuid>)>(&mut conn)
+                .context("Failed to fetch queued deliveries")?;
+
+            let opens: HashMap<Uuid, (DateTime<Utc>, i32)> = issue_opens::table
+                .filter(issue_opens::newsletter_issue_id.eq(issue_id))
+                .select((issue_opens::subscriber_id, issue_opens::first_opened_at, issue_opens::open_count))
+                .load::<(Uuid, DateTime<Utc>, i32)>(&mut conn)
+                .context("Failed to fetch opens")?
+                .into_iter()
+                .map(|(subscriber_id, first, count)| (subscriber_id, (first, count)))
+                .collect();
+            let clicks: HashMap<Uuid, i64> = issue_clicks::table
+                .filter(issue_clicks::newsletter_issue_id.eq(issue_id))
+                .group_by(issue_clicks::subscriber_id)
+                .select((issue_clicks::subscriber_id, count_star()))
+                .load::<(Uuid, i64)>(&mut conn)
+                .context("Failed to fetch clicks")?
+                .into_iter()
+                .collect();
+            let unsubscribed: HashSet<String> = attributed_unsubscribes(&mut conn, issue_id)?
+                .into_iter()
+                .collect();
+
+            let queued = queued
+                .into_iter()
+                .map(|(email, subscriber_id)| (email, "queued".to_string(), None, None, subscriber_id));
+            let mut rows: Vec<RecipientActivity> = deliveries
+                .into_iter()
+                .chain(queued)
+                .map(|(email, delivery, attempted_at, bounced_at, subscriber_id)| {
+                    let opened = subscriber_id.and_then(|id| opens.get(&id));
+                    RecipientActivity {
+                        unsubscribed: unsubscribed.contains(&email),
+                        email,
+                        delivery,
+                        attempted_at,
+                        bounced_at,
+                        first_opened_at: opened.map(|(first, _)| *first),
+                        opens: opened.map(|(_, count)| *count).unwrap_or(0),
+                        clicks: subscriber_id.and_then(|id| clicks.get(&id)).copied().unwrap_or(0),
+                    }
+                })
+                .collect();
+            rows.sort_by(|a, b| a.email.cmp(&b.email));
+
+            Ok(rows)
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")?
+}
+
+/// Addresses whose unsubscribe is put down to this issue: they were sent it,
+/// left afterwards and were not sent anything else in between.
+fn attributed_unsubscribes(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    use diesel::dsl::{exists, not};
+    use diesel::prelude::*;
+    use crate::schema::{issue_deliveries, subscriptions};
+
+    let later = diesel::alias!(issue_deliveries as later_deliveries);
+    let sent = DeliveryOutcome::Sent.as_str();
+
+    issue_deliveries::table
+        .inner_join(subscriptions::table.on(subscriptions::email.eq(issue_deliveries::subscriber_email)))
+        .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+        .filter(issue_deliveries::outcome.eq(sent))
+        .filter(subscriptions::status.eq("unsubscribed"))
+        .filter(subscriptions::unsubscribed_at.ge(issue_deliveries::attempted_at.nullable()))
+        .filter(not(exists(
+            later
+                .filter(later.field(issue_deliveries::subscriber_email).eq(issue_deliveries::subscriber_email))
+                .filter(later.field(issue_deliveries::outcome).eq(sent))
+                .filter(later.field(issue_deliveries::attempted_at).gt(issue_deliveries::attempted_at))
+                .filter(later.field(issue_deliveries::attempted_at).nullable().le(subscriptions::unsubscribed_at))
+        )))
+        .select(issue_deliveries::subscriber_email)
+        .load::<String>(conn)
+        .context("Failed to fetch unsubscribes")
+}
@@ -0,0 +1,170 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+
+use crate::{audit::{get_audit_events, AuditAction, AuditFilter}, utils::{e400, e500, html_escape, parse_datetime}};
+
+const PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    username: Option<String>,
+    action: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl TryFrom<&AuditQuery> for AuditFilter {
+    type Error = String;
+    fn try_from(query: &AuditQuery) -> Result<Self, Self::Error> {
+        let action = match non_empty(&query.action) {
+            Some(a) => Some(AuditAction::try_from(a)?),
+            None => None,
+        };
+        let from = match non_empty(&query.from) {
+            Some(f) => Some(parse_timestamp(f, false)?),
+            None => None,
+        };
+        let to = match non_empty(&query.to) {
+            Some(t) => Some(parse_timestamp(t, true)?),
+            None => None,
+        };
+
+        Ok(Self {
+            username: non_empty(&query.username).map(|u| u.to_string()),
+            action,
+            from,
+            to,
+        })
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Like `parse_datetime`, but also accepts plain dates. A plain date used
+/// as an upper bound covers the whole day.
+fn parse_timestamp(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(t) = parse_datetime(value) {
+        return Ok(t);
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let t = if end_of_day {
+            d.and_hms_opt(23, 59, 59).unwrap()
+        } else {
+            d.and_hms_opt(0, 0, 0).unwrap()
+        };
+        return Ok(t.and_utc());
+    }
+    Err(format!("{} is not a valid timestamp.", value))
+}
+
+#[tracing::instrument(name = "View audit log", skip(pool, request))]
+pub async fn audit_log(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = AuditFilter::try_from(&query.0).map_err(e400)?;
+    let events = get_audit_events(&pool, filter, Some(PAGE_SIZE)).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for e in &events {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td><code>{}</code></td>
+            <td>{}</td>
+        </tr>"#,
+            e.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            html_escape(e.username.as_deref().unwrap_or("-")),
+            html_escape(&e.action),
+            html_escape(e.target.as_deref().unwrap_or("-")),
+            html_escape(&e.metadata.to_string()),
+            html_escape(e.ip_address.as_deref().unwrap_or("-")),
+        ).unwrap();
+    }
+
+    let mut action_options = String::from(r#"<option value="">Any</option>"#);
+    let selected_action = non_empty(&query.action).unwrap_or_default();
+    for a in AuditAction::ALL {
+        let selected = if a.as_str() == selected_action { " selected" } else { "" };
+        write!(action_options, r#"<option value="{0}"{selected}>{0}</option>"#, a.as_str()).unwrap();
+    }
+
+    let username = html_escape(query.username.as_deref().unwrap_or_default());
+    let from = html_escape(query.from.as_deref().unwrap_or_default());
+    let to = html_escape(query.to.as_deref().unwrap_or_default());
+    let export_url = html_escape(&format!("/admin/audit/export?{}", request.query_string()));
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Audit log</title>
+</head>
+<body>
+    <h1>Audit log</h1>
+    <form action="/admin/audit" method="get">
+        <label>Username
+            <input type="text" name="username" value="{username}">
+        </label>
+        <label>Action
+            <select name="action">{action_options}</select>
+        </label>
+        <label>From
+            <input type="datetime-local" name="from" value="{from}">
+        </label>
+        <label>To
+            <input type="datetime-local" name="to" value="{to}">
+        </label>
+        <button type="submit">Filter</button>
+    </form>
+    <p><a href="{export_url}">Export as NDJSON</a></p>
+    <table>
+        <tr>
+            <th>Time</th>
+            <th>User</th>
+            <th>Action</th>
+            <th>Target</th>
+            <th>Metadata</th>
+            <th>IP address</th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+#[tracing::instrument(name = "Export audit log", skip(pool))]
+pub async fn audit_log_export(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = AuditFilter::try_from(&query.0).map_err(e400)?;
+    let events = get_audit_events(&pool, filter, None).await.map_err(e500)?;
+
+    let mut body = String::new();
+    for e in &events {
+        writeln!(body, "{}", e.to_json()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            "Content-Disposition",
+            r#"attachment; filename="audit-log.ndjson""#,
+        ))
+        .body(body))
+}
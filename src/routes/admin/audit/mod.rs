@@ -0,0 +1,2 @@
+mod get;
+pub use get::{audit_log, audit_log_export};
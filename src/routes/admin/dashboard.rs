@@ -4,11 +4,12 @@ use diesel::{r2d2::ConnectionManager, ExpressionMethods, PgConnection, QueryDsl,
 use r2d2::Pool;
 use uuid::Uuid;
 
-use crate::{session_state::UserId, utils::e500};
+use crate::{csrf::CsrfToken, session_state::UserId, utils::e500};
 
-pub async fn admin_dashboard(pool:web::Data<Pool<ConnectionManager<PgConnection>>>, user_id: web::ReqData<UserId>) -> Result<HttpResponse, actix_web::Error>{
+pub async fn admin_dashboard(pool:web::Data<Pool<ConnectionManager<PgConnection>>>, user_id: web::ReqData<UserId>, csrf_token: web::ReqData<CsrfToken>) -> Result<HttpResponse, actix_web::Error>{
     let user_id = user_id.into_inner();
-    let username = get_username(*user_id, &pool).await.map_err(|e| e500(e))?;
+    let csrf_token = csrf_token.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
 
     Ok(HttpResponse::Ok()
             .content_type(ContentType::html())
@@ -26,10 +27,17 @@ pub async fn admin_dashboard(pool:web::Data<Pool<ConnectionManager<PgConnection>
             <li><a href="/admin/password">Change password</a></li>
             <li>
               <form name="logoutForm" action="/admin/logout" method="post">
+                <input hidden type="text" name="csrf_token" value="{csrf_token}">
                 <input type="submit" value="Logout">
               </form>
             </li>
             <li><a href="/admin/newsletter">Send a newsletter issue</a></li>
+            <li><a href="/admin/issues">Newsletter drafts</a></li>
+            <li><a href="/admin/templates">Email templates</a></li>
+            <li><a href="/admin/suppressions">Suppression list</a></li>
+            <li><a href="/admin/sequence">Welcome sequence</a></li>
+            <li><a href="/admin/sessions">Manage active sessions</a></li>
+            <li><a href="/admin/audit">Audit log</a></li>
         </ol>
     </body>
     </html>"#,
@@ -1,14 +1,45 @@
 use std::fmt::Write;
 
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use uuid::Uuid;
 
-pub async fn newsletter_delivery_form(flash_messages: IncomingFlashMessages) -> HttpResponse{
+use crate::{csrf::CsrfToken, models::Template, routes::admin::{issues::track_opens_html, templates::{template_select_html, MERGE_TAGS_HELP}}, templates::{get_templates, TemplateKind}, utils::{e500, html_escape}};
+
+/// Values to pre-fill the newsletter form with.
+#[derive(Default)]
+pub struct NewsletterFormValues {
+    pub title: String,
+    pub text: String,
+    pub html: String,
+    pub markdown: String,
+    pub template_id: String,
+    pub track_opens: bool,
+    pub test_recipients: String,
+}
+
+pub async fn newsletter_delivery_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
-    let idempotency_key = uuid::Uuid::new_v4();
+    let templates = get_templates(&pool, Some(TemplateKind::Issue)).await.map_err(e500)?;
+    Ok(render_newsletter_form(&msg_html, &NewsletterFormValues::default(), &templates, &Uuid::new_v4().to_string(), &csrf_token))
+}
+
+pub fn render_newsletter_form(
+    msg_html: &str,
+    values: &NewsletterFormValues,
+    templates: &[Template],
+    idempotency_key: &str,
+    csrf_token: &CsrfToken,
+) -> HttpResponse {
     HttpResponse::Ok().body(format!(r#"
         <!DOCTYPE html>
         <html lang="en">
@@ -22,19 +53,45 @@ pub async fn newsletter_delivery_form(flash_messages: IncomingFlashMessages) ->
             {}
             <form action="/admin/newsletter" method="POST">
                 <label for="title">Title:</label><br>
-                <input type="text" id="title" name="title" required><br><br>
+                <input type="text" id="title" name="title" value="{}" required><br><br>
+
+                {}
+
+                <label for="markdown">Markdown:</label><br>
+                <textarea id="markdown" name="markdown" rows="15" cols="80">{}</textarea><br>
+                <small>When Markdown is provided, the plain text and HTML below are generated from it.</small><br>
+                <small>{}</small><br><br>
 
                 <label for="text">Content:</label><br>
-                <input type="text" id="text" name="text" required><br><br>
+                <textarea id="text" name="text" rows="15" cols="80">{}</textarea><br><br>
 
                 <label for="html">HTML:</label><br>
-                <input type="text" id="html" name="html" required><br><br>
+                <textarea id="html" name="html" rows="15" cols="80">{}</textarea><br><br>
+
+                {}
+
+                <label for="test_recipients">Test recipients (comma separated):</label><br>
+                <input type="text" id="test_recipients" name="test_recipients" value="{}"><br><br>
 
                 <input hidden type="text" name="idempotency_key" value="{}">
+                <input hidden type="text" name="csrf_token" value="{}">
 
+                <input type="submit" formaction="/admin/newsletter/test" value="Send test">
                 <input type="submit" value="Submit">
             </form>
         </body>
         </html>
-    "#, msg_html, idempotency_key))
+    "#,
+        msg_html,
+        html_escape(&values.title),
+        template_select_html(templates, Uuid::parse_str(&values.template_id).ok()),
+        html_escape(&values.markdown),
+        MERGE_TAGS_HELP,
+        html_escape(&values.text),
+        html_escape(&values.html),
+        track_opens_html(values.track_opens),
+        html_escape(&values.test_recipients),
+        html_escape(idempotency_key),
+        csrf_token,
+    ))
 }
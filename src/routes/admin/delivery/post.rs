@@ -8,8 +8,7 @@ use diesel::{r2d2::{ConnectionManager, Pool}, PgConnection};
 use diesel::prelude::*;
 use uuid::Uuid;
 
-use crate::{email_client::EmailClient, idempotency::{get_saved_response, persistence::{save_response, try_processing, NextAction}, IdempotencyKey}, models::{IssueDeliveryQueue, NewsletterIssue, Subscription}, routes::admin::dashboard::get_username, session_state::UserId, utils::see_other};
-use crate::domain::subscriber_email::SubscriberEmail;
+use crate::{archive::assign_slug, audit::{insert_audit_event, AuditAction, NewAuditEvent}, csrf::CsrfToken, domain::subscriber_email::SubscriberEmail, email_client::EmailClient, email_html::prepare_html, idempotency::{persistence::{save_response, try_processing, NextAction}, IdempotencyKey}, issue_delivery_worker::{enqueue_delivery_tasks, mark_issue_as_sent_if_delivered}, issues::{IssueContent, IssueStatus}, models::NewsletterIssue, rendering::{render_issue_email, Recipient}, routes::admin::{dashboard::get_username, delivery::get::{render_newsletter_form, NewsletterFormValues}}, session_state::UserId, startup::ApplicationBaseUrl, subject_tests::save_subject_test, templates::{get_template, get_templates, TemplateKind}, utils::{client_ip, e500, html_escape, see_other}};
 
 use crate::routes::subscribe::error_chain_fmt;
 
@@ -38,32 +37,165 @@ impl ResponseError for PublishError {
 #[derive(Deserialize, Debug)]
 pub struct BodyData{
     title: String,
+    #[serde(default)]
     text: String,
+    #[serde(default)]
     html: String,
+    #[serde(default)]
+    markdown: String,
+    #[serde(default)]
+    template_id: String,
+    #[serde(default)]
+    track_opens: bool,
     idempotency_key: String
 }
 
+#[derive(Deserialize, Debug)]
+pub struct TestIssueData {
+    title: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    html: String,
+    #[serde(default)]
+    markdown: String,
+    #[serde(default)]
+    template_id: String,
+    #[serde(default)]
+    track_opens: bool,
+    idempotency_key: String,
+    #[serde(default)]
+    test_recipients: String,
+}
+
+/// Sends the issue straight to the given reviewer addresses, bypassing the
+/// delivery queue. Nothing is stored and the idempotency key is left unused,
+/// so the same form can still be submitted for real afterwards.
+#[tracing::instrument(
+    name = "Sending a test issue",
+    skip(body, pool, email_client, base_url, csrf_token)
+)]
+pub async fn send_test_issue(
+    body: web::Form<TestIssueData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let TestIssueData { title, text, html, markdown, template_id, track_opens, idempotency_key, test_recipients } = body.0;
+
+    let mut msg_html = String::new();
+    let content = IssueContent::parse(title.clone(), text.clone(), html.clone(), markdown.clone(), &template_id, track_opens);
+    let recipients: Result<Vec<SubscriberEmail>, String> = test_recipients
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| SubscriberEmail::parse(r.to_string()))
+        .collect();
 
-pub struct ConfirmedSubscriber{
-    email: SubscriberEmail
+    match (recipients, content) {
+        (Err(e), _) | (_, Err(e)) => {
+            msg_html.push_str(&format!("<p><i>{}</i></p>", html_escape(&e)));
+        }
+        (Ok(recipients), _) if recipients.is_empty() => {
+            msg_html.push_str("<p><i>Enter at least one address to send a test issue to.</i></p>");
+        }
+        (Ok(recipients), Ok(mut content)) => {
+            // Reviewers get the HTML exactly as publishing would store it.
+            let prepared = prepare_html(&content.html);
+            for message in prepared.errors.iter().chain(&prepared.warnings) {
+                msg_html.push_str(&format!("<p><i>{}</i></p>", message));
+            }
+            if prepared.errors.is_empty() {
+                content.html = prepared.html;
+                let template = match content.template_id {
+                    Some(id) => get_template(&pool, id).await.map_err(e500)?,
+                    None => None,
+                };
+                let issue = content.to_unsaved_issue();
+                for recipient in &recipients {
+                    let sample = Recipient {
+                        email: recipient.inner(),
+                        ..Recipient::sample(&base_url.0)
+                    };
+                    let rendered = match render_issue_email(&issue, template.as_ref(), &sample, &base_url.0) {
+                        Ok(rendered) => rendered,
+                        Err(e) => {
+                            let msg = format!("The template could not be rendered: {}", e);
+                            msg_html.push_str(&format!("<p><i>{}</i></p>", html_escape(&msg)));
+                            break;
+                        }
+                    };
+                    let subject = format!("[TEST] {}", rendered.subject);
+                    let outcome = email_client
+                        .send_email(recipient, &subject, &rendered.html, &rendered.text)
+                        .await;
+                    let msg = match outcome {
+                        Ok(()) => format!("Test issue sent to {}.", recipient.inner()),
+                        Err(e) => {
+                            tracing::error!(
+                                error.cause_chain = ?e,
+                                error.message = %e,
+                                "Failed to send a test issue",
+                            );
+                            format!("Failed to send a test issue to {}.", recipient.inner())
+                        }
+                    };
+                    msg_html.push_str(&format!("<p><i>{}</i></p>", html_escape(&msg)));
+                }
+            }
+        }
+    }
+
+    let templates = get_templates(&pool, Some(TemplateKind::Issue)).await.map_err(e500)?;
+    let values = NewsletterFormValues { title, text, html, markdown, template_id, track_opens, test_recipients };
+    Ok(render_newsletter_form(&msg_html, &values, &templates, &idempotency_key, &csrf_token))
 }
 
 #[tracing::instrument(
     name = "Sending newsletter to confirmed subscribers",
-    skip(body, pool, email_client),
+    skip(body, pool, csrf_token),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
-pub async fn newsletter_delivery(body: web::Form<BodyData>, pool: web::Data<Pool<ConnectionManager<PgConnection>>>, email_client: web::Data<EmailClient>, request: HttpRequest, user_id: web::ReqData<UserId>) -> Result<HttpResponse, PublishError>{
-
-    let BodyData{ title, text, html, idempotency_key } = body.0;
+pub async fn newsletter_delivery(body: web::Form<BodyData>, pool: web::Data<Pool<ConnectionManager<PgConnection>>>, request: HttpRequest, user_id: web::ReqData<UserId>, csrf_token: web::ReqData<CsrfToken>) -> Result<HttpResponse, PublishError>{
+
+    let BodyData{ title, text, html, markdown, template_id, track_opens, idempotency_key } = body.0;
+    let content = IssueContent::parse(title.clone(), text.clone(), html.clone(), markdown.clone(), &template_id, track_opens)
+        .and_then(|content| if content.has_body() {
+            Ok(content)
+        } else {
+            Err("The issue needs either a Markdown body or both plain text and HTML.".to_string())
+        })
+        .map_err(|e| vec![html_escape(&e)])
+        .and_then(|mut content| {
+            let prepared = prepare_html(&content.html);
+            if !prepared.errors.is_empty() {
+                return Err(prepared.errors);
+            }
+            content.html = prepared.html;
+            Ok((content, prepared.warnings))
+        });
+    let (content, warnings) = match content {
+        Ok(prepared) => prepared,
+        // Shown on the form along with what was submitted, so nothing is lost.
+        Err(errors) => {
+            let templates = get_templates(&pool, Some(TemplateKind::Issue)).await?;
+            let values = NewsletterFormValues { title, text, html, markdown, template_id, track_opens, ..Default::default() };
+            let msg_html: String = errors.iter().map(|e| format!("<p><i>{}</i></p>", e)).collect();
+            let mut response = render_newsletter_form(&msg_html, &values, &templates, &idempotency_key, &csrf_token);
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(response);
+        }
+    };
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(PublishError::UnexpectedError)?;
+    let title = content.title.clone();
 
 
     let user_id = user_id.into_inner();
-    tracing::Span::current().record("user_id", &tracing::field::display(&*user_id));
+    tracing::Span::current().record("user_id", tracing::field::display(&*user_id));
 
     let username = get_username(*user_id, &pool).await.map_err(PublishError::UnexpectedError)?;
-    tracing::Span::current().record("username", &tracing::field::display(username));
+    tracing::Span::current().record("username", tracing::field::display(username));
 
     match try_processing(&pool, &idempotency_key, *user_id).await.map_err(PublishError::UnexpectedError)?{
         NextAction::StartProcessing => {},
@@ -75,13 +207,20 @@ pub async fn newsletter_delivery(body: web::Form<BodyData>, pool: web::Data<Pool
 
     insert_issue_and_enqueue_tasks(
         &pool,
-        title,
-        text,
-        html
+        Some(*user_id),
+        content,
+        Some(
+            NewAuditEvent::new(Some(*user_id), AuditAction::NewsletterPublished)
+                .metadata(serde_json::json!({ "title": title }))
+                .ip_address(client_ip(&request))
+        ),
     )
     .await?;
 
     FlashMessage::info("Successfully sent newsletter.").send();
+    for warning in warnings {
+        FlashMessage::warning(warning).send();
+    }
     let response = see_other("/admin/newsletter");
     let response = save_response(&pool, &idempotency_key, *user_id, response)
                     .await
@@ -90,53 +229,38 @@ pub async fn newsletter_delivery(body: web::Form<BodyData>, pool: web::Data<Pool
     Ok(response)
 }
 
-#[tracing::instrument(
-    name = "Get confirmed subscribers",
-    skip(pool)
-)]
-async fn get_confirmed_subscribers(pool: &Pool<ConnectionManager<PgConnection>>) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error>{
-    use crate::schema::subscriptions::dsl::*;
-    
-    let mut conn = pool.get()?;
-    let subscription_vec: Vec<Subscription> = subscriptions
-                        .select((email, name, status))
-                        .filter(status.eq("confirmed"))
-                        .load::<Subscription>(&mut conn)?;
-
-    Ok(
-        subscription_vec
-            .iter()
-            .map(|elem| {
-                match SubscriberEmail::parse(elem.email.clone()){
-                    Ok(e) => Ok(ConfirmedSubscriber{email: e}),
-                    Err(error) => Err(anyhow::anyhow!(error))
-                }
-            })
-            .collect()
-    )
-}
-
+/// Stores an issue and enqueues it for every confirmed subscriber.
+/// `author_id` is `None` for issues nobody wrote by hand, such as those the
+/// feed poller creates. `audit_event` is recorded in the same transaction,
+/// with the new issue as its target.
 #[tracing::instrument(skip_all)] 
 pub async fn insert_issue_and_enqueue_tasks(
     pool: &Pool<ConnectionManager<PgConnection>>,
-    title_val: String,
-    text_content: String,
-    html_content: String,
-) -> Result<(), anyhow::Error> {
+    author_id: Option<Uuid>,
+    content: IssueContent,
+    audit_event: Option<NewAuditEvent>,
+) -> Result<Uuid, anyhow::Error> {
     let mut conn = pool.get()?;
 
     let current_span = tracing::Span::current();
 
-    web::block(move || {
+    let newsletter_issue_id = web::block(move || {
         current_span.in_scope(|| {
             conn.transaction::<_, anyhow::Error, _>(|conn| {
-                let newsletter_issue_id = insert_newsletter_issue(conn, title_val, text_content, html_content)
+                let newsletter_issue_id = insert_newsletter_issue(conn, author_id, content)
                     .context("Failed to store newsletter issue details")?;
 
+                assign_slug(conn, newsletter_issue_id)?;
                 enqueue_delivery_tasks(conn, newsletter_issue_id)
                     .context("Failed to enqueue delivery tasks")?;
-                
-                Ok(())
+
+                mark_issue_as_sent_if_delivered(conn, newsletter_issue_id)?;
+
+                if let Some(event) = audit_event {
+                    insert_audit_event(conn, event.target(newsletter_issue_id))?;
+                }
+
+                Ok(newsletter_issue_id)
             })
             .context("Failed to execute insertion of issue and enqueuing of tasks")
         })
@@ -144,65 +268,97 @@ pub async fn insert_issue_and_enqueue_tasks(
     .await
     .context("Failed due to threadpool error")??;
 
-    Ok(())
+    Ok(newsletter_issue_id)
+}
+
+/// Moves a draft to `sending` with its HTML replaced by `prepared_html`, see
+/// `prepare_html`, and enqueues it for every confirmed subscriber.
+/// `audit_event` is recorded in the same transaction.
+/// Returns `false` if there is no such issue or it is no longer a draft.
+#[tracing::instrument(skip(pool, prepared_html, audit_event))]
+pub async fn publish_draft(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    prepared_html: String,
+    audit_event: NewAuditEvent,
+) -> Result<bool, anyhow::Error> {
+    let mut conn = pool.get()?;
+
+    let current_span = tracing::Span::current();
+
+    let published = web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction::<_, anyhow::Error, _>(|conn| {
+                use crate::schema::newsletter_issues::dsl::*;
+
+                let rows_affected = diesel::update(
+                    newsletter_issues
+                        .filter(newsletter_issue_id.eq(issue_id))
+                        .filter(status.eq(IssueStatus::Draft.as_str()))
+                )
+                .set((
+                    status.eq(IssueStatus::Sending.as_str()),
+                    published_at.eq(Utc::now()),
+                    html.eq(prepared_html),
+                ))
+                .execute(conn)
+                .context("Failed to update newsletter issue status")?;
+
+                if rows_affected == 0 {
+                    return Ok(false);
+                }
+
+                assign_slug(conn, issue_id)?;
+                enqueue_delivery_tasks(conn, issue_id)
+                    .context("Failed to enqueue delivery tasks")?;
+
+                mark_issue_as_sent_if_delivered(conn, issue_id)?;
+                insert_audit_event(conn, audit_event)?;
+
+                Ok(true)
+            })
+            .context("Failed to publish draft issue")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(published)
 }
 
 #[tracing::instrument(skip_all)] 
 fn insert_newsletter_issue(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    title_val: String,
-    text_content: String,
-    html_content: String,
+    author_id: Option<Uuid>,
+    content: IssueContent,
 ) -> Result<Uuid, anyhow::Error>{
     use diesel::prelude::*;
     use crate::schema::newsletter_issues::dsl::*;
 
     let newsletter_issue_id_val = Uuid::new_v4();
+    let subject_test = content.subject_test;
     let issue = NewsletterIssue{
         newsletter_issue_id: newsletter_issue_id_val,
-        title: title_val.clone(),
-        text: text_content.clone(),
-        html: html_content.clone(),
-        published_at: Utc::now().to_string()
+        title: content.title,
+        text: content.text,
+        html: content.html,
+        markdown: content.markdown,
+        published_at: Some(Utc::now()),
+        status: IssueStatus::Sending.as_str().to_string(),
+        created_by: author_id,
+        updated_at: Utc::now(),
+        scheduled_for: None,
+        template_id: content.template_id,
+        track_opens: content.track_opens,
+        slug: None,
+        is_public: content.is_public,
+        schedule_error: None,
     };
 
     diesel::insert_into(newsletter_issues)
         .values(&issue)
         .execute(conn)?;
+    save_subject_test(conn, newsletter_issue_id_val, subject_test.as_ref())?;
 
     Ok(newsletter_issue_id_val)
 }
-
-#[tracing::instrument(skip_all)] 
-fn enqueue_delivery_tasks(
-    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    newsletter_issue_id_val: Uuid
-) -> Result<(), anyhow::Error> {
-    use diesel::prelude::*;
-
-    let confirmed_emails: Vec<String> = {
-        use crate::schema::subscriptions::dsl::*;
-
-        subscriptions.filter(status.eq("confirmed"))
-            .select(email)
-            .load(conn)?
-    };
-
-    let new_entries: Vec<IssueDeliveryQueue> = confirmed_emails
-        .iter()
-        .map(|subscriber_email| IssueDeliveryQueue {
-            newsletter_issue_id: newsletter_issue_id_val,
-            subscriber_email: subscriber_email.to_string(),
-        })
-        .collect();
-
-    {
-        use crate::schema::issue_delivery_queue::dsl::*;
-
-        diesel::insert_into(issue_delivery_queue)
-            .values(&new_entries)
-            .execute(conn)?;
-    }
-
-    Ok(())
-}
@@ -0,0 +1,696 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{archive::slugify, csrf::CsrfToken, email_html::prepare_html, models::{SubjectTest, SubjectVariant}, issues::{get_delivery_progress, get_issue, get_issues, DeliveryProgress, IssueStatus}, rendering::{find_recipient, render_issue_email, Recipient}, reports::{get_issue_report, get_recipient_activity, IssueReport, OPEN_SERIES_HOURS}, routes::admin::templates::{template_select_html, MERGE_TAGS_HELP}, startup::ApplicationBaseUrl, subject_tests::{get_subject_test, get_subject_test_results, WinningMetric, DEFAULT_SAMPLE_PERCENT, DEFAULT_WAIT_MINUTES}, templates::{get_template, get_templates, TemplateKind}, utils::{e400, e500, html_escape, see_other}};
+
+#[derive(Deserialize)]
+pub struct IssuesQuery {
+    status: Option<String>,
+}
+
+pub async fn issues_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    query: web::Query<IssuesQuery>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token.into_inner();
+    let status_filter = match query.status.as_deref() {
+        None | Some("") => None,
+        Some(s) => Some(IssueStatus::try_from(s).map_err(e400)?),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let issues = get_issues(&pool, status_filter).await.map_err(e500)?;
+    let progress = get_delivery_progress(&pool).await.map_err(e500)?;
+
+    // Reload the page while the worker is busy so the counts keep moving.
+    let refresh_html = if issues.iter().any(|i| i.status() == Ok(IssueStatus::Sending)) {
+        r#"<meta http-equiv="refresh" content="10">"#
+    } else {
+        ""
+    };
+
+    let mut filter_html = String::from(r#"<a href="/admin/issues">All</a>"#);
+    for s in IssueStatus::ALL {
+        write!(filter_html, r#" | <a href="/admin/issues?status={0}">{0}</a>"#, s.as_str()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for issue in issues {
+        let status = issue.status().map_err(e500)?;
+        let title = html_escape(&issue.title);
+        let title = if issue.is_editable() {
+            format!(r#"<a href="/admin/issues/{}">{title}</a>"#, issue.newsletter_issue_id)
+        } else {
+            title
+        };
+        let title = match issue.schedule_error {
+            Some(_) => format!("{title}<br><small>Not published at its scheduled time, see the issue for why.</small>"),
+            None => title,
+        };
+        let delivery_html = match (issue.published_at, progress.get(&issue.newsletter_issue_id)) {
+            (Some(_), Some(p)) => format!(
+                "<td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>",
+                p.recipients(),
+                p.sent,
+                p.failed + p.skipped,
+                p.remaining,
+                format_eta(status, p),
+            ),
+            _ => "<td></td><td></td><td></td><td></td><td></td>".to_string(),
+        };
+        let id = issue.newsletter_issue_id;
+        let report_html = if issue.published_at.is_some() {
+            format!(r#" | <a href="/admin/issues/{id}/report">Report</a>"#)
+        } else {
+            String::new()
+        };
+        let action_form = |action: &str, label: &str| format!(
+            r#"<form action="/admin/issues/{id}/{action}" method="post" style="display: inline">
+                <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                <button type="submit">{label}</button>
+            </form>"#
+        );
+        let mut actions_html = match status {
+            IssueStatus::Sending => format!("{}{}", action_form("pause", "Pause"), action_form("cancel", "Cancel")),
+            IssueStatus::Paused => format!("{}{}", action_form("resume", "Resume"), action_form("cancel", "Cancel")),
+            _ => String::new(),
+        };
+        if issue.published_at.is_some() {
+            actions_html.push_str(&if issue.is_public {
+                action_form("make_private", "Make private")
+            } else {
+                action_form("make_public", "Make public")
+            });
+        }
+        let public_html = match (&issue.slug, issue.is_public && issue.published_at.is_some()) {
+            (Some(slug), true) => format!(r#" | <a href="/issues/{}">Public page</a>"#, html_escape(slug)),
+            _ => String::new(),
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{title}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            {delivery_html}
+            <td>{}</td>
+            <td><a href="/admin/issues/{}/preview">Preview</a>{report_html}{public_html}</td>
+            <td>{actions_html}</td>
+        </tr>"#,
+            issue.status,
+            issue.scheduled_for
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default(),
+            issue.published_at
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default(),
+            issue.updated_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            issue.newsletter_issue_id,
+        ).unwrap();
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    {refresh_html}
+    <title>Newsletter issues</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Newsletter issues</h1>
+    <p><a href="/admin/issues/new">New draft</a></p>
+    <p>Show: {filter_html}</p>
+    <table>
+        <tr>
+            <th>Title</th>
+            <th>Status</th>
+            <th>Scheduled for</th>
+            <th>Published</th>
+            <th>Recipients</th>
+            <th>Sent</th>
+            <th>Failed</th>
+            <th>Remaining</th>
+            <th>ETA</th>
+            <th>Last updated</th>
+            <th></th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+fn format_eta(status: IssueStatus, progress: &DeliveryProgress) -> String {
+    match status {
+        IssueStatus::Paused => return "paused".to_string(),
+        IssueStatus::Cancelled => return "cancelled".to_string(),
+        _ if progress.remaining == 0 => return "done".to_string(),
+        _ => {}
+    }
+    match progress.eta() {
+        None => "calculating".to_string(),
+        Some(eta) if eta.num_seconds() < 60 => "under a minute".to_string(),
+        Some(eta) if eta.num_minutes() < 120 => format!("about {} min", eta.num_minutes() + 1),
+        Some(eta) => format!("about {} h", eta.num_hours() + 1),
+    }
+}
+
+pub async fn issue_report(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let report = get_issue_report(&pool, issue.newsletter_issue_id, issue.published_at)
+        .await
+        .map_err(e500)?;
+
+    let id = issue.newsletter_issue_id;
+    let title = html_escape(&issue.title);
+    let published = match issue.published_at {
+        Some(t) => format!("Published {}.", t.format("%Y-%m-%d %H:%M UTC")),
+        None => "This issue has not been published yet.".to_string(),
+    };
+    let IssueReport { recipients, delivered, failed, bounced, unique_opens, unique_clicks, unsubscribes, .. } = report;
+    let opened = percentage(unique_opens, delivered);
+    let clicked = percentage(unique_clicks, delivered);
+    let bounced_share = percentage(bounced, delivered);
+    let unsubscribed_share = percentage(unsubscribes, delivered);
+
+    let mut links_html = String::new();
+    for link in &report.top_links {
+        writeln!(
+            links_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            html_escape(&link.url),
+            link.unique_clicks,
+            link.clicks,
+        ).unwrap();
+    }
+    if links_html.is_empty() {
+        links_html.push_str(r#"<tr><td colspan="3">No clicks yet.</td></tr>"#);
+    }
+
+    let subject_test_html = match get_subject_test_results(&pool, id).await.map_err(e500)? {
+        Some((test, results)) => {
+            let metric = test.metric().map_err(e500)?;
+            let status = match test.decided_at {
+                Some(t) => format!("The winner was picked by {} at {}.", metric.label(), t.format("%Y-%m-%d %H:%M UTC")),
+                None => format!(
+                    "{}% of the list is sent a variant. The winner is picked by {} {} minutes after publishing.",
+                    test.sample_percent,
+                    metric.label(),
+                    test.wait_minutes,
+                ),
+            };
+            let mut rows_html = String::new();
+            for result in &results {
+                let winner = if test.winning_variant_id == Some(result.variant.subject_variant_id) { "Winner" } else { "" };
+                writeln!(
+                    rows_html,
+                    "<tr><td>{}</td><td>{}</td><td>{} ({})</td><td>{} ({})</td><td>{winner}</td></tr>",
+                    html_escape(&result.variant.subject),
+                    result.sent,
+                    result.unique_opens,
+                    percentage(result.unique_opens, result.sent),
+                    result.unique_clicks,
+                    percentage(result.unique_clicks, result.sent),
+                ).unwrap();
+            }
+            format!(r#"<h2>Subject test</h2>
+    <p>{status}</p>
+    <table>
+        <tr>
+            <th>Subject</th>
+            <th>Sample recipients</th>
+            <th>Unique opens</th>
+            <th>Unique clicks</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>"#)
+        }
+        None => String::new(),
+    };
+
+    let busiest = report.opens_by_hour.iter().copied().max().unwrap_or(0).max(1);
+    let mut series_html = String::new();
+    for (hour, opens) in report.opens_by_hour.iter().enumerate() {
+        writeln!(
+            series_html,
+            r#"<tr><td>{hour}-{}</td><td>{opens}</td><td><span style="display: inline-block; height: 10px; width: {}px; background: #369"></span></td></tr>"#,
+            hour + 1,
+            opens * 300 / busiest,
+        ).unwrap();
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Report</title>
+</head>
+<body>
+    <h1>Report: {title}</h1>
+    <p>{published}</p>
+    <p><a href="/admin/issues/{id}/report/export">Download per-recipient CSV</a></p>
+    <table>
+        <tr><th>Recipients</th><td>{recipients}</td></tr>
+        <tr><th>Delivered</th><td>{delivered}</td></tr>
+        <tr><th>Failed</th><td>{failed}</td></tr>
+        <tr><th>Bounced</th><td>{bounced} ({bounced_share})</td></tr>
+        <tr><th>Unique opens</th><td>{unique_opens} ({opened})</td></tr>
+        <tr><th>Unique clicks</th><td>{unique_clicks} ({clicked})</td></tr>
+        <tr><th>Unsubscribes</th><td>{unsubscribes} ({unsubscribed_share})</td></tr>
+    </table>
+    <p><small>Rates are relative to delivered emails. Opens are only counted for issues with open tracking, and only for readers whose mail client loads images. An unsubscribe is attributed to the last issue the subscriber was sent before leaving.</small></p>
+    {subject_test_html}
+    <h2>Top links</h2>
+    <table>
+        <tr>
+            <th>Link</th>
+            <th>Unique clicks</th>
+            <th>Clicks</th>
+        </tr>
+        {links_html}
+    </table>
+    <h2>Opens in the first {OPEN_SERIES_HOURS} hours</h2>
+    <table>
+        <tr>
+            <th>Hours after publishing</th>
+            <th>First opens</th>
+            <th></th>
+        </tr>
+        {series_html}
+    </table>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+fn percentage(part: i64, whole: i64) -> String {
+    if whole == 0 {
+        return "n/a".to_string();
+    }
+    format!("{:.1}%", part as f64 * 100.0 / whole as f64)
+}
+
+pub async fn issue_report_export(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let rows = get_recipient_activity(&pool, issue.newsletter_issue_id).await.map_err(e500)?;
+
+    let timestamp = |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
+    let mut body = String::from("email,delivery,attempted_at,bounced_at,first_opened_at,opens,clicks,unsubscribed\r\n");
+    for row in &rows {
+        write!(
+            body,
+            "{},{},{},{},{},{},{},{}\r\n",
+            csv_field(&row.email),
+            row.delivery,
+            timestamp(row.attempted_at),
+            timestamp(row.bounced_at),
+            timestamp(row.first_opened_at),
+            row.opens,
+            row.clicks,
+            row.unsubscribed,
+        ).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!(r#"attachment; filename="issue-{}-report.csv""#, issue.newsletter_issue_id),
+        ))
+        .body(body))
+}
+
+/// Quotes a value for CSV. Values that a spreadsheet would run as a formula
+/// get a leading apostrophe.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Subject variants of an issue and the settings of the test between them.
+pub fn subject_test_html(test: Option<&(SubjectTest, Vec<SubjectVariant>)>) -> Result<String, String> {
+    let subjects: Vec<&str> = test
+        .map(|(_, variants)| variants.iter().map(|v| v.subject.as_str()).collect())
+        .unwrap_or_default();
+    let (sample_percent, wait_minutes, metric) = match test {
+        Some((test, _)) => (test.sample_percent, test.wait_minutes, test.metric()?),
+        None => (DEFAULT_SAMPLE_PERCENT, DEFAULT_WAIT_MINUTES, WinningMetric::Opens),
+    };
+    let mut metric_options = String::new();
+    for m in WinningMetric::ALL {
+        write!(
+            metric_options,
+            r#"<option value="{}"{}>{}</option>"#,
+            m.as_str(),
+            if m == metric { " selected" } else { "" },
+            m.label(),
+        ).unwrap();
+    }
+    Ok(format!(
+        r#"<label for="subject_variants">Subject variants:</label><br>
+        <textarea id="subject_variants" name="subject_variants" rows="4" cols="80">{}</textarea><br>
+        <small>One subject per line. With two or more, each is sent to an equal share of a sample of the list and the best performing one goes to everybody else. Leave empty to use the title as the subject.</small><br>
+        <label for="sample_percent">Sample size (% of the list):</label>
+        <input type="number" id="sample_percent" name="sample_percent" min="1" max="100" value="{sample_percent}"><br>
+        <label for="wait_minutes">Wait before picking the winner (minutes):</label>
+        <input type="number" id="wait_minutes" name="wait_minutes" min="0" value="{wait_minutes}"><br>
+        <label for="winning_metric">Pick the winner by:</label>
+        <select id="winning_metric" name="winning_metric">{metric_options}</select><br><br>"#,
+        html_escape(&subjects.join("\n")),
+    ))
+}
+
+/// Checkbox listing an issue in the public archive once it is published.
+pub fn is_public_html(checked: bool) -> String {
+    format!(
+        r#"<input type="checkbox" id="is_public" name="is_public" value="true"{}>
+        <label for="is_public">Public</label><br>
+        <small>Lists the issue at /issues once it is published, and adds a "View in browser" link to the emails.</small><br><br>"#,
+        if checked { " checked" } else { "" }
+    )
+}
+
+/// Checkbox opting an issue into the open-tracking pixel.
+pub fn track_opens_html(checked: bool) -> String {
+    format!(
+        r#"<input type="checkbox" id="track_opens" name="track_opens" value="true"{}>
+        <label for="track_opens">Track opens</label><br>
+        <small>Adds an invisible image to the HTML part when open tracking is enabled in the configuration.</small><br><br>"#,
+        if checked { " checked" } else { "" }
+    )
+}
+
+pub async fn new_issue_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_token = csrf_token.into_inner();
+    let templates = get_templates(&pool, Some(TemplateKind::Issue)).await.map_err(e500)?;
+    let template_select = template_select_html(&templates, None);
+    let track_opens = track_opens_html(false);
+    let is_public = is_public_html(false);
+    let subject_test = subject_test_html(None).map_err(e500)?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>New draft</title>
+</head>
+<body>
+    {msg_html}
+    <h1>New draft</h1>
+    <form action="/admin/issues" method="post">
+        <label for="title">Title:</label><br>
+        <input type="text" id="title" name="title" required><br><br>
+
+        {template_select}
+
+        <label for="markdown">Markdown:</label><br>
+        <textarea id="markdown" name="markdown" rows="15" cols="80"></textarea><br>
+        <small>When Markdown is provided, the plain text and HTML below are generated from it.</small><br>
+        <small>{MERGE_TAGS_HELP}</small><br><br>
+
+        <label for="text">Plain text:</label><br>
+        <textarea id="text" name="text" rows="15" cols="80"></textarea><br><br>
+
+        <label for="html">HTML:</label><br>
+        <textarea id="html" name="html" rows="15" cols="80"></textarea><br><br>
+
+        {track_opens}
+
+        {is_public}
+
+        {subject_test}
+
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Save draft</button>
+    </form>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+pub async fn edit_issue_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if !issue.is_editable() {
+        FlashMessage::error("This issue has already been published.").send();
+        return Ok(see_other("/admin/issues"));
+    }
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_token = csrf_token.into_inner();
+    let idempotency_key = Uuid::new_v4();
+    let id = issue.newsletter_issue_id;
+    let title = html_escape(&issue.title);
+    let text = html_escape(&issue.text);
+    let html = html_escape(&issue.html);
+    let markdown = html_escape(issue.markdown.as_deref().unwrap_or_default());
+    let templates = get_templates(&pool, Some(TemplateKind::Issue)).await.map_err(e500)?;
+    let template_select = template_select_html(&templates, issue.template_id);
+    let track_opens = track_opens_html(issue.track_opens);
+    let is_public = is_public_html(issue.is_public);
+    let subject_test = get_subject_test(&pool, id).await.map_err(e500)?;
+    let subject_test = subject_test_html(subject_test.as_ref()).map_err(e500)?;
+
+    let schedule_error_html = match &issue.schedule_error {
+        Some(reason) => format!(
+            "<p><strong>This issue was not published at its scheduled time.</strong> {}</p>",
+            html_escape(reason)
+        ),
+        None => String::new(),
+    };
+
+    let status = issue.status().map_err(e500)?;
+    let actions_html = match issue.scheduled_for {
+        Some(scheduled_for) if status == IssueStatus::Scheduled => format!(r#"
+    <p>Scheduled for {}.</p>
+    <form action="/admin/issues/{id}/schedule" method="post">
+        <label for="scheduled_for">Reschedule to (UTC):</label>
+        <input type="datetime-local" id="scheduled_for" name="scheduled_for" value="{}" required>
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Reschedule</button>
+    </form>
+    <form action="/admin/issues/{id}/unschedule" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Cancel schedule</button>
+    </form>"#,
+            scheduled_for.format("%Y-%m-%d %H:%M UTC"),
+            scheduled_for.format("%Y-%m-%dT%H:%M"),
+        ),
+        _ => format!(r#"
+    <form action="/admin/issues/{id}/schedule" method="post">
+        <label for="scheduled_for">Publish at (UTC):</label>
+        <input type="datetime-local" id="scheduled_for" name="scheduled_for" required>
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Schedule</button>
+    </form>
+    <form action="/admin/issues/{id}/publish" method="post">
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Publish now</button>
+    </form>
+    <form action="/admin/issues/{id}/delete" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Delete draft</button>
+    </form>"#),
+    };
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit issue</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Edit issue</h1>
+    {schedule_error_html}
+    <form action="/admin/issues/{id}" method="post">
+        <label for="title">Title:</label><br>
+        <input type="text" id="title" name="title" value="{title}" required><br><br>
+
+        {template_select}
+
+        <label for="markdown">Markdown:</label><br>
+        <textarea id="markdown" name="markdown" rows="15" cols="80">{markdown}</textarea><br>
+        <small>When Markdown is provided, the plain text and HTML below are generated from it.</small><br>
+        <small>{MERGE_TAGS_HELP}</small><br><br>
+
+        <label for="text">Plain text:</label><br>
+        <textarea id="text" name="text" rows="15" cols="80">{text}</textarea><br><br>
+
+        <label for="html">HTML:</label><br>
+        <textarea id="html" name="html" rows="15" cols="80">{html}</textarea><br><br>
+
+        {track_opens}
+
+        {is_public}
+
+        {subject_test}
+
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/issues/{id}/preview">Preview</a></p>
+    {actions_html}
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+#[derive(Deserialize)]
+pub struct PreviewQuery {
+    subscriber: Option<String>,
+}
+
+pub async fn preview_issue(
+    query: web::Query<PreviewQuery>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let template = match issue.template_id {
+        Some(template_id) => get_template(&pool, template_id).await.map_err(e500)?,
+        None => None,
+    };
+
+    let mut msg_html = String::new();
+    // Published issues are stored as sent. Drafts get what publishing them
+    // would do: the HTML prepared and, for public ones, the slug they will
+    // most likely get.
+    if issue.published_at.is_none() {
+        let prepared = prepare_html(&issue.html);
+        for message in prepared.errors.iter().chain(&prepared.warnings) {
+            writeln!(msg_html, "<p><i>{}</i></p>", message).unwrap();
+        }
+        issue.html = prepared.html;
+        if issue.slug.is_none() {
+            issue.slug = Some(slugify(&issue.title));
+        }
+    }
+
+    let base_url = &base_url.0;
+    let chosen = query
+        .subscriber
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    let recipient = match chosen {
+        Some(email) => match find_recipient(&pool, email.to_string(), base_url.clone()).await.map_err(e500)? {
+            Some(recipient) => recipient,
+            None => {
+                writeln!(
+                    msg_html,
+                    "<p><i>There is no subscriber with the address {}. Showing a sample subscriber instead.</i></p>",
+                    html_escape(email)
+                ).unwrap();
+                Recipient::sample(base_url)
+            }
+        },
+        None => Recipient::sample(base_url),
+    };
+
+    let rendered = match render_issue_email(&issue, template.as_ref(), &recipient, base_url) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            writeln!(
+                msg_html,
+                "<p><i>The template could not be rendered: {}</i></p>",
+                html_escape(&e.to_string())
+            ).unwrap();
+            render_issue_email(&issue, None, &recipient, base_url).map_err(e500)?
+        }
+    };
+    let id = issue.newsletter_issue_id;
+    let subject = html_escape(&rendered.subject);
+    let recipient_email = html_escape(&recipient.email);
+    let recipient_name = html_escape(&recipient.name);
+    // The issue HTML is untrusted as far as the admin UI is concerned, so it is
+    // shown in a sandboxed iframe with no scripts, forms or same-origin access.
+    let html = html_escape(&rendered.html);
+    let text = html_escape(&rendered.text);
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Preview</h1>
+    <form action="/admin/issues/{id}/preview" method="get">
+        <label for="subscriber">Preview as subscriber:</label>
+        <input type="email" id="subscriber" name="subscriber" value="{recipient_email}">
+        <button type="submit">Preview</button>
+    </form>
+    <p>To: {recipient_name} &lt;{recipient_email}&gt;</p>
+    <p>Subject: {subject}</p>
+    <h2>HTML</h2>
+    <iframe sandbox="" srcdoc="{html}" width="100%" height="600"></iframe>
+    <h2>Plain text</h2>
+    <pre>{text}</pre>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
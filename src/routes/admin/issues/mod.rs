@@ -0,0 +1,4 @@
+mod get;
+pub use get::{edit_issue_form, issue_report, issue_report_export, issues_page, new_issue_form, preview_issue, track_opens_html};
+mod post;
+pub use post::{cancel_issue, create_issue, delete_issue, make_issue_private, make_issue_public, pause_issue, publish_issue, resume_issue, schedule_issue, unschedule_issue, update_issue};
@@ -0,0 +1,381 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use actix_web_flash_messages::FlashMessage;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{archive::set_issue_public, audit::{record_audit_event, AuditAction, NewAuditEvent}, email_html::prepare_html, idempotency::{persistence::{save_response, try_processing, NextAction}, IdempotencyKey}, issues::{self, get_issue, IssueContent}, rendering::check_merge_tags, routes::admin::delivery::post::{publish_draft, PublishError}, session_state::UserId, subject_tests::SubjectTestContent, utils::{client_ip, e500, html_escape, parse_datetime, see_other}};
+
+#[derive(Deserialize)]
+pub struct IssueFormData {
+    title: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    html: String,
+    #[serde(default)]
+    markdown: String,
+    #[serde(default)]
+    template_id: String,
+    #[serde(default)]
+    track_opens: bool,
+    #[serde(default)]
+    is_public: bool,
+    #[serde(default)]
+    subject_variants: String,
+    #[serde(default)]
+    sample_percent: String,
+    #[serde(default)]
+    wait_minutes: String,
+    #[serde(default)]
+    winning_metric: String,
+}
+
+impl TryFrom<IssueFormData> for IssueContent {
+    type Error = String;
+    fn try_from(form: IssueFormData) -> Result<Self, Self::Error> {
+        let mut content = IssueContent::parse(form.title, form.text, form.html, form.markdown, &form.template_id, form.track_opens)?;
+        content.is_public = form.is_public;
+        content.subject_test = SubjectTestContent::parse(
+            &form.subject_variants,
+            &form.sample_percent,
+            &form.wait_minutes,
+            &form.winning_metric,
+            content.track_opens,
+        )?;
+        Ok(content)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleFormData {
+    scheduled_for: String,
+}
+
+#[derive(Deserialize)]
+pub struct PublishFormData {
+    idempotency_key: String,
+}
+
+#[tracing::instrument("Create a draft issue", skip(form, pool, user_id))]
+pub async fn create_issue(
+    form: web::Form<IssueFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let content = match IssueContent::try_from(form.0) {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/issues/new"));
+        }
+    };
+
+    let issue_id = issues::create_draft(&pool, Some(**user_id), content)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+#[tracing::instrument("Update a draft issue", skip(form, pool))]
+pub async fn update_issue(
+    form: web::Form<IssueFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let content = match IssueContent::try_from(form.0) {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+        }
+    };
+
+    if issues::update_draft(&pool, issue_id, content).await.map_err(e500)? {
+        FlashMessage::info("The issue has been saved.").send();
+        Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+    } else {
+        FlashMessage::error("This issue has already been published.").send();
+        Ok(see_other("/admin/issues"))
+    }
+}
+
+#[tracing::instrument("Delete a draft issue", skip(pool))]
+pub async fn delete_issue(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if issues::delete_draft(&pool, *issue_id).await.map_err(e500)? {
+        FlashMessage::info("The draft has been deleted.").send();
+    } else {
+        FlashMessage::error("Only drafts can be deleted.").send();
+    }
+    Ok(see_other("/admin/issues"))
+}
+
+#[tracing::instrument("Schedule an issue", skip(form, pool))]
+pub async fn schedule_issue(
+    form: web::Form<ScheduleFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue_page = format!("/admin/issues/{}", issue_id);
+
+    let publish_at = match parse_datetime(form.scheduled_for.trim()) {
+        Ok(t) => t,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&issue_page));
+        }
+    };
+    if publish_at <= Utc::now() {
+        FlashMessage::error("The publish time must be in the future.").send();
+        return Ok(see_other(&issue_page));
+    }
+
+    // The HTML is prepared again when the scheduler publishes the issue; this
+    // is so the editor hears about problems while there is time to fix them.
+    let warnings = match get_issue(&pool, issue_id).await.map_err(e500)? {
+        Some(issue) => {
+            let prepared = prepare_html(&issue.html);
+            if !prepared.errors.is_empty() {
+                for error in prepared.errors {
+                    FlashMessage::error(error).send();
+                }
+                return Ok(see_other(&issue_page));
+            }
+            prepared.warnings
+        }
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    if issues::schedule_issue(&pool, issue_id, publish_at).await.map_err(e500)? {
+        FlashMessage::info(format!(
+            "The issue will be published at {}.",
+            publish_at.format("%Y-%m-%d %H:%M UTC")
+        ))
+        .send();
+        for warning in warnings {
+            FlashMessage::warning(warning).send();
+        }
+        Ok(see_other(&issue_page))
+    } else {
+        FlashMessage::error("This issue has already been published.").send();
+        Ok(see_other("/admin/issues"))
+    }
+}
+
+#[tracing::instrument("Unschedule an issue", skip(pool))]
+pub async fn unschedule_issue(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+
+    if issues::unschedule_issue(&pool, issue_id).await.map_err(e500)? {
+        FlashMessage::info("The schedule has been cancelled. The issue is a draft again.").send();
+        Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+    } else {
+        FlashMessage::error("This issue is not scheduled.").send();
+        Ok(see_other("/admin/issues"))
+    }
+}
+
+#[tracing::instrument(
+    "Publish a draft issue",
+    skip(form, pool, user_id, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn publish_issue(
+    form: web::Form<PublishFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let issue_id = issue_id.into_inner();
+    let user_id = user_id.into_inner();
+    tracing::Span::current().record("user_id", tracing::field::display(&*user_id));
+
+    let idempotency_key: IdempotencyKey = form.0.idempotency_key.try_into().map_err(PublishError::UnexpectedError)?;
+
+    let issue = match get_issue(&pool, issue_id).await? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    // Drafts saved before merge tags were checked may still contain broken ones.
+    if let Err(e) = check_merge_tags(&issue.title, &issue.html, &issue.text) {
+        FlashMessage::error(html_escape(&e)).send();
+        return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+    }
+    let prepared = prepare_html(&issue.html);
+    if !prepared.errors.is_empty() {
+        for error in prepared.errors {
+            FlashMessage::error(error).send();
+        }
+        return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+    }
+
+    match try_processing(&pool, &idempotency_key, *user_id).await.map_err(PublishError::UnexpectedError)? {
+        NextAction::StartProcessing => {},
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    }
+
+    let audit_event = NewAuditEvent::new(Some(*user_id), AuditAction::NewsletterPublished)
+        .target(issue_id)
+        .metadata(serde_json::json!({ "title": issue.title }))
+        .ip_address(client_ip(&request));
+    if publish_draft(&pool, issue_id, prepared.html, audit_event).await? {
+        FlashMessage::info("The issue has been published.").send();
+        for warning in prepared.warnings {
+            FlashMessage::warning(warning).send();
+        }
+    } else {
+        FlashMessage::error("Only drafts can be published.").send();
+    }
+
+    let response = see_other("/admin/issues");
+    let response = save_response(&pool, &idempotency_key, *user_id, response)
+                    .await
+                    .map_err(PublishError::UnexpectedError)?;
+
+    Ok(response)
+}
+
+#[tracing::instrument("Pause a sending issue", skip(pool, user_id, request))]
+pub async fn pause_issue(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+
+    if issues::pause_issue(&pool, issue_id).await.map_err(e500)? {
+        record_audit_event(
+            &pool,
+            NewAuditEvent::new(Some(**user_id), AuditAction::NewsletterPaused)
+                .target(issue_id)
+                .ip_address(client_ip(&request))
+        )
+        .await
+        .map_err(e500)?;
+        FlashMessage::info("The issue has been paused. No more emails will go out until it is resumed.").send();
+    } else {
+        FlashMessage::error("Only issues that are being sent can be paused.").send();
+    }
+    Ok(see_other("/admin/issues"))
+}
+
+#[tracing::instrument("Resume a paused issue", skip(pool, user_id, request))]
+pub async fn resume_issue(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+
+    if issues::resume_issue(&pool, issue_id).await.map_err(e500)? {
+        record_audit_event(
+            &pool,
+            NewAuditEvent::new(Some(**user_id), AuditAction::NewsletterResumed)
+                .target(issue_id)
+                .ip_address(client_ip(&request))
+        )
+        .await
+        .map_err(e500)?;
+        FlashMessage::info("The issue has been resumed.").send();
+    } else {
+        FlashMessage::error("Only paused issues can be resumed.").send();
+    }
+    Ok(see_other("/admin/issues"))
+}
+
+#[tracing::instrument("Cancel a sending issue", skip(pool, user_id, request))]
+pub async fn cancel_issue(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+
+    match issues::cancel_issue(&pool, issue_id).await.map_err(e500)? {
+        Some(sent) => {
+            record_audit_event(
+                &pool,
+                NewAuditEvent::new(Some(**user_id), AuditAction::NewsletterCancelled)
+                    .target(issue_id)
+                    .metadata(serde_json::json!({ "already_sent": sent }))
+                    .ip_address(client_ip(&request))
+            )
+            .await
+            .map_err(e500)?;
+            FlashMessage::info(format!(
+                "The issue has been cancelled. {} {} already received it.",
+                sent,
+                if sent == 1 { "recipient had" } else { "recipients had" },
+            ))
+            .send();
+        }
+        None => {
+            FlashMessage::error("Only issues that are being sent can be cancelled.").send();
+        }
+    }
+    Ok(see_other("/admin/issues"))
+}
+
+#[tracing::instrument("Add an issue to the public archive", skip(pool, user_id, request))]
+pub async fn make_issue_public(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_visibility(&pool, issue_id.into_inner(), true, **user_id, &request).await
+}
+
+#[tracing::instrument("Remove an issue from the public archive", skip(pool, user_id, request))]
+pub async fn make_issue_private(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_visibility(&pool, issue_id.into_inner(), false, **user_id, &request).await
+}
+
+async fn change_visibility(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    public: bool,
+    user_id: Uuid,
+    request: &HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    if set_issue_public(pool, issue_id, public).await.map_err(e500)? {
+        record_audit_event(
+            pool,
+            NewAuditEvent::new(Some(user_id), AuditAction::NewsletterVisibilityChanged)
+                .target(issue_id)
+                .metadata(serde_json::json!({ "public": public }))
+                .ip_address(client_ip(request))
+        )
+        .await
+        .map_err(e500)?;
+        if public {
+            FlashMessage::info("The issue is now in the public archive.").send();
+        } else {
+            FlashMessage::info("The issue has been removed from the public archive.").send();
+        }
+    } else {
+        FlashMessage::error("Only published issues can be added to the public archive. Use the checkbox on drafts.").send();
+    }
+    Ok(see_other("/admin/issues"))
+}
@@ -1,9 +1,30 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
 
-use crate::{session_state::TypedSession, utils::see_other};
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, session_registry::revoke_session, session_state::{SessionId, TypedSession, UserId}, utils::{client_ip, e500, see_other}};
+
+pub async fn log_out(
+    session: TypedSession,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<SessionId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    revoke_session(&pool, **user_id, **session_id)
+        .await
+        .map_err(e500)?;
+
+    record_audit_event(
+        &pool,
+        NewAuditEvent::new(Some(**user_id), AuditAction::Logout)
+            .target(**session_id)
+            .ip_address(client_ip(&request))
+    )
+    .await
+    .map_err(e500)?;
 
-pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
     session.log_out();
     FlashMessage::info("You have successfully logged out.").send();
     Ok(see_other("/login"))
@@ -3,5 +3,17 @@ pub use dashboard::admin_dashboard;
 mod password;
 pub use password::*;
 pub mod logout;
+mod sessions;
+pub use sessions::*;
+mod audit;
+pub use audit::*;
+mod issues;
+pub use issues::*;
+mod templates;
+pub use templates::*;
+mod suppressions;
+pub use suppressions::*;
+mod sequence;
+pub use sequence::*;
 pub mod delivery;
 pub use delivery::*;
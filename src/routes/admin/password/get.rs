@@ -1,10 +1,13 @@
 use std::fmt::Write;
 
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
 
-pub async fn change_password_form(flash_messages: IncomingFlashMessages) -> Result<HttpResponse, actix_web::Error>{
+use crate::csrf::CsrfToken;
+
+pub async fn change_password_form(flash_messages: IncomingFlashMessages, csrf_token: web::ReqData<CsrfToken>) -> Result<HttpResponse, actix_web::Error>{
+    let csrf_token = csrf_token.into_inner();
 
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
@@ -46,6 +49,7 @@ pub async fn change_password_form(flash_messages: IncomingFlashMessages) -> Resu
             >
         </label>
         <br>
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
         <button type="submit">Change password</button>
     </form>
     <p><a href="/admin/dashboard">&lt;- Back</a></p>
@@ -1,11 +1,11 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use diesel::{r2d2::ConnectionManager, PgConnection};
 use r2d2::Pool;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 
-use crate::{authentication::{validate_credentials, AuthError, Credentials}, routes::admin::dashboard::get_username, session_state::UserId, utils::{e500, see_other}};
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, authentication::{validate_credentials, AuthError, Credentials}, configuration::PasswordHashSettings, routes::admin::dashboard::get_username, session_state::UserId, utils::{client_ip, e500, see_other}};
 
 #[derive(Deserialize)]
 pub struct FormData{
@@ -16,9 +16,9 @@ pub struct FormData{
 
 #[tracing::instrument(
     "Change current password",
-    skip(form, pool, user_id)
+    skip(form, pool, hash_settings, user_id, request)
 )]
-pub async fn change_password(form: web::Form<FormData>, pool: web::Data<Pool<ConnectionManager<PgConnection>>>, user_id: web::ReqData<UserId>) -> Result<HttpResponse, actix_web::Error> {
+pub async fn change_password(form: web::Form<FormData>, pool: web::Data<Pool<ConnectionManager<PgConnection>>>, hash_settings: web::Data<PasswordHashSettings>, user_id: web::ReqData<UserId>, request: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
     if form.new_password.expose_secret() != form.new_password_check.expose_secret(){
@@ -34,21 +34,30 @@ pub async fn change_password(form: web::Form<FormData>, pool: web::Data<Pool<Con
         password: form.0.current_password
     };
 
-    if let Err(e) = validate_credentials(credentials, &pool).await{
+    if let Err(e) = validate_credentials(credentials, &pool, &hash_settings).await{
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
                 Ok(see_other("/admin/password"))
             },
 
-            AuthError::UnexpectedError(_) => Err(e500(e).into())
+            AuthError::UnexpectedError(_) => Err(e500(e))
         }
     }
 
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    crate::authentication::change_password(*user_id, form.0.new_password, &pool, &hash_settings)
         .await
         .map_err(e500)?;
 
+    record_audit_event(
+        &pool,
+        NewAuditEvent::new(Some(*user_id), AuditAction::PasswordChanged)
+            .target(*user_id)
+            .ip_address(client_ip(&request))
+    )
+    .await
+    .map_err(e500)?;
+
     FlashMessage::info("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
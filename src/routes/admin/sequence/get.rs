@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{csrf::CsrfToken, routes::admin::templates::MERGE_TAGS_HELP, utils::{e500, html_escape}, welcome_sequence::{get_sent_counts, get_sequence_step, get_sequence_steps}};
+
+pub async fn sequence_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_token = csrf_token.into_inner();
+
+    let steps = get_sequence_steps(&pool).await.map_err(e500)?;
+    let sent_counts = get_sent_counts(&pool).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for step in &steps {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>Day {}</td>
+            <td><a href="/admin/sequence/{}">{}</a></td>
+            <td>{}</td>
+        </tr>"#,
+            step.delay_days,
+            step.sequence_step_id,
+            html_escape(&step.subject),
+            sent_counts.get(&step.sequence_step_id).copied().unwrap_or_default(),
+        ).unwrap();
+    }
+    if steps.is_empty() {
+        rows_html.push_str(r#"<tr><td colspan="3">There are no steps, so new subscribers get no welcome emails.</td></tr>"#);
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Welcome sequence</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Welcome sequence</h1>
+    <p>Subscribers receive these emails the given number of days after confirming, until they unsubscribe.</p>
+    <table>
+        <tr>
+            <th>Sent after</th>
+            <th>Subject</th>
+            <th>Sent</th>
+        </tr>
+        {rows_html}
+    </table>
+    <h2>Add a step</h2>
+    <p>{MERGE_TAGS_HELP}</p>
+    <p>Steps are sent without a template, so both bodies need their own <code>{{{{unsubscribe_url}}}}</code> link.</p>
+    <form action="/admin/sequence" method="post">
+        <label for="delay_days">Days after confirming:</label><br>
+        <input type="number" id="delay_days" name="delay_days" min="0" value="0" required><br><br>
+
+        <label for="subject">Subject:</label><br>
+        <input type="text" id="subject" name="subject" size="80" required><br><br>
+
+        <label for="html">HTML:</label><br>
+        <textarea id="html" name="html" rows="15" cols="80"></textarea><br><br>
+
+        <label for="text">Plain text:</label><br>
+        <textarea id="text" name="text" rows="15" cols="80"></textarea><br><br>
+
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Add step</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+pub async fn edit_sequence_step_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    step_id: web::Path<Uuid>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let step = match get_sequence_step(&pool, *step_id).await.map_err(e500)? {
+        Some(step) => step,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_token = csrf_token.into_inner();
+    let id = step.sequence_step_id;
+    let delay_days = step.delay_days;
+    let subject = html_escape(&step.subject);
+    let html = html_escape(&step.html);
+    let text = html_escape(&step.text);
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit step</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Edit step</h1>
+    <p>Changes apply to subscribers who have not received this step yet. It is not sent again to anyone who has.</p>
+    <p>{MERGE_TAGS_HELP}</p>
+    <p>Steps are sent without a template, so both bodies need their own <code>{{{{unsubscribe_url}}}}</code> link.</p>
+    <form action="/admin/sequence/{id}" method="post">
+        <label for="delay_days">Days after confirming:</label><br>
+        <input type="number" id="delay_days" name="delay_days" min="0" value="{delay_days}" required><br><br>
+
+        <label for="subject">Subject:</label><br>
+        <input type="text" id="subject" name="subject" value="{subject}" size="80" required><br><br>
+
+        <label for="html">HTML:</label><br>
+        <textarea id="html" name="html" rows="15" cols="80">{html}</textarea><br><br>
+
+        <label for="text">Plain text:</label><br>
+        <textarea id="text" name="text" rows="15" cols="80">{text}</textarea><br><br>
+
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Save</button>
+    </form>
+    <form action="/admin/sequence/{id}/delete" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Delete step</button>
+    </form>
+    <p><a href="/admin/sequence">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
@@ -0,0 +1,4 @@
+mod get;
+pub use get::{edit_sequence_step_form, sequence_page};
+mod post;
+pub use post::{create_sequence_step, delete_sequence_step, update_sequence_step};
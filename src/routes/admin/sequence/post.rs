@@ -0,0 +1,109 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, session_state::UserId, utils::{client_ip, e500, html_escape, see_other}, welcome_sequence::{self, SequenceStepContent}};
+
+#[derive(Deserialize)]
+pub struct SequenceStepFormData {
+    delay_days: String,
+    subject: String,
+    #[serde(default)]
+    html: String,
+    #[serde(default)]
+    text: String,
+}
+
+impl SequenceStepFormData {
+    fn parse(self) -> Result<SequenceStepContent, String> {
+        SequenceStepContent::parse(&self.delay_days, self.subject, self.html, self.text)
+    }
+}
+
+#[tracing::instrument("Create a sequence step", skip(form, pool, user_id, request))]
+pub async fn create_sequence_step(
+    form: web::Form<SequenceStepFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let content = match form.0.parse() {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(html_escape(&e)).send();
+            return Ok(see_other("/admin/sequence"));
+        }
+    };
+
+    let step_id = welcome_sequence::create_sequence_step(&pool, content)
+        .await
+        .map_err(e500)?;
+    record_step_change(&pool, step_id, "created", **user_id, &request).await?;
+
+    FlashMessage::info("The step has been added.").send();
+    Ok(see_other("/admin/sequence"))
+}
+
+#[tracing::instrument("Update a sequence step", skip(form, pool, user_id, request))]
+pub async fn update_sequence_step(
+    form: web::Form<SequenceStepFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    step_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let step_id = step_id.into_inner();
+    let step_page = format!("/admin/sequence/{}", step_id);
+    let content = match form.0.parse() {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(html_escape(&e)).send();
+            return Ok(see_other(&step_page));
+        }
+    };
+
+    if welcome_sequence::update_sequence_step(&pool, step_id, content).await.map_err(e500)? {
+        record_step_change(&pool, step_id, "updated", **user_id, &request).await?;
+        FlashMessage::info("The step has been saved.").send();
+        Ok(see_other(&step_page))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+#[tracing::instrument("Delete a sequence step", skip(pool, user_id, request))]
+pub async fn delete_sequence_step(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    step_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    if welcome_sequence::delete_sequence_step(&pool, *step_id).await.map_err(e500)? {
+        record_step_change(&pool, *step_id, "deleted", **user_id, &request).await?;
+        FlashMessage::info("The step has been deleted.").send();
+        Ok(see_other("/admin/sequence"))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+async fn record_step_change(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    step_id: Uuid,
+    change: &str,
+    user_id: Uuid,
+    request: &HttpRequest,
+) -> Result<(), actix_web::Error> {
+    record_audit_event(
+        pool,
+        NewAuditEvent::new(Some(user_id), AuditAction::ConfigChanged)
+            .target(step_id)
+            .metadata(serde_json::json!({ "setting": "welcome_sequence_step", "change": change }))
+            .ip_address(client_ip(request))
+    )
+    .await
+    .map_err(e500)
+}
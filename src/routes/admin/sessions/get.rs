@@ -0,0 +1,79 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+
+use crate::{csrf::CsrfToken, session_registry::get_active_sessions, session_state::{SessionId, UserId}, utils::{e500, html_escape}};
+
+pub async fn sessions_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<SessionId>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token.into_inner();
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let sessions = get_active_sessions(&pool, **user_id).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for s in sessions {
+        let current = if s.session_id == **session_id { " (this device)" } else { "" };
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{}{current}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>
+                <form action="/admin/sessions/revoke" method="post">
+                    <input hidden type="text" name="session_id" value="{}">
+                    <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                    <button type="submit">Revoke</button>
+                </form>
+            </td>
+        </tr>"#,
+            html_escape(s.user_agent.as_deref().unwrap_or("Unknown device")),
+            html_escape(s.ip_address.as_deref().unwrap_or("Unknown")),
+            s.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            s.last_seen_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            s.session_id,
+        ).unwrap();
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Active sessions</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Active sessions</h1>
+    <table>
+        <tr>
+            <th>Device</th>
+            <th>IP address</th>
+            <th>Signed in</th>
+            <th>Last seen</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/sessions/revoke_all" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Log out everywhere</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
@@ -0,0 +1,82 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, session_registry, session_state::{SessionId, TypedSession, UserId}, utils::{client_ip, e500, see_other}};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    session_id: Uuid,
+}
+
+#[tracing::instrument(
+    "Revoke a session",
+    skip(form, pool, session, user_id, session_id, request)
+)]
+pub async fn revoke_session(
+    form: web::Form<FormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    session_id: web::ReqData<SessionId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = session_registry::revoke_session(&pool, **user_id, form.session_id)
+        .await
+        .map_err(e500)?;
+
+    if revoked {
+        record_audit_event(
+            &pool,
+            NewAuditEvent::new(Some(**user_id), AuditAction::SessionRevoked)
+                .target(form.session_id)
+                .ip_address(client_ip(&request))
+        )
+        .await
+        .map_err(e500)?;
+    }
+
+    if form.session_id == **session_id {
+        session.log_out();
+        FlashMessage::info("You have successfully logged out.").send();
+        return Ok(see_other("/login"));
+    }
+
+    if revoked {
+        FlashMessage::info("The session has been revoked.").send();
+    } else {
+        FlashMessage::error("The session does not exist or has already been revoked.").send();
+    }
+
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(
+    "Revoke all sessions",
+    skip(pool, session, user_id, request)
+)]
+pub async fn revoke_all_sessions(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    session_registry::revoke_all_sessions(&pool, **user_id)
+        .await
+        .map_err(e500)?;
+
+    record_audit_event(
+        &pool,
+        NewAuditEvent::new(Some(**user_id), AuditAction::AllSessionsRevoked)
+            .ip_address(client_ip(&request))
+    )
+    .await
+    .map_err(e500)?;
+
+    session.log_out();
+    FlashMessage::info("You have been logged out on every device.").send();
+    Ok(see_other("/login"))
+}
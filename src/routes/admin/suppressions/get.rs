@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+
+use crate::{csrf::CsrfToken, suppressions::get_suppressions, utils::{e500, html_escape}};
+
+pub async fn suppressions_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = csrf_token.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let suppressions = get_suppressions(&pool).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for suppression in suppressions {
+        let email = html_escape(&suppression.email);
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{email}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>
+                <form action="/admin/suppressions/remove" method="post">
+                    <input hidden type="text" name="email" value="{email}">
+                    <input hidden type="text" name="csrf_token" value="{csrf_token}">
+                    <button type="submit">Remove</button>
+                </form>
+            </td>
+        </tr>"#,
+            suppression.reason,
+            html_escape(&suppression.source),
+            suppression.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        ).unwrap();
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Suppression list</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Suppression list</h1>
+    <p>Nothing is sent to these addresses, and they cannot sign up again until they are removed. Hard bounces, spam complaints and unsubscribes are added automatically.</p>
+    <form action="/admin/suppressions" method="post">
+        <label for="email">Suppress address:</label>
+        <input type="email" id="email" name="email" required>
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Add</button>
+    </form>
+    <table>
+        <tr>
+            <th>Email</th>
+            <th>Reason</th>
+            <th>Source</th>
+            <th>Added</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
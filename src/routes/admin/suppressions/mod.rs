@@ -0,0 +1,4 @@
+mod get;
+pub use get::suppressions_page;
+mod post;
+pub use post::{add_suppression, remove_suppression};
@@ -0,0 +1,71 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, domain::subscriber_email::SubscriberEmail, session_state::UserId, suppressions::{self, SuppressionReason}, utils::{client_ip, e500, html_escape, see_other}};
+
+#[derive(Deserialize)]
+pub struct SuppressionFormData {
+    email: String,
+}
+
+#[tracing::instrument("Add a suppression", skip(form, pool, user_id, request))]
+pub async fn add_suppression(
+    form: web::Form<SuppressionFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.0.email.trim().to_string()) {
+        Ok(email) => email.inner(),
+        Err(e) => {
+            FlashMessage::error(html_escape(&e)).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+
+    let added = suppressions::add_suppression(&pool, email.clone(), SuppressionReason::Manual, "admin".to_string())
+        .await
+        .map_err(e500)?;
+    if added {
+        record_audit_event(
+            &pool,
+            NewAuditEvent::new(Some(**user_id), AuditAction::SuppressionAdded)
+                .target(email.to_lowercase())
+                .ip_address(client_ip(&request))
+        )
+        .await
+        .map_err(e500)?;
+        FlashMessage::info(format!("{} has been added to the suppression list.", html_escape(&email))).send();
+    } else {
+        FlashMessage::error(format!("{} is already suppressed.", html_escape(&email))).send();
+    }
+    Ok(see_other("/admin/suppressions"))
+}
+
+#[tracing::instrument("Remove a suppression", skip(form, pool, user_id, request))]
+pub async fn remove_suppression(
+    form: web::Form<SuppressionFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = form.0.email;
+
+    if suppressions::remove_suppression(&pool, email.clone()).await.map_err(e500)? {
+        record_audit_event(
+            &pool,
+            NewAuditEvent::new(Some(**user_id), AuditAction::SuppressionRemoved)
+                .target(email.trim().to_lowercase())
+                .ip_address(client_ip(&request))
+        )
+        .await
+        .map_err(e500)?;
+        FlashMessage::info(format!("{} can be mailed again.", html_escape(&email))).send();
+    } else {
+        FlashMessage::error(format!("{} is not on the suppression list.", html_escape(&email))).send();
+    }
+    Ok(see_other("/admin/suppressions"))
+}
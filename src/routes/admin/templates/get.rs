@@ -0,0 +1,193 @@
+use std::fmt::Write;
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{csrf::CsrfToken, models::Template, templates::{get_template, get_templates, TemplateKind}, utils::{e500, html_escape}};
+
+/// Shown next to the issue editors.
+pub const MERGE_TAGS_HELP: &str = "Personalise the title and body with <code>{{name}}</code>, \
+    <code>{{email}}</code> and <code>{{unsubscribe_url}}</code>. \
+    Use <code>{{name|default('there')}}</code> for subscribers without a name.";
+
+const SLOTS_HELP: &str = "Issue templates must place <code>{{content}}</code> in both bodies. \
+    Also available: <code>{{issue.title}}</code>, <code>{{subscriber.name}}</code>, \
+    <code>{{subscriber.email}}</code> and <code>{{unsubscribe_url}}</code>.";
+
+const CONFIRMATION_SLOTS_HELP: &str = "Both bodies must include <code>{{confirmation_url}}</code>. \
+    Also available: <code>{{subscriber.name}}</code> and <code>{{subscriber.email}}</code>.";
+
+/// A `<select name="template_id">` listing the issue templates, with an
+/// empty option for sending without a layout.
+pub fn template_select_html(templates: &[Template], selected: Option<Uuid>) -> String {
+    let mut options = String::from(r#"<option value="">(none)</option>"#);
+    for template in templates {
+        writeln!(
+            options,
+            r#"<option value="{}"{}>{}</option>"#,
+            template.template_id,
+            if Some(template.template_id) == selected { " selected" } else { "" },
+            html_escape(&template.name),
+        ).unwrap();
+    }
+    format!(r#"<label for="template_id">Template:</label><br>
+        <select id="template_id" name="template_id">{options}</select><br><br>"#)
+}
+
+pub async fn templates_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let templates = get_templates(&pool, None).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for template in templates {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td><a href="/admin/templates/{}">{}</a></td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            template.template_id,
+            html_escape(&template.name),
+            template.kind,
+            template.updated_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        ).unwrap();
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Email templates</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Email templates</h1>
+    <p><a href="/admin/templates/new">New template</a></p>
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Kind</th>
+            <th>Last updated</th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
+
+pub async fn new_template_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_token = csrf_token.into_inner();
+
+    HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>New template</title>
+</head>
+<body>
+    {msg_html}
+    <h1>New template</h1>
+    <p>{SLOTS_HELP}</p>
+    <form action="/admin/templates" method="post">
+        <label for="name">Name:</label><br>
+        <input type="text" id="name" name="name" required><br><br>
+
+        <label for="subject">Subject:</label><br>
+        <input type="text" id="subject" name="subject" value="{{{{issue.title}}}}" size="80"><br><br>
+
+        <label for="html">HTML:</label><br>
+        <textarea id="html" name="html" rows="15" cols="80">{{{{content}}}}</textarea><br><br>
+
+        <label for="text">Plain text:</label><br>
+        <textarea id="text" name="text" rows="15" cols="80">{{{{content}}}}</textarea><br><br>
+
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Save template</button>
+    </form>
+    <p><a href="/admin/templates">&lt;- Back</a></p>
+</body>
+</html>"#))
+}
+
+pub async fn edit_template_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    template_id: web::Path<Uuid>,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let template = match get_template(&pool, *template_id).await.map_err(e500)? {
+        Some(template) => template,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_token = csrf_token.into_inner();
+    let id = template.template_id;
+    let name = html_escape(&template.name);
+    let subject = html_escape(&template.subject);
+    let html = html_escape(&template.html);
+    let text = html_escape(&template.text);
+
+    let (help, delete_html) = match template.kind() {
+        TemplateKind::Issue => (SLOTS_HELP, format!(r#"
+    <form action="/admin/templates/{id}/delete" method="post">
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Delete template</button>
+    </form>"#)),
+        TemplateKind::Confirmation => (CONFIRMATION_SLOTS_HELP, String::new()),
+    };
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit template</title>
+</head>
+<body>
+    {msg_html}
+    <h1>Edit template</h1>
+    <p>{help}</p>
+    <form action="/admin/templates/{id}" method="post">
+        <label for="name">Name:</label><br>
+        <input type="text" id="name" name="name" value="{name}" required><br><br>
+
+        <label for="subject">Subject:</label><br>
+        <input type="text" id="subject" name="subject" value="{subject}" size="80"><br><br>
+
+        <label for="html">HTML:</label><br>
+        <textarea id="html" name="html" rows="15" cols="80">{html}</textarea><br><br>
+
+        <label for="text">Plain text:</label><br>
+        <textarea id="text" name="text" rows="15" cols="80">{text}</textarea><br><br>
+
+        <input hidden type="text" name="csrf_token" value="{csrf_token}">
+        <button type="submit">Save</button>
+    </form>
+    {delete_html}
+    <p><a href="/admin/templates">&lt;- Back</a></p>
+</body>
+</html>"#)))
+}
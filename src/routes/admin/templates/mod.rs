@@ -0,0 +1,4 @@
+mod get;
+pub use get::{edit_template_form, new_template_form, template_select_html, templates_page, MERGE_TAGS_HELP};
+mod post;
+pub use post::{create_template, delete_template, update_template};
@@ -0,0 +1,115 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, session_state::UserId, templates::{self, get_template, TemplateContent, TemplateKind}, utils::{client_ip, e500, see_other}};
+
+#[derive(Deserialize)]
+pub struct TemplateFormData {
+    name: String,
+    #[serde(default)]
+    subject: String,
+    #[serde(default)]
+    html: String,
+    #[serde(default)]
+    text: String,
+}
+
+impl TemplateFormData {
+    fn parse(self, kind: TemplateKind) -> Result<TemplateContent, String> {
+        TemplateContent::parse(kind, self.name, self.subject, self.html, self.text)
+    }
+}
+
+#[tracing::instrument("Create a template", skip(form, pool, user_id, request))]
+pub async fn create_template(
+    form: web::Form<TemplateFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let content = match form.0.parse(TemplateKind::Issue) {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/templates/new"));
+        }
+    };
+
+    let template_id = templates::create_template(&pool, content)
+        .await
+        .map_err(e500)?;
+    record_template_change(&pool, template_id, "created", **user_id, &request).await?;
+
+    FlashMessage::info("The template has been saved.").send();
+    Ok(see_other(&format!("/admin/templates/{}", template_id)))
+}
+
+#[tracing::instrument("Update a template", skip(form, pool, user_id, request))]
+pub async fn update_template(
+    form: web::Form<TemplateFormData>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    template_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let template_id = template_id.into_inner();
+    let template = match get_template(&pool, template_id).await.map_err(e500)? {
+        Some(template) => template,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let template_page = format!("/admin/templates/{}", template_id);
+    let content = match form.0.parse(template.kind()) {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&template_page));
+        }
+    };
+
+    if templates::update_template(&pool, template_id, content).await.map_err(e500)? {
+        record_template_change(&pool, template_id, "updated", **user_id, &request).await?;
+        FlashMessage::info("The template has been saved.").send();
+        Ok(see_other(&template_page))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+#[tracing::instrument("Delete a template", skip(pool, user_id, request))]
+pub async fn delete_template(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    template_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    if templates::delete_template(&pool, *template_id).await.map_err(e500)? {
+        record_template_change(&pool, *template_id, "deleted", **user_id, &request).await?;
+        FlashMessage::info("The template has been deleted.").send();
+    } else {
+        FlashMessage::error("Only issue templates can be deleted.").send();
+    }
+    Ok(see_other("/admin/templates"))
+}
+
+async fn record_template_change(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    template_id: Uuid,
+    change: &str,
+    user_id: Uuid,
+    request: &HttpRequest,
+) -> Result<(), actix_web::Error> {
+    record_audit_event(
+        pool,
+        NewAuditEvent::new(Some(user_id), AuditAction::ConfigChanged)
+            .target(template_id)
+            .metadata(serde_json::json!({ "setting": "template", "change": change }))
+            .ip_address(client_ip(request))
+    )
+    .await
+    .map_err(e500)
+}
@@ -0,0 +1,89 @@
+use std::fmt::Write;
+
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+
+use crate::{archive::{get_public_issue, get_public_issues, public_issue_title, render_public_issue}, feeds::FeedFormat, utils::{e500, html_escape}};
+
+/// Lists the issues in the public archive.
+pub async fn archive_page(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_public_issues(&pool, None).await.map_err(e500)?;
+
+    let mut items_html = String::new();
+    for issue in &issues {
+        writeln!(
+            items_html,
+            r#"<li><a href="/issues/{}">{}</a> <small>{}</small></li>"#,
+            html_escape(issue.slug.as_deref().unwrap_or_default()),
+            html_escape(&public_issue_title(issue)),
+            issue.published_at.map(|t| t.format("%Y-%m-%d").to_string()).unwrap_or_default(),
+        ).unwrap();
+    }
+    if issues.is_empty() {
+        items_html.push_str("<li>Nothing has been published yet.</li>");
+    }
+
+    let feed_links = FeedFormat::ALL
+        .iter()
+        .map(|format| format!(
+            r#"<link rel="alternate" type="{}" href="{}">"#,
+            format.content_type().split(';').next().unwrap_or_default(),
+            format.path()
+        ))
+        .collect::<Vec<_>>()
+        .join("\n    ");
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Past issues</title>
+    {feed_links}
+</head>
+<body>
+    <h1>Past issues</h1>
+    <ul>
+        {items_html}
+    </ul>
+    <p><a href="/feed.rss">RSS</a> | <a href="/feed.atom">Atom</a> | <a href="/feed.json">JSON Feed</a></p>
+    <p><a href="/">Home</a></p>
+</body>
+</html>"#)))
+}
+
+/// Shows one issue of the public archive. Issues that are not public are
+/// reported as missing.
+pub async fn archive_issue(
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_public_issue(&pool, slug.into_inner()).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let rendered = render_public_issue(&issue).map_err(e500)?;
+
+    let title = html_escape(&rendered.subject);
+    let published = issue.published_at.map(|t| t.format("%Y-%m-%d").to_string()).unwrap_or_default();
+    // Issue HTML is sanitized when it is published, see `prepare_html`.
+    let body = rendered.html;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p><time datetime="{published}">{published}</time></p>
+    <article>
+        {body}
+    </article>
+    <p><a href="/issues">&lt;- All issues</a></p>
+</body>
+</html>"#)))
+}
@@ -0,0 +1,99 @@
+use std::time::SystemTime;
+
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, EntityTag, ETag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified, CONTENT_TYPE, IF_NONE_MATCH},
+    web, HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, SubsecRound};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+
+use crate::{
+    archive::{get_archive_changed_at, get_public_issues},
+    feeds::{render_feed, FeedEntry, FeedFormat, FEED_LENGTH},
+    startup::ApplicationBaseUrl,
+    utils::e500,
+};
+
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    feed(&request, &pool, &base_url.0, FeedFormat::Rss).await
+}
+
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    feed(&request, &pool, &base_url.0, FeedFormat::Atom).await
+}
+
+pub async fn json_feed(
+    request: HttpRequest,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    feed(&request, &pool, &base_url.0, FeedFormat::Json).await
+}
+
+/// Serves the public archive as a feed. Readers that send back the ETag or
+/// Last-Modified of their copy get a 304 while nothing has changed.
+async fn feed(
+    request: &HttpRequest,
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    base_url: &str,
+    format: FeedFormat,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_public_issues(pool, Some(FEED_LENGTH as i64)).await.map_err(e500)?;
+
+    let mut entries = Vec::with_capacity(FEED_LENGTH);
+    for issue in &issues {
+        if let Some(entry) = FeedEntry::from_issue(issue, base_url).map_err(e500)? {
+            entries.push(entry);
+        }
+    }
+    let last_modified = get_archive_changed_at(pool)
+        .await
+        .map_err(e500)?
+        .unwrap_or(DateTime::UNIX_EPOCH);
+    let rendered = render_feed(format, &entries, base_url);
+
+    let etag = EntityTag::new_strong(rendered.etag);
+    // HTTP dates have no fractions of a second.
+    let last_modified = HttpDate::from(SystemTime::from(last_modified.trunc_subsecs(0)));
+    let fresh = is_fresh(request, &etag, last_modified);
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header(LastModified(last_modified))
+        .insert_header(CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(300)]));
+
+    if fresh {
+        Ok(response.finish())
+    } else {
+        Ok(response.insert_header((CONTENT_TYPE, format.content_type())).body(rendered.body))
+    }
+}
+
+/// Whether the client's copy is still current. As RFC 9110 asks,
+/// `If-Modified-Since` is only looked at when there is no `If-None-Match`.
+fn is_fresh(request: &HttpRequest, etag: &EntityTag, last_modified: HttpDate) -> bool {
+    if request.headers().contains_key(IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+    match IfModifiedSince::parse(request) {
+        Ok(IfModifiedSince(since)) => SystemTime::from(last_modified) <= SystemTime::from(since),
+        Err(_) => false,
+    }
+}
@@ -1,4 +1,4 @@
-use actix_web::{error::InternalError, http::{header::LOCATION, StatusCode}, web, HttpResponse};
+use actix_web::{error::InternalError, http::{header::{LOCATION, USER_AGENT}, StatusCode}, web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use diesel::{r2d2::ConnectionManager, PgConnection};
 use diesel::r2d2::Pool;
@@ -6,7 +6,7 @@ use secrecy::Secret;
 use serde::Deserialize;
 use actix_web::error::ResponseError;
 
-use crate::{authentication::{validate_credentials, AuthError, Credentials}, routes::subscribe::error_chain_fmt, session_state::TypedSession};
+use crate::{audit::{record_audit_event, AuditAction, NewAuditEvent}, authentication::{validate_credentials, AuthError, Credentials}, configuration::PasswordHashSettings, csrf::CsrfToken, routes::subscribe::error_chain_fmt, session_registry::record_session, session_state::{session_timestamp, TypedSession}, utils::client_ip};
 
 #[derive(Deserialize)] 
 pub struct FormData {
@@ -15,27 +15,58 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(form, pool, hash_settings, session, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
-pub async fn login(form: web::Form<FormData>, pool: web::Data<Pool<ConnectionManager<PgConnection>>>, session: TypedSession) -> Result<HttpResponse, InternalError<LoginError>> {
+pub async fn login(form: web::Form<FormData>, pool: web::Data<Pool<ConnectionManager<PgConnection>>>, hash_settings: web::Data<PasswordHashSettings>, session: TypedSession, request: HttpRequest) -> Result<HttpResponse, InternalError<LoginError>> {
 
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
+    let attempted_username = credentials.username.clone();
 
     tracing::Span::current()
-        .record("username", &tracing::field::display(&credentials.username));
+        .record("username", tracing::field::display(&credentials.username));
 
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &pool, &hash_settings).await {
         Ok(user_id) => {
             tracing::Span::current()
-                .record("user_id", &tracing::field::display(&user_id));
+                .record("user_id", tracing::field::display(&user_id));
+
+            let user_agent = request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.to_string());
+            let ip_address = client_ip(&request);
+
+            let session_id = record_session(&pool, user_id, user_agent, ip_address.clone())
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+
+            record_audit_event(
+                &pool,
+                NewAuditEvent::new(Some(user_id), AuditAction::Login)
+                    .target(session_id)
+                    .ip_address(ip_address)
+            )
+            .await
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
 
             session.renew();
             session.insert_user_id(user_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session.insert_session_id(session_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+
+            let now = session_timestamp();
+            session.insert_logged_in_at(now)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session.insert_last_activity_at(now)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session.insert_csrf_token(CsrfToken::generate().to_string())
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
 
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
@@ -43,6 +74,20 @@ pub async fn login(form: web::Form<FormData>, pool: web::Data<Pool<ConnectionMan
         },
 
         Err(e) => {
+            if let AuthError::InvalidCredentials(_) = e {
+                let event = NewAuditEvent::new(None, AuditAction::LoginFailed)
+                    .metadata(serde_json::json!({ "username": attempted_username }))
+                    .ip_address(client_ip(&request));
+
+                if let Err(e) = record_audit_event(&pool, event).await {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to record failed login attempt",
+                    );
+                }
+            }
+
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
@@ -1,6 +1,11 @@
+pub mod archive;
+pub mod feeds;
 pub mod health_check;
 pub mod subscribe;
 pub mod subscriptions_confirm;
+pub mod subscriptions_unsubscribe;
+pub mod tracking;
+pub mod webhooks;
 pub mod home;
 pub use home::*;
 mod login;
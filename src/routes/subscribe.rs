@@ -1,5 +1,6 @@
 
 use actix_web::{error::BlockingError, http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
 use chrono::Utc;
 use diesel::{
     associations::HasTable, r2d2::{ConnectionManager, Pool}, Connection, PgConnection, RunQueryDsl
@@ -16,7 +17,8 @@ use crate::{
     },
     email_client::EmailClient,
     models::{SubscribeFormData, SubscriptionAdd, SubscriptionTokensAdd},
-    startup::ApplicationBaseUrl,
+    rendering::{render_confirmation, unsubscribe_url, Recipient},
+    templates::get_confirmation_template,
 };
 
 impl TryFrom<SubscribeFormData> for NewSubscriber {
@@ -33,13 +35,16 @@ impl TryFrom<SubscribeFormData> for NewSubscriber {
 }
 
 #[derive(thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum SubscribeError{
     #[error("{0}")]
     ValidationError(String),
     #[error("Failed to insert subscriber to database")]
     InsertSubscriberError(#[from] InsertSubscriberError),
     #[error("Failed to send confirmation email to user")]
-    SendEmailError(#[from] reqwest::Error)
+    SendEmailError(#[from] anyhow::Error),
+    #[error("Failed to check the suppression list")]
+    SuppressionCheckError(#[source] anyhow::Error),
 }
 
 impl std::fmt::Debug for SubscribeError {
@@ -52,7 +57,7 @@ impl ResponseError for SubscribeError{
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             Self::ValidationError(_) => StatusCode::BAD_REQUEST,
-            Self::InsertSubscriberError(_) | Self::SendEmailError(_) => StatusCode::INTERNAL_SERVER_ERROR
+            Self::InsertSubscriberError(_) | Self::SendEmailError(_) | Self::SuppressionCheckError(_) => StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 }
@@ -88,7 +93,7 @@ pub enum InsertSubscriberError{
 }
 
 pub fn error_chain_fmt(e: &impl std::error::Error, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}\n", e)?;
+    writeln!(f, "{}", e)?;
     let mut current = e.source();
 
     while let Some(cause) = current {
@@ -166,28 +171,37 @@ pub async fn insert_subscriber(
 
 #[tracing::instrument(
     name = "Sending confirmation mail to subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, pool, new_subscriber, base_url)
 )]
 pub async fn send_confirmation_mail(
     email_client: &EmailClient,
+    pool: &Pool<ConnectionManager<PgConnection>>,
     new_subscriber: &NewSubscriber,
     base_url: &String,
     sub_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, sub_token
     );
 
-    let res = email_client.send_email(&new_subscriber.email,
-        "Welcome!",
-        &format!("Welcome to our newsletter! Click <a href = \"{}\">here</a> to confirm your subscription", confirmation_link),
-        &format!("Welcome to our newsletter! Visit {} to confirm subscription", confirmation_link)
-    ).await;
-
-    if res.is_err() {
-        return res;
-    }
+    let template = get_confirmation_template(pool).await?;
+    let recipient = Recipient {
+        subscriber_id: None,
+        email: new_subscriber.email.inner(),
+        name: new_subscriber.name.inner(),
+        unsubscribe_url: unsubscribe_url(base_url, sub_token),
+    };
+    let rendered = render_confirmation(&template, &recipient, &confirmation_link)
+        .context("Failed to render the confirmation email")?;
+
+    email_client.send_email(&new_subscriber.email,
+        &rendered.subject,
+        &rendered.html,
+        &rendered.text,
+    )
+    .await
+    .context("Failed to send the confirmation email")?;
 
     Ok(())
 }
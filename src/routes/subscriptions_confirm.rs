@@ -1,13 +1,7 @@
 use actix_web::{web, HttpResponse};
-use diesel::{
-    r2d2::{ConnectionManager, Pool},
-    ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
-};
 use serde::Deserialize;
-use uuid::Uuid;
 
-use crate::{schema::subscriptions, traits::SubscriptionService};
-use crate::{models::SubscriptionToken, schema::subscription_tokens::dsl::*};
+use crate::traits::SubscriptionService;
 
 #[derive(Deserialize)]
 pub struct Parameters {
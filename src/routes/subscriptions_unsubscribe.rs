@@ -0,0 +1,32 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::traits::SubscriptionService;
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, subscription_service))]
+pub async fn unsubscribe<S: SubscriptionService>(
+    parameters: web::Query<Parameters>,
+    subscription_service: web::Data<S>,
+) -> HttpResponse {
+    let result = subscription_service
+        .unsubscribe(&parameters.subscription_token)
+        .await;
+
+    match result {
+        Ok(true) => {
+            tracing::info!("Subscriber has unsubscribed");
+            HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body("<p>You have been unsubscribed.</p>")
+        }
+
+        Ok(false) => HttpResponse::NotFound().finish(),
+
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,75 @@
+use actix_web::{http::header::{CacheControl, CacheDirective, LOCATION, USER_AGENT}, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+
+use crate::tracking::{EngagementEvent, EngagementRecorder, Tracking};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Serves the tracking pixel. The open is handed to the recorder rather than
+/// stored here, and the image is served even for unknown tokens so a broken
+/// link never shows up in the email.
+#[tracing::instrument(name = "Track an open", skip_all)]
+pub async fn track_open(
+    token: web::Path<String>,
+    tracking: web::Data<Tracking>,
+    recorder: web::Data<EngagementRecorder>,
+    request: HttpRequest,
+) -> HttpResponse {
+    match tracking.verify_open_token(&token) {
+        Some((issue_id, subscriber_id)) => {
+            recorder.record(EngagementEvent::Open {
+                issue_id,
+                subscriber_id,
+                user_agent: user_agent(&request),
+                at: Utc::now(),
+            });
+        }
+        None => tracing::warn!("Ignoring an open with an invalid token"),
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL)
+}
+
+/// Sends the reader on to the link they clicked. Only destinations we signed
+/// are followed, so the endpoint cannot be used as an open redirect.
+#[tracing::instrument(name = "Track a click", skip_all)]
+pub async fn track_click(
+    token: web::Path<String>,
+    tracking: web::Data<Tracking>,
+    recorder: web::Data<EngagementRecorder>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let Some((issue_id, subscriber_id, url)) = tracking.verify_click_token(&token) else {
+        tracing::warn!("Refusing to follow a click with an invalid token");
+        return HttpResponse::BadRequest().body("This link is not valid.");
+    };
+
+    recorder.record(EngagementEvent::Click {
+        issue_id,
+        subscriber_id,
+        url: url.clone(),
+        user_agent: user_agent(&request),
+        at: Utc::now(),
+    });
+
+    HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish()
+}
+
+fn user_agent(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string())
+}
@@ -0,0 +1,62 @@
+use actix_web::{http::header::{AUTHORIZATION, WWW_AUTHENTICATE}, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use hmac::{Hmac, Mac};
+use r2d2::Pool;
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+
+use crate::{configuration::PostmarkWebhookSettings, delivery_events::{record_delivery_event, DeliveryEvent, PostmarkEvent}, utils::{e400, e500}};
+
+/// Receives Postmark's bounce, spam complaint and delivery webhooks. Anything
+/// other than a 200 makes Postmark retry, so record types we do not use are
+/// acknowledged too.
+#[tracing::instrument(name = "Receive Postmark webhook", skip_all)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    settings: web::Data<PostmarkWebhookSettings>,
+    pool: web::Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !is_authorised(&request, &settings) {
+        tracing::warn!("Rejecting a webhook with missing or invalid credentials");
+        return Ok(HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, r#"Basic realm="webhooks""#))
+            .finish());
+    }
+
+    let payload: PostmarkEvent = serde_json::from_slice(&body).map_err(e400)?;
+    if let Some(event) = DeliveryEvent::from_postmark(payload) {
+        let kind = event.kind.as_str();
+        if !record_delivery_event(&pool, event).await.map_err(e500)? {
+            tracing::info!(kind, "Ignoring a webhook that was already recorded");
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn is_authorised(request: &HttpRequest, settings: &PostmarkWebhookSettings) -> bool {
+    let Some(credentials) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+    else {
+        return false;
+    };
+    let expected = format!("{}:{}", settings.username, settings.password.expose_secret());
+    constant_time_eq(&credentials, expected.as_bytes())
+}
+
+/// Compares MACs of both values, which `verify_slice` does in constant time
+/// whatever their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mac = |value: &[u8]| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"webhook credentials").expect("HMAC can take a key of any size");
+        mac.update(value);
+        mac
+    };
+    mac(a).verify_slice(&mac(b).finalize().into_bytes()).is_ok()
+}
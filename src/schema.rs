@@ -6,6 +6,40 @@ pub mod sql_types {
     pub struct HeaderPair;
 }
 
+diesel::table! {
+    audit_events (audit_event_id) {
+        audit_event_id -> Uuid,
+        user_id -> Nullable<Uuid>,
+        action -> Text,
+        target -> Nullable<Text>,
+        metadata -> Jsonb,
+        ip_address -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    delivery_events (delivery_event_id) {
+        delivery_event_id -> Uuid,
+        kind -> Text,
+        email -> Text,
+        subscriber_id -> Nullable<Uuid>,
+        newsletter_issue_id -> Nullable<Uuid>,
+        message_id -> Nullable<Text>,
+        bounce_type -> Nullable<Text>,
+        description -> Nullable<Text>,
+        occurred_at -> Timestamptz,
+        received_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    followed_feeds (source) {
+        source -> Text,
+        started_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::HeaderPair;
@@ -20,11 +54,46 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    issue_clicks (issue_click_id) {
+        issue_click_id -> Uuid,
+        newsletter_issue_id -> Uuid,
+        subscriber_id -> Uuid,
+        url -> Text,
+        user_agent -> Nullable<Text>,
+        clicked_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    issue_deliveries (newsletter_issue_id, subscriber_email) {
+        newsletter_issue_id -> Uuid,
+        subscriber_email -> Text,
+        outcome -> Text,
+        detail -> Nullable<Text>,
+        attempted_at -> Timestamptz,
+        bounced_at -> Nullable<Timestamptz>,
+        subject_variant_id -> Nullable<Uuid>,
+    }
+}
+
 diesel::table! {
     issue_delivery_queue (newsletter_issue_id, subscriber_email) {
         newsletter_issue_id -> Uuid,
         subscriber_email -> Text,
         state -> Nullable<Text>,
+        subject_variant_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    issue_opens (newsletter_issue_id, subscriber_id) {
+        newsletter_issue_id -> Uuid,
+        subscriber_id -> Uuid,
+        first_opened_at -> Timestamptz,
+        last_opened_at -> Timestamptz,
+        open_count -> Int4,
+        user_agent -> Nullable<Text>,
     }
 }
 
@@ -34,7 +103,76 @@ diesel::table! {
         title -> Text,
         text -> Text,
         html -> Text,
-        published_at -> Text,
+        published_at -> Nullable<Timestamptz>,
+        status -> Text,
+        created_by -> Nullable<Uuid>,
+        updated_at -> Timestamptz,
+        scheduled_for -> Nullable<Timestamptz>,
+        markdown -> Nullable<Text>,
+        template_id -> Nullable<Uuid>,
+        track_opens -> Bool,
+        slug -> Nullable<Text>,
+        is_public -> Bool,
+        schedule_error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    processed_feed_entries (source, guid) {
+        source -> Text,
+        guid -> Text,
+        newsletter_issue_id -> Nullable<Uuid>,
+        processed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sequence_deliveries (subscriber_id, sequence_step_id) {
+        subscriber_id -> Uuid,
+        sequence_step_id -> Uuid,
+        due_at -> Timestamptz,
+        outcome -> Nullable<Text>,
+        detail -> Nullable<Text>,
+        attempted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    sequence_enrollments (subscriber_id) {
+        subscriber_id -> Uuid,
+        enrolled_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sequence_steps (sequence_step_id) {
+        sequence_step_id -> Uuid,
+        delay_days -> Int4,
+        subject -> Text,
+        html -> Text,
+        text -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    subject_tests (newsletter_issue_id) {
+        newsletter_issue_id -> Uuid,
+        sample_percent -> Int4,
+        wait_minutes -> Int4,
+        metric -> Text,
+        winning_variant_id -> Nullable<Uuid>,
+        decided_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    subject_variants (subject_variant_id) {
+        subject_variant_id -> Uuid,
+        newsletter_issue_id -> Uuid,
+        position -> Int4,
+        subject -> Text,
     }
 }
 
@@ -52,6 +190,41 @@ diesel::table! {
         name -> Text,
         subscribed_at -> Timestamptz,
         status -> Text,
+        unsubscribed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    suppressions (email) {
+        email -> Text,
+        reason -> Text,
+        source -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    templates (template_id) {
+        template_id -> Uuid,
+        name -> Text,
+        kind -> Text,
+        subject -> Text,
+        html -> Text,
+        text -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_sessions (session_id) {
+        session_id -> Uuid,
+        user_id -> Uuid,
+        user_agent -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -63,15 +236,50 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(audit_events -> users (user_id));
+diesel::joinable!(delivery_events -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(delivery_events -> subscriptions (subscriber_id));
 diesel::joinable!(idempotency -> users (user_id));
+diesel::joinable!(issue_clicks -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(issue_clicks -> subscriptions (subscriber_id));
+diesel::joinable!(issue_deliveries -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(issue_deliveries -> subject_variants (subject_variant_id));
 diesel::joinable!(issue_delivery_queue -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(issue_delivery_queue -> subject_variants (subject_variant_id));
+diesel::joinable!(issue_opens -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(issue_opens -> subscriptions (subscriber_id));
+diesel::joinable!(newsletter_issues -> templates (template_id));
+diesel::joinable!(newsletter_issues -> users (created_by));
+diesel::joinable!(processed_feed_entries -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(sequence_deliveries -> sequence_steps (sequence_step_id));
+diesel::joinable!(sequence_deliveries -> subscriptions (subscriber_id));
+diesel::joinable!(sequence_enrollments -> subscriptions (subscriber_id));
+diesel::joinable!(subject_tests -> newsletter_issues (newsletter_issue_id));
+diesel::joinable!(subject_tests -> subject_variants (winning_variant_id));
+diesel::joinable!(subject_variants -> newsletter_issues (newsletter_issue_id));
 diesel::joinable!(subscription_tokens -> subscriptions (subscriber_id));
+diesel::joinable!(user_sessions -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_events,
+    delivery_events,
+    followed_feeds,
     idempotency,
+    issue_clicks,
+    issue_deliveries,
     issue_delivery_queue,
+    issue_opens,
     newsletter_issues,
+    processed_feed_entries,
+    sequence_deliveries,
+    sequence_enrollments,
+    sequence_steps,
+    subject_tests,
+    subject_variants,
     subscription_tokens,
     subscriptions,
+    suppressions,
+    templates,
+    user_sessions,
     users,
 );
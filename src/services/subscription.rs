@@ -18,6 +18,18 @@ impl<U, V> SubscriptionService for NewsletterSubscriptionService<U, V>
     async fn create_subscription(&self, form: SubscribeFormData) -> Result<(), SubscribeError> {
         let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
 
+        // Answer as if all went well, so the form does not reveal which
+        // addresses bounced or complained.
+        if self
+            .subscription_repository
+            .is_suppressed(&new_subscriber.email.inner())
+            .await
+            .map_err(SubscribeError::SuppressionCheckError)?
+        {
+            tracing::info!("Not sending a confirmation to a suppressed address.");
+            return Ok(());
+        }
+
         let result = self
             .subscription_repository
             .insert_subscriber(&new_subscriber)
@@ -47,4 +59,14 @@ impl<U, V> SubscriptionService for NewsletterSubscriptionService<U, V>
 
         Ok(())
     }
+
+    async fn unsubscribe(&self, subscription_token: &str) -> Result<bool, String> {
+        self.subscription_repository
+            .unsubscribe_subscriber(subscription_token)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to unsubscribe: {:?}", e);
+                "Failed to unsubscribe".to_string()
+            })
+    }
 }
@@ -0,0 +1,169 @@
+use actix_web::web;
+use anyhow::Context;
+use chrono::Utc;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::models::{UserSession, UserSessionAdd};
+
+#[tracing::instrument(name = "Record new session", skip(pool, agent, ip))]
+pub async fn record_session(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    uid: Uuid,
+    agent: Option<String>,
+    ip: Option<String>,
+) -> Result<Uuid, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::user_sessions::dsl::*;
+
+    let now = Utc::now();
+    let new_session = UserSessionAdd {
+        session_id: Uuid::new_v4(),
+        user_id: uid,
+        user_agent: agent,
+        ip_address: ip,
+        created_at: now,
+        last_seen_at: now,
+    };
+    let new_session_id = new_session.session_id;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            diesel::insert_into(user_sessions)
+                .values(new_session)
+                .execute(&mut conn)
+                .context("Failed to insert session record")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(new_session_id)
+}
+
+/// Bumps `last_seen_at` for a session that has not been revoked.
+/// Returns `false` if the session is unknown or has been revoked.
+#[tracing::instrument(name = "Touch session", skip(pool))]
+pub async fn touch_session(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    uid: Uuid,
+    sid: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::user_sessions::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(
+                user_sessions
+                    .filter(session_id.eq(sid))
+                    .filter(user_id.eq(uid))
+                    .filter(revoked_at.is_null())
+            )
+            .set(last_seen_at.eq(Utc::now()))
+            .execute(&mut conn)
+            .context("Failed to update session record")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+#[tracing::instrument(name = "Get active sessions", skip(pool))]
+pub async fn get_active_sessions(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    uid: Uuid,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::user_sessions::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let sessions = web::block(move || {
+        current_span.in_scope(|| {
+            user_sessions
+                .select((session_id, user_agent, ip_address, created_at, last_seen_at))
+                .filter(user_id.eq(uid))
+                .filter(revoked_at.is_null())
+                .order(last_seen_at.desc())
+                .load::<UserSession>(&mut conn)
+                .context("Failed to fetch active sessions")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(sessions)
+}
+
+/// Revokes a single session belonging to `uid`.
+/// Returns `false` if there was no such active session.
+#[tracing::instrument(name = "Revoke session", skip(pool))]
+pub async fn revoke_session(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    uid: Uuid,
+    sid: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::user_sessions::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(
+                user_sessions
+                    .filter(session_id.eq(sid))
+                    .filter(user_id.eq(uid))
+                    .filter(revoked_at.is_null())
+            )
+            .set(revoked_at.eq(Utc::now()))
+            .execute(&mut conn)
+            .context("Failed to revoke session")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+#[tracing::instrument(name = "Revoke all sessions", skip(pool))]
+pub async fn revoke_all_sessions(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    uid: Uuid,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::user_sessions::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(
+                user_sessions
+                    .filter(user_id.eq(uid))
+                    .filter(revoked_at.is_null())
+            )
+            .set(revoked_at.eq(Utc::now()))
+            .execute(&mut conn)
+            .context("Failed to revoke sessions")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(())
+}
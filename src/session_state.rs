@@ -1,17 +1,25 @@
 use std::rc::Rc;
 
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
-use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, FromRequest, HttpMessage};
-use futures_util::{future::{ready, Either, LocalBoxFuture, Ready}, FutureExt};
+use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, web, FromRequest, HttpMessage};
+use actix_web_flash_messages::FlashMessage;
+use chrono::Utc;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use futures_util::{future::{ready, LocalBoxFuture, Ready}, FutureExt};
+use r2d2::Pool;
 use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::utils::{e500, see_other};
+use crate::{configuration::SessionSettings, session_registry::{revoke_session, touch_session}, utils::{e500, see_other}};
 
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const LOGGED_IN_AT_KEY: &'static str = "logged_in_at";
+    const LAST_ACTIVITY_KEY: &'static str = "last_activity_at";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -25,11 +33,68 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    pub fn insert_logged_in_at(&self, timestamp: f64) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::LOGGED_IN_AT_KEY, timestamp)
+    }
+
+    pub fn get_logged_in_at(&self) -> Result<Option<f64>, SessionGetError> {
+        self.0.get(Self::LOGGED_IN_AT_KEY)
+    }
+
+    pub fn insert_last_activity_at(&self, timestamp: f64) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::LAST_ACTIVITY_KEY, timestamp)
+    }
+
+    pub fn get_last_activity_at(&self) -> Result<Option<f64>, SessionGetError> {
+        self.0.get(Self::LAST_ACTIVITY_KEY)
+    }
+
+    pub fn insert_csrf_token(&self, token: String) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    /// Returns the message to show the user if the session has outlived
+    /// either the idle timeout or its absolute lifetime.
+    pub fn expiry_reason(&self, now: f64, settings: &SessionSettings) -> Result<Option<&'static str>, SessionGetError> {
+        let (logged_in_at, last_activity_at) = match (self.get_logged_in_at()?, self.get_last_activity_at()?) {
+            (Some(l), Some(a)) => (l, a),
+            _ => return Ok(Some("Your session has expired. Please log in again.")),
+        };
+
+        if now - logged_in_at > settings.absolute_timeout_seconds {
+            return Ok(Some("Your session has reached its maximum lifetime. Please log in again."));
+        }
+
+        if now - last_activity_at > settings.idle_timeout_seconds {
+            return Ok(Some("You have been logged out due to inactivity. Please log in again."));
+        }
+
+        Ok(None)
+    }
+
     pub fn log_out(&self){
         self.0.purge()
     }
 }
 
+/// Seconds since the epoch, as stored in the session. Fractions are kept so
+/// that timeouts below a second work too.
+pub fn session_timestamp() -> f64 {
+    Utc::now().timestamp_millis() as f64 / 1000.0
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
 
@@ -46,6 +111,22 @@ impl std::ops::Deref for UserId {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
+pub struct SessionId(Uuid);
+
+impl std::ops::Deref for SessionId {
+    type Target = Uuid;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Session> for TypedSession {
+    fn from(session: Session) -> Self {
+        Self(session)
+    }
+}
+
 impl FromRequest for TypedSession {
     type Error = <Session as FromRequest>::Error;
     type Future = Ready<Result<TypedSession, Self::Error>>;
@@ -55,14 +136,9 @@ impl FromRequest for TypedSession {
     }
 }
 
+#[derive(Default)]
 pub struct SessionAuthMiddlewareFactory;
 
-impl SessionAuthMiddlewareFactory {
-    pub fn default() -> Self{
-        return SessionAuthMiddlewareFactory
-    }
-}
-
 impl<S> Transform<S, ServiceRequest> for SessionAuthMiddlewareFactory
 where 
     S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static
@@ -100,29 +176,49 @@ where
         skip(self, req)
     )]
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let session = TypedSession(req.get_session());
-
-        let temp = session.get_user_id().map_err(e500);
-        let either = match temp{
-            Ok(r) => {
-                if r.is_none(){
-                    Either::Left(req.into_response(see_other("/login"))) 
-                } else {
-                    req.extensions_mut().insert(UserId(r.unwrap()));
-                    Either::Right(self.service.call(req))
-                }
-            },
-
-            Err(e) => return Box::pin(ready(Err(e)))
-        };
+        let service = self.service.clone();
 
         let current_span = tracing::Span::current();
         async move {
-            let a = match either {
-                Either::Left(res) => Ok(res),
-                Either::Right(fut) => fut.await,
+            let session = TypedSession(req.get_session());
+
+            let (user_id, session_id) = match (
+                session.get_user_id().map_err(e500)?,
+                session.get_session_id().map_err(e500)?,
+            ) {
+                (Some(user_id), Some(session_id)) => (user_id, session_id),
+                _ => return Ok(req.into_response(see_other("/login"))),
             };
-            a
+
+            let pool = req
+                .app_data::<web::Data<Pool<ConnectionManager<PgConnection>>>>()
+                .ok_or_else(|| e500("Database pool is not configured"))?
+                .clone();
+            let settings = req
+                .app_data::<web::Data<SessionSettings>>()
+                .ok_or_else(|| e500("Session settings are not configured"))?
+                .clone();
+
+            let now = session_timestamp();
+            if let Some(reason) = session.expiry_reason(now, &settings).map_err(e500)? {
+                revoke_session(&pool, user_id, session_id).await.map_err(e500)?;
+                session.log_out();
+                FlashMessage::info(reason).send();
+                return Ok(req.into_response(see_other("/login")));
+            }
+
+            // Sessions can be revoked from another device, so the session
+            // record has to be checked on every request.
+            if !touch_session(&pool, user_id, session_id).await.map_err(e500)? {
+                session.log_out();
+                return Ok(req.into_response(see_other("/login")));
+            }
+
+            session.insert_last_activity_at(now).map_err(e500)?;
+
+            req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(SessionId(session_id));
+            service.call(req).await
         }.instrument(current_span).boxed_local()
     }
 }
@@ -2,17 +2,25 @@ use std::net::TcpListener;
 use std::time::Duration;
 
 use crate::configuration::{DatabaseSettings, Settings};
+use crate::csrf::CsrfMiddlewareFactory;
 use crate::diesel_adapter::subscription_repository::DieselSubscriptionRepository;
 use crate::email_client::{EmailClient, SubscriberConfirmationEmailer};
+use crate::routes::archive::{archive_issue, archive_page};
+use crate::routes::feeds::{atom_feed, json_feed, rss_feed};
 use crate::routes::get::newsletter_delivery_form;
 use crate::routes::health_check::health_check;
 use crate::routes::logout::log_out;
-use crate::routes::post::newsletter_delivery;
-use crate::routes::{admin_dashboard, change_password, change_password_form, home, login, login_form};
+use crate::routes::post::{newsletter_delivery, send_test_issue};
+use crate::routes::{add_suppression, admin_dashboard, audit_log, audit_log_export, cancel_issue, change_password, change_password_form, create_issue, create_sequence_step, create_template, delete_issue, delete_sequence_step, delete_template, edit_issue_form, edit_sequence_step_form, edit_template_form, home, issue_report, issue_report_export, issues_page, login, login_form, make_issue_private, make_issue_public, new_issue_form, new_template_form, pause_issue, preview_issue, publish_issue, remove_suppression, resume_issue, revoke_all_sessions, revoke_session, schedule_issue, sequence_page, sessions_page, suppressions_page, templates_page, unschedule_issue, update_issue, update_sequence_step, update_template};
 use crate::routes::subscribe::subscribe;
 use crate::routes::subscriptions_confirm::confirm;
+use crate::routes::subscriptions_unsubscribe::unsubscribe;
+use crate::routes::tracking::{track_click, track_open};
+use crate::routes::webhooks::postmark_webhook;
 use crate::services::subscription::NewsletterSubscriptionService;
 use crate::session_state::SessionAuthMiddlewareFactory;
+use crate::tracking::{EngagementRecorder, Tracking};
+use actix_session::config::BrowserSession;
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
@@ -21,7 +29,7 @@ use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::{FlashMessagesFramework, Level};
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::PgConnection;
-use secrecy::{ExposeSecret, Secret};
+use secrecy::ExposeSecret;
 use tracing_actix_web::TracingLogger;
 
 pub type SubscriptionServiceType = NewsletterSubscriptionService<
@@ -61,9 +69,7 @@ impl Application {
             listener,
             connection_pool,
             email_client,
-            config.application.base_url,
-            config.application.hmac_secret,
-            config.redis_uri
+            config
         ).await?;
         Ok(Self { port, server })
     }
@@ -79,9 +85,11 @@ impl Application {
 
 pub fn get_connection_pool(config: &DatabaseSettings) -> Pool<ConnectionManager<PgConnection>> {
     let manager =
-        ConnectionManager::<PgConnection>::new(&*config.connection_string().expose_secret());
+        ConnectionManager::<PgConnection>::new(config.connection_string().expose_secret());
+    // Connections are opened as they are needed rather than all up front.
     Pool::builder()
         .test_on_check_out(true)
+        .min_idle(Some(1))
         .connection_timeout(Duration::from_secs(5))
         .build_unchecked(manager)
 }
@@ -90,25 +98,33 @@ async fn run(
     listener: TcpListener,
     connection_pool: Pool<ConnectionManager<PgConnection>>,
     email_client: EmailClient,
-    base_url: String,
-    secret_key: Secret<String>,
-    redis_uri: Secret<String>
+    config: Settings
 ) -> Result<Server, anyhow::Error> {
     let connection_pool = web::Data::new(connection_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let base_url = web::Data::new(ApplicationBaseUrl(config.application.base_url));
+    let password_hash_settings = web::Data::new(config.password_hash);
+    // Keep session state around a little longer than its absolute lifetime,
+    // so SessionAuthMiddleware can still tell the user why they were logged out.
+    let session_state_ttl = actix_web::cookie::time::Duration::seconds_f64(
+        config.session.absolute_timeout_seconds + config.session.idle_timeout_seconds
+    );
+    let session_settings = web::Data::new(config.session);
+    let tracking = web::Data::new(Tracking::new(config.application.hmac_secret.clone(), &config.tracking));
+    let engagement_recorder = web::Data::new(EngagementRecorder::spawn(connection_pool.get_ref().clone()));
+    let postmark_webhook_settings = web::Data::new(config.postmark_webhook);
 
     let diesel_subscription_repository = DieselSubscriptionRepository::new(connection_pool.clone());
-    let confirmation_emailer = SubscriberConfirmationEmailer::new(base_url.clone(), email_client.clone());
+    let confirmation_emailer = SubscriberConfirmationEmailer::new(base_url.clone(), email_client.clone(), connection_pool.clone());
 
     let newsletter_subscription_service = web::Data::new(NewsletterSubscriptionService{
         subscription_repository: diesel_subscription_repository,
         email_sender: confirmation_emailer
     });
 
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let redis_store = RedisSessionStore::new(config.redis_uri.expose_secret()).await?;
 
-    let key = Key::from(secret_key.expose_secret().as_bytes());
+    let key = Key::from(config.application.hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(
         key.clone()
     ).build();
@@ -119,27 +135,83 @@ async fn run(
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(redis_store.clone(), key.clone()))
+            .wrap(
+                SessionMiddleware::builder(redis_store.clone(), key.clone())
+                    .session_lifecycle(BrowserSession::default().state_ttl(session_state_ttl))
+                    .build()
+            )
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe::<SubscriptionServiceType>))
             .route("/subscriptions/confirm", web::get().to(confirm::<SubscriptionServiceType>))
+            .route("/subscriptions/unsubscribe", web::get().to(unsubscribe::<SubscriptionServiceType>))
+            .route("/t/open/{token}", web::get().to(track_open))
+            .route("/t/click/{token}", web::get().to(track_click))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
+            .route("/issues", web::get().to(archive_page))
+            .route("/issues/{slug}", web::get().to(archive_issue))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route("/feed.json", web::get().to(json_feed))
             .route("/", web::get().to(home))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .service(
                 web::scope("/admin")
-                    .wrap(SessionAuthMiddlewareFactory::default())
+                    .wrap(CsrfMiddlewareFactory)
+                    .wrap(SessionAuthMiddlewareFactory)
                     .route("/password", web::post().to(change_password))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/password", web::get().to(change_password_form))
                     .route("/logout", web::post().to(log_out))
                     .route("/newsletter", web::get().to(newsletter_delivery_form))
                     .route("/newsletter", web::post().to(newsletter_delivery))
+                    .route("/newsletter/test", web::post().to(send_test_issue))
+                    .route("/sessions", web::get().to(sessions_page))
+                    .route("/sessions/revoke", web::post().to(revoke_session))
+                    .route("/sessions/revoke_all", web::post().to(revoke_all_sessions))
+                    .route("/audit", web::get().to(audit_log))
+                    .route("/audit/export", web::get().to(audit_log_export))
+                    .route("/issues", web::get().to(issues_page))
+                    .route("/issues", web::post().to(create_issue))
+                    .route("/issues/new", web::get().to(new_issue_form))
+                    .route("/issues/{issue_id}", web::get().to(edit_issue_form))
+                    .route("/issues/{issue_id}", web::post().to(update_issue))
+                    .route("/issues/{issue_id}/delete", web::post().to(delete_issue))
+                    .route("/issues/{issue_id}/preview", web::get().to(preview_issue))
+                    .route("/issues/{issue_id}/report", web::get().to(issue_report))
+                    .route("/issues/{issue_id}/report/export", web::get().to(issue_report_export))
+                    .route("/issues/{issue_id}/publish", web::post().to(publish_issue))
+                    .route("/issues/{issue_id}/schedule", web::post().to(schedule_issue))
+                    .route("/issues/{issue_id}/unschedule", web::post().to(unschedule_issue))
+                    .route("/issues/{issue_id}/pause", web::post().to(pause_issue))
+                    .route("/issues/{issue_id}/resume", web::post().to(resume_issue))
+                    .route("/issues/{issue_id}/cancel", web::post().to(cancel_issue))
+                    .route("/issues/{issue_id}/make_public", web::post().to(make_issue_public))
+                    .route("/issues/{issue_id}/make_private", web::post().to(make_issue_private))
+                    .route("/templates", web::get().to(templates_page))
+                    .route("/templates", web::post().to(create_template))
+                    .route("/templates/new", web::get().to(new_template_form))
+                    .route("/templates/{template_id}", web::get().to(edit_template_form))
+                    .route("/templates/{template_id}", web::post().to(update_template))
+                    .route("/templates/{template_id}/delete", web::post().to(delete_template))
+                    .route("/suppressions", web::get().to(suppressions_page))
+                    .route("/suppressions", web::post().to(add_suppression))
+                    .route("/suppressions/remove", web::post().to(remove_suppression))
+                    .route("/sequence", web::get().to(sequence_page))
+                    .route("/sequence", web::post().to(create_sequence_step))
+                    .route("/sequence/{step_id}", web::get().to(edit_sequence_step_form))
+                    .route("/sequence/{step_id}", web::post().to(update_sequence_step))
+                    .route("/sequence/{step_id}/delete", web::post().to(delete_sequence_step))
             )
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(password_hash_settings.clone())
+            .app_data(session_settings.clone())
+            .app_data(tracking.clone())
+            .app_data(engagement_recorder.clone())
+            .app_data(postmark_webhook_settings.clone())
             .app_data(newsletter_subscription_service.clone())
     })
     .listen(listener)?
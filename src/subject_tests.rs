@@ -0,0 +1,525 @@
+use std::collections::HashSet;
+
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+use rand::{seq::SliceRandom, Rng};
+use uuid::Uuid;
+
+use crate::{issue_delivery_worker::DeliveryOutcome, issues::IssueStatus, models::{IssueDeliveryQueue, SubjectTest, SubjectVariant}, rendering::check_merge_tags};
+
+/// Queue state of the recipients outside the sample. The worker leaves them
+/// alone until the winning subject is known.
+pub const HELD_STATE: &str = "held";
+
+pub const DEFAULT_SAMPLE_PERCENT: i32 = 20;
+pub const DEFAULT_WAIT_MINUTES: i32 = 240;
+
+/// What decides the winning subject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WinningMetric {
+    Opens,
+    Clicks,
+}
+
+impl WinningMetric {
+    pub const ALL: [WinningMetric; 2] = [
+        WinningMetric::Opens,
+        WinningMetric::Clicks,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WinningMetric::Opens => "opens",
+            WinningMetric::Clicks => "clicks",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            WinningMetric::Opens => "open rate",
+            WinningMetric::Clicks => "click rate",
+        }
+    }
+}
+
+impl TryFrom<&str> for WinningMetric {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        WinningMetric::ALL
+            .into_iter()
+            .find(|metric| metric.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known winning metric.", s))
+    }
+}
+
+impl SubjectTest {
+    pub fn metric(&self) -> Result<WinningMetric, String> {
+        WinningMetric::try_from(self.metric.as_str())
+    }
+}
+
+/// The subject variants of an issue and how to pick between them, as an
+/// editor submitted them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectTestContent {
+    pub subjects: Vec<String>,
+    pub sample_percent: i32,
+    pub wait_minutes: i32,
+    pub metric: WinningMetric,
+}
+
+impl SubjectTestContent {
+    /// `subjects` holds one variant per line. Returns `None` when no variants
+    /// were given, in which case the issue is sent with its title as the
+    /// subject.
+    pub fn parse(
+        subjects: &str,
+        sample_percent: &str,
+        wait_minutes: &str,
+        metric: &str,
+        track_opens: bool,
+    ) -> Result<Option<Self>, String> {
+        let subjects: Vec<String> = subjects
+            .lines()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        match subjects.len() {
+            0 => return Ok(None),
+            1 => return Err("A subject test needs at least two subject variants.".to_string()),
+            _ => {}
+        }
+        if subjects.iter().collect::<HashSet<_>>().len() != subjects.len() {
+            return Err("The subject variants must all be different.".to_string());
+        }
+        for (i, subject) in subjects.iter().enumerate() {
+            check_merge_tags(subject, "", "").map_err(|e| format!("Subject variant {}: {}", i + 1, e))?;
+        }
+
+        let sample_percent = match sample_percent.trim() {
+            "" => DEFAULT_SAMPLE_PERCENT,
+            s => s.parse::<i32>()
+                .ok()
+                .filter(|p| (1..=100).contains(p))
+                .ok_or_else(|| "The sample size must be a percentage between 1 and 100.".to_string())?,
+        };
+        let wait_minutes = match wait_minutes.trim() {
+            "" => DEFAULT_WAIT_MINUTES,
+            s => s.parse::<i32>()
+                .ok()
+                .filter(|m| *m >= 0)
+                .ok_or_else(|| "The wait must be a whole number of minutes.".to_string())?,
+        };
+        let metric = match metric.trim() {
+            "" => WinningMetric::Opens,
+            m => WinningMetric::try_from(m)?,
+        };
+        if metric == WinningMetric::Opens && !track_opens {
+            return Err("Picking the winner by open rate needs open tracking.".to_string());
+        }
+
+        Ok(Some(Self { subjects, sample_percent, wait_minutes, metric }))
+    }
+}
+
+/// How one variant did with the sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantResult {
+    pub variant: SubjectVariant,
+    /// Sample recipients the variant was sent to.
+    pub sent: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+}
+
+impl VariantResult {
+    pub fn engaged(&self, metric: WinningMetric) -> i64 {
+        match metric {
+            WinningMetric::Opens => self.unique_opens,
+            WinningMetric::Clicks => self.unique_clicks,
+        }
+    }
+}
+
+/// The variant with the best rate. Ties, including a sample nobody engaged
+/// with, go to the variant listed first.
+pub fn choose_winner(results: &[VariantResult], metric: WinningMetric) -> Option<&VariantResult> {
+    results.iter().fold(None, |best: Option<&VariantResult>, result| match best {
+        // Compares engaged / sent without dividing, so an empty sample
+        // counts as a rate of zero.
+        Some(best) if result.engaged(metric) * best.sent.max(1) <= best.engaged(metric) * result.sent.max(1) => Some(best),
+        _ => Some(result),
+    })
+}
+
+/// Replaces the subject test of an issue, or removes it when `test` is `None`.
+/// Only for issues that have not been published; variants that were sent are
+/// referenced by deliveries.
+pub fn save_subject_test(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    test: Option<&SubjectTestContent>,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{subject_tests, subject_variants};
+
+    diesel::delete(subject_tests::table.filter(subject_tests::newsletter_issue_id.eq(issue_id)))
+        .execute(conn)
+        .context("Failed to delete subject test")?;
+    diesel::delete(subject_variants::table.filter(subject_variants::newsletter_issue_id.eq(issue_id)))
+        .execute(conn)
+        .context("Failed to delete subject variants")?;
+
+    let Some(test) = test else {
+        return Ok(());
+    };
+    diesel::insert_into(subject_tests::table)
+        .values(SubjectTest {
+            newsletter_issue_id: issue_id,
+            sample_percent: test.sample_percent,
+            wait_minutes: test.wait_minutes,
+            metric: test.metric.as_str().to_string(),
+            winning_variant_id: None,
+            decided_at: None,
+        })
+        .execute(conn)
+        .context("Failed to insert subject test")?;
+    let variants: Vec<SubjectVariant> = test.subjects
+        .iter()
+        .enumerate()
+        .map(|(position, subject)| SubjectVariant {
+            subject_variant_id: Uuid::new_v4(),
+            newsletter_issue_id: issue_id,
+            position: position as i32,
+            subject: subject.clone(),
+        })
+        .collect();
+    diesel::insert_into(subject_variants::table)
+        .values(&variants)
+        .execute(conn)
+        .context("Failed to insert subject variants")?;
+
+    Ok(())
+}
+
+/// Blocking lookup for callers that already hold a connection. Variants come
+/// in the order the editor listed them.
+pub fn find_subject_test(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Option<(SubjectTest, Vec<SubjectVariant>)>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{subject_tests, subject_variants};
+
+    let Some(test) = subject_tests::table
+        .filter(subject_tests::newsletter_issue_id.eq(issue_id))
+        .first::<SubjectTest>(conn)
+        .optional()
+        .context("Failed to fetch subject test")?
+    else {
+        return Ok(None);
+    };
+    let variants = subject_variants::table
+        .filter(subject_variants::newsletter_issue_id.eq(issue_id))
+        .order(subject_variants::position.asc())
+        .load::<SubjectVariant>(conn)
+        .context("Failed to fetch subject variants")?;
+
+    Ok(Some((test, variants)))
+}
+
+#[tracing::instrument(name = "Get subject test", skip(pool))]
+pub async fn get_subject_test(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Option<(SubjectTest, Vec<SubjectVariant>)>, anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let test = web::block(move || {
+        current_span.in_scope(|| find_subject_test(&mut conn, issue_id))
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(test)
+}
+
+pub fn find_subject_variant(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    variant_id: Uuid,
+) -> Result<SubjectVariant, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::subject_variants;
+
+    subject_variants::table
+        .filter(subject_variants::subject_variant_id.eq(variant_id))
+        .first::<SubjectVariant>(conn)
+        .context("Failed to fetch subject variant")
+}
+
+/// Queue entries for an issue with a subject test: a random sample of the
+/// recipients is split evenly between the variants, everybody else is held
+/// back for the winner.
+pub fn sample_recipients(
+    test: &SubjectTest,
+    variants: &[SubjectVariant],
+    mut emails: Vec<String>,
+    rng: &mut impl Rng,
+) -> Vec<IssueDeliveryQueue> {
+    emails.shuffle(rng);
+    let sample_size = (emails.len() * test.sample_percent as usize).div_ceil(100);
+
+    emails
+        .into_iter()
+        .enumerate()
+        .map(|(i, subscriber_email)| {
+            let variant = (i < sample_size).then(|| &variants[i % variants.len()]);
+            IssueDeliveryQueue {
+                newsletter_issue_id: test.newsletter_issue_id,
+                subscriber_email,
+                state: variant.is_none().then(|| HELD_STATE.to_string()),
+                subject_variant_id: variant.map(|v| v.subject_variant_id),
+            }
+        })
+        .collect()
+}
+
+fn load_variant_results(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+    variants: Vec<SubjectVariant>,
+) -> Result<Vec<VariantResult>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{issue_clicks, issue_deliveries, issue_opens, subscriptions};
+
+    let deliveries = issue_deliveries::table
+        .filter(issue_deliveries::newsletter_issue_id.eq(issue_id))
+        .filter(issue_deliveries::outcome.eq(DeliveryOutcome::Sent.as_str()))
+        .filter(issue_deliveries::subject_variant_id.is_not_null())
+        .select((issue_deliveries::subject_variant_id.assume_not_null(), issue_deliveries::subscriber_email))
+        .load::<(Uuid, String)>(conn)
+        .context("Failed to fetch deliveries")?;
+    let openers: HashSet<String> = issue_opens::table
+        .inner_join(subscriptions::table)
+        .filter(issue_opens::newsletter_issue_id.eq(issue_id))
+        .select(subscriptions::email)
+        .load::<String>(conn)
+        .context("Failed to fetch opens")?
+        .into_iter()
+        .collect();
+    let clickers: HashSet<String> = issue_clicks::table
+        .inner_join(subscriptions::table)
+        .filter(issue_clicks::newsletter_issue_id.eq(issue_id))
+        .select(subscriptions::email)
+        .distinct()
+        .load::<String>(conn)
+        .context("Failed to fetch clicks")?
+        .into_iter()
+        .collect();
+
+    Ok(variants
+        .into_iter()
+        .map(|variant| {
+            let recipients: Vec<&String> = deliveries
+                .iter()
+                .filter(|(variant_id, _)| *variant_id == variant.subject_variant_id)
+                .map(|(_, email)| email)
+                .collect();
+            VariantResult {
+                sent: recipients.len() as i64,
+                unique_opens: recipients.iter().filter(|e| openers.contains(**e)).count() as i64,
+                unique_clicks: recipients.iter().filter(|e| clickers.contains(**e)).count() as i64,
+                variant,
+            }
+        })
+        .collect())
+}
+
+/// The subject test of an issue along with how each variant has done so far.
+#[tracing::instrument(name = "Get subject test results", skip(pool))]
+pub async fn get_subject_test_results(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    issue_id: Uuid,
+) -> Result<Option<(SubjectTest, Vec<VariantResult>)>, anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            let Some((test, variants)) = find_subject_test(&mut conn, issue_id)? else {
+                return Ok(None);
+            };
+            let results = load_variant_results(&mut conn, issue_id, variants)?;
+            Ok(Some((test, results)))
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")?
+}
+
+/// Picks the winning subject of every sending issue whose sample has gone out
+/// and whose wait is over, and releases the held recipients with it. Returns
+/// the ids of the issues decided.
+///
+/// Tests are locked with `SKIP LOCKED`, so each is decided exactly once even
+/// with several schedulers running.
+#[tracing::instrument(skip_all)]
+pub fn pick_subject_test_winners(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    use diesel::dsl::exists;
+    use diesel::prelude::*;
+    use crate::schema::{issue_delivery_queue, newsletter_issues, subject_tests};
+
+    conn.transaction(|conn| {
+        let now = Utc::now();
+        let sending_issues = newsletter_issues::table
+            .filter(newsletter_issues::status.eq(IssueStatus::Sending.as_str()))
+            .select(newsletter_issues::newsletter_issue_id);
+        let undecided = subject_tests::table
+            .filter(subject_tests::decided_at.is_null())
+            .filter(subject_tests::newsletter_issue_id.eq_any(sending_issues))
+            .for_update()
+            .skip_locked()
+            .load::<SubjectTest>(conn)?;
+
+        let mut decided = Vec::new();
+        for test in undecided {
+            let issue_id = test.newsletter_issue_id;
+            let published_at = newsletter_issues::table
+                .filter(newsletter_issues::newsletter_issue_id.eq(issue_id))
+                .select(newsletter_issues::published_at)
+                .first::<Option<DateTime<Utc>>>(conn)?;
+            let due = published_at.is_some_and(|t| t + TimeDelta::minutes(test.wait_minutes.into()) <= now);
+            // The whole sample has to be out before it can be judged.
+            let sample_queued = diesel::select(exists(
+                issue_delivery_queue::table
+                    .filter(issue_delivery_queue::newsletter_issue_id.eq(issue_id))
+                    .filter(issue_delivery_queue::state.is_null())
+            ))
+            .get_result::<bool>(conn)?;
+            if !due || sample_queued {
+                continue;
+            }
+
+            let Some((_, variants)) = find_subject_test(conn, issue_id)? else {
+                continue;
+            };
+            let results = load_variant_results(conn, issue_id, variants)?;
+            let winner = choose_winner(&results, test.metric().map_err(anyhow::Error::msg)?)
+                .context("A subject test has no variants")?
+                .variant
+                .subject_variant_id;
+
+            diesel::update(subject_tests::table.filter(subject_tests::newsletter_issue_id.eq(issue_id)))
+                .set((
+                    subject_tests::winning_variant_id.eq(winner),
+                    subject_tests::decided_at.eq(now),
+                ))
+                .execute(conn)?;
+            diesel::update(
+                issue_delivery_queue::table
+                    .filter(issue_delivery_queue::newsletter_issue_id.eq(issue_id))
+                    .filter(issue_delivery_queue::state.eq(HELD_STATE))
+            )
+            .set((
+                issue_delivery_queue::state.eq(None::<String>),
+                issue_delivery_queue::subject_variant_id.eq(winner),
+            ))
+            .execute(conn)?;
+
+            tracing::info!(newsletter_issue_id = %issue_id, subject_variant_id = %winner, "Picked the winning subject");
+            decided.push(issue_id);
+        }
+
+        Ok(decided)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use uuid::Uuid;
+
+    use crate::models::{SubjectTest, SubjectVariant};
+
+    use super::{choose_winner, sample_recipients, SubjectTestContent, VariantResult, WinningMetric, HELD_STATE};
+
+    fn variant(position: i32) -> SubjectVariant {
+        SubjectVariant {
+            subject_variant_id: Uuid::new_v4(),
+            newsletter_issue_id: Uuid::nil(),
+            position,
+            subject: format!("Subject {}", position),
+        }
+    }
+
+    fn result(position: i32, sent: i64, unique_opens: i64) -> VariantResult {
+        VariantResult { variant: variant(position), sent, unique_opens, unique_clicks: 0 }
+    }
+
+    #[test]
+    fn variants_are_parsed_one_per_line() {
+        let test = SubjectTestContent::parse("First\n\n  Second  \n", "", "", "", true).unwrap().unwrap();
+        assert_eq!(test.subjects, vec!["First", "Second"]);
+        assert_eq!(test.metric, WinningMetric::Opens);
+
+        assert_eq!(SubjectTestContent::parse("  \n", "50", "10", "clicks", false), Ok(None));
+    }
+
+    #[test]
+    fn invalid_subject_tests_are_rejected() {
+        for (subjects, sample_percent, wait_minutes, metric, track_opens) in [
+            ("Only one", "", "", "", true),
+            ("Same\nSame", "", "", "", true),
+            ("Hi {{nickname}}\nHello", "", "", "", true),
+            ("A\nB", "0", "", "", true),
+            ("A\nB", "101", "", "", true),
+            ("A\nB", "", "-1", "", true),
+            ("A\nB", "", "", "replies", true),
+            ("A\nB", "", "", "opens", false),
+        ] {
+            assert!(SubjectTestContent::parse(subjects, sample_percent, wait_minutes, metric, track_opens).is_err());
+        }
+    }
+
+    #[test]
+    fn the_best_rate_wins_and_ties_go_to_the_first_variant() {
+        let results = [result(0, 10, 2), result(1, 4, 1), result(2, 5, 2)];
+        assert_eq!(choose_winner(&results, WinningMetric::Opens).unwrap().variant.position, 2);
+
+        let results = [result(0, 10, 0), result(1, 0, 0)];
+        assert_eq!(choose_winner(&results, WinningMetric::Opens).unwrap().variant.position, 0);
+        assert_eq!(choose_winner(&results, WinningMetric::Clicks).unwrap().variant.position, 0);
+    }
+
+    #[test]
+    fn the_sample_is_split_between_variants_and_the_rest_held() {
+        let variants = [variant(0), variant(1)];
+        let test = SubjectTest {
+            newsletter_issue_id: Uuid::nil(),
+            sample_percent: 25,
+            wait_minutes: 60,
+            metric: "opens".to_string(),
+            winning_variant_id: None,
+            decided_at: None,
+        };
+        let emails = (0..10).map(|i| format!("{}@example.com", i)).collect();
+
+        let entries = sample_recipients(&test, &variants, emails, &mut StdRng::seed_from_u64(42));
+        assert_eq!(entries.len(), 10);
+        // 25% of 10 rounds up to 3.
+        let sampled: Vec<_> = entries.iter().filter_map(|e| e.subject_variant_id).collect();
+        assert_eq!(sampled.len(), 3);
+        assert_eq!(sampled.iter().filter(|id| **id == variants[0].subject_variant_id).count(), 2);
+        assert!(entries
+            .iter()
+            .filter(|e| e.subject_variant_id.is_none())
+            .all(|e| e.state.as_deref() == Some(HELD_STATE)));
+    }
+}
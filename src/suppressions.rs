@@ -0,0 +1,177 @@
+use actix_web::web;
+use anyhow::Context;
+use chrono::Utc;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+
+use crate::models::Suppression;
+
+diesel::define_sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+/// Why an address must not be mailed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuppressionReason {
+    HardBounce,
+    SpamComplaint,
+    Unsubscribed,
+    /// Added by an admin.
+    Manual,
+}
+
+impl SuppressionReason {
+    pub const ALL: [SuppressionReason; 4] = [
+        SuppressionReason::HardBounce,
+        SuppressionReason::SpamComplaint,
+        SuppressionReason::Unsubscribed,
+        SuppressionReason::Manual,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "hard_bounce",
+            SuppressionReason::SpamComplaint => "spam_complaint",
+            SuppressionReason::Unsubscribed => "unsubscribed",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+}
+
+impl TryFrom<&str> for SuppressionReason {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        SuppressionReason::ALL
+            .into_iter()
+            .find(|reason| reason.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known suppression reason.", s))
+    }
+}
+
+/// Blocking insert for callers that already hold a connection, like the
+/// webhook and unsubscribe transactions. An address that is already
+/// suppressed keeps its original reason; returns `false` in that case.
+pub fn suppress(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    address: &str,
+    reason: SuppressionReason,
+    source: &str,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::suppressions;
+
+    let rows_affected = diesel::insert_into(suppressions::table)
+        .values(Suppression {
+            email: address.trim().to_lowercase(),
+            reason: reason.as_str().to_string(),
+            source: source.to_string(),
+            created_at: Utc::now(),
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("Failed to insert suppression")?;
+
+    Ok(rows_affected > 0)
+}
+
+/// Blocking lookup for callers that already hold a connection, like the
+/// delivery worker.
+pub fn find_suppression(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    address: &str,
+) -> Result<Option<Suppression>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::suppressions;
+
+    suppressions::table
+        .filter(suppressions::email.eq(address.trim().to_lowercase()))
+        .first::<Suppression>(conn)
+        .optional()
+        .context("Failed to fetch suppression")
+}
+
+#[tracing::instrument(name = "Get suppression", skip(pool))]
+pub async fn get_suppression(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    address: String,
+) -> Result<Option<Suppression>, anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let suppression = web::block(move || {
+        current_span.in_scope(|| find_suppression(&mut conn, &address))
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(suppression)
+}
+
+/// Returns the suppression list, newest first.
+#[tracing::instrument(name = "Get suppressions", skip(pool))]
+pub async fn get_suppressions(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Suppression>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::suppressions::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows = web::block(move || {
+        current_span.in_scope(|| {
+            suppressions
+                .order((created_at.desc(), email.asc()))
+                .load::<Suppression>(&mut conn)
+                .context("Failed to fetch suppressions")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows)
+}
+
+/// Returns `false` if the address was already suppressed.
+#[tracing::instrument(name = "Add suppression", skip(pool))]
+pub async fn add_suppression(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    address: String,
+    reason: SuppressionReason,
+    source: String,
+) -> Result<bool, anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let added = web::block(move || {
+        current_span.in_scope(|| suppress(&mut conn, &address, reason, &source))
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(added)
+}
+
+/// Lets the address be mailed again. Returns `false` if it was not
+/// suppressed.
+#[tracing::instrument(name = "Remove suppression", skip(pool))]
+pub async fn remove_suppression(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    address: String,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::suppressions::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::delete(suppressions.filter(email.eq(address.trim().to_lowercase())))
+                .execute(&mut conn)
+                .context("Failed to delete suppression")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
@@ -0,0 +1,306 @@
+use actix_web::web;
+use anyhow::Context;
+use chrono::Utc;
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::{models::Template, rendering::{render_confirmation, render_issue, Recipient}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateKind {
+    /// A layout wrapped around the content of newsletter issues.
+    Issue,
+    /// The email asking new subscribers to confirm their address.
+    Confirmation,
+}
+
+impl TemplateKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateKind::Issue => "issue",
+            TemplateKind::Confirmation => "confirmation",
+        }
+    }
+}
+
+impl TryFrom<&str> for TemplateKind {
+    type Error = String;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "issue" => Ok(TemplateKind::Issue),
+            "confirmation" => Ok(TemplateKind::Confirmation),
+            other => Err(format!("{} is not a known template kind.", other)),
+        }
+    }
+}
+
+impl Template {
+    pub fn kind(&self) -> TemplateKind {
+        // The database only accepts known kinds, see the check constraint on
+        // `templates.kind`.
+        TemplateKind::try_from(self.kind.as_str()).expect("Unknown template kind")
+    }
+}
+
+#[derive(Debug)]
+pub struct TemplateContent {
+    pub name: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl TemplateContent {
+    /// Checks that every part of the template compiles and renders against a
+    /// sample recipient, so mistakes surface when saving rather than when the
+    /// worker is halfway through a send.
+    pub fn parse(
+        kind: TemplateKind,
+        name: String,
+        subject: String,
+        html: String,
+        text: String,
+    ) -> Result<Self, String> {
+        if name.trim().is_empty() {
+            return Err("The name cannot be empty.".to_string());
+        }
+
+        let content = Self { name, subject, html, text };
+        let template = Template {
+            template_id: Uuid::nil(),
+            name: content.name.clone(),
+            kind: kind.as_str().to_string(),
+            subject: content.subject.clone(),
+            html: content.html.clone(),
+            text: content.text.clone(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        match kind {
+            TemplateKind::Issue => {
+                const MARKER: &str = "__issue_content__";
+                let issue = crate::models::NewsletterIssue {
+                    newsletter_issue_id: Uuid::nil(),
+                    title: "Sample issue".to_string(),
+                    text: MARKER.to_string(),
+                    html: MARKER.to_string(),
+                    published_at: None,
+                    status: "draft".to_string(),
+                    created_by: None,
+                    updated_at: Utc::now(),
+                    scheduled_for: None,
+                    markdown: None,
+                    template_id: None,
+                    track_opens: false,
+                    slug: None,
+                    is_public: false,
+                    schedule_error: None,
+                };
+                let rendered = render_issue(&issue, Some(&template), &Recipient::sample(""))
+                    .map_err(|e| format!("The template is invalid: {}", e))?;
+                if !rendered.html.contains(MARKER) || !rendered.text.contains(MARKER) {
+                    return Err("Both the HTML and plain-text layouts must include {{content}}.".to_string());
+                }
+            }
+            TemplateKind::Confirmation => {
+                const MARKER: &str = "__confirmation_url__";
+                let rendered = render_confirmation(&template, &Recipient::sample(""), MARKER)
+                    .map_err(|e| format!("The template is invalid: {}", e))?;
+                if !rendered.html.contains(MARKER) || !rendered.text.contains(MARKER) {
+                    return Err("Both the HTML and plain-text bodies must include {{confirmation_url}}.".to_string());
+                }
+            }
+        }
+
+        Ok(content)
+    }
+}
+
+/// Returns the templates of the given kind, ordered by name.
+#[tracing::instrument(name = "Get templates", skip(pool))]
+pub async fn get_templates(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    template_kind: Option<TemplateKind>,
+) -> Result<Vec<Template>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::templates::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows = web::block(move || {
+        current_span.in_scope(|| {
+            let mut query = templates.order(name.asc()).into_boxed();
+            if let Some(k) = template_kind {
+                query = query.filter(kind.eq(k.as_str()));
+            }
+            query
+                .load::<Template>(&mut conn)
+                .context("Failed to fetch templates")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows)
+}
+
+#[tracing::instrument(name = "Get template", skip(pool))]
+pub async fn get_template(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    id: Uuid,
+) -> Result<Option<Template>, anyhow::Error> {
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let template = web::block(move || {
+        current_span.in_scope(|| find_template(&mut conn, id))
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(template)
+}
+
+/// Blocking lookup for callers that already hold a connection, like the
+/// delivery worker.
+pub fn find_template(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    id: Uuid,
+) -> Result<Option<Template>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::templates::dsl::*;
+
+    templates
+        .filter(template_id.eq(id))
+        .first::<Template>(conn)
+        .optional()
+        .context("Failed to fetch template")
+}
+
+#[tracing::instrument(name = "Get confirmation template", skip(pool))]
+pub async fn get_confirmation_template(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<Template, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::templates::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let template = web::block(move || {
+        current_span.in_scope(|| {
+            templates
+                .filter(kind.eq(TemplateKind::Confirmation.as_str()))
+                .first::<Template>(&mut conn)
+                .context("Failed to fetch the confirmation template")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(template)
+}
+
+#[tracing::instrument(name = "Create template", skip(pool, content))]
+pub async fn create_template(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    content: TemplateContent,
+) -> Result<Uuid, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::templates::dsl::*;
+
+    let now = Utc::now();
+    let template = Template {
+        template_id: Uuid::new_v4(),
+        name: content.name,
+        kind: TemplateKind::Issue.as_str().to_string(),
+        subject: content.subject,
+        html: content.html,
+        text: content.text,
+        created_at: now,
+        updated_at: now,
+    };
+    let new_template_id = template.template_id;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            diesel::insert_into(templates)
+                .values(template)
+                .execute(&mut conn)
+                .context("Failed to insert template")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(new_template_id)
+}
+
+/// Returns `false` if there is no such template.
+#[tracing::instrument(name = "Update template", skip(pool, content))]
+pub async fn update_template(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    id: Uuid,
+    content: TemplateContent,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::templates::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::update(templates.filter(template_id.eq(id)))
+                .set((
+                    name.eq(content.name),
+                    subject.eq(content.subject),
+                    html.eq(content.html),
+                    text.eq(content.text),
+                ))
+                .execute(&mut conn)
+                .context("Failed to update template")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+/// Issues using the template fall back to being sent without a layout.
+/// Returns `false` if there is no such issue template; the confirmation
+/// template cannot be deleted.
+#[tracing::instrument(name = "Delete template", skip(pool))]
+pub async fn delete_template(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::templates::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::delete(
+                templates
+                    .filter(template_id.eq(id))
+                    .filter(kind.eq(TemplateKind::Issue.as_str()))
+            )
+            .execute(&mut conn)
+            .context("Failed to delete template")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
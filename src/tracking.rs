@@ -0,0 +1,377 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use hmac::{Hmac, Mac};
+use linkify::{LinkFinder, LinkKind};
+use lol_html::{element, errors::RewritingError, rewrite_str, RewriteStrSettings};
+use r2d2::Pool;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{configuration::TrackingSettings, models::{IssueClickAdd, IssueOpenAdd, NewsletterIssue}, rendering::RenderedEmail, utils::html_unescape};
+
+/// Kept apart from other signed tokens, so one kind cannot be passed off as
+/// another.
+const OPEN_TOKEN: &str = "open";
+const CLICK_TOKEN: &str = "click";
+
+/// Builds and checks the signed URLs that let us tell which recipient opened
+/// an issue or followed one of its links. Tokens are signed with
+/// `hmac_secret`, so nobody can record engagement on behalf of somebody else
+/// or use the click tracker to redirect to an address of their choosing.
+#[derive(Clone)]
+pub struct Tracking {
+    hmac_secret: Secret<String>,
+    open_tracking: bool,
+    click_tracking: bool,
+}
+
+impl Tracking {
+    pub fn new(hmac_secret: Secret<String>, settings: &TrackingSettings) -> Self {
+        Self {
+            hmac_secret,
+            open_tracking: settings.open_tracking,
+            click_tracking: settings.click_tracking,
+        }
+    }
+
+    /// Adds whatever tracking the issue and the configuration ask for to an
+    /// email rendered for one subscriber.
+    pub fn add_tracking(
+        &self,
+        mut email: RenderedEmail,
+        issue: &NewsletterIssue,
+        subscriber_id: Uuid,
+        base_url: &str,
+    ) -> Result<RenderedEmail, RewritingError> {
+        let issue_id = issue.newsletter_issue_id;
+        if self.click_tracking {
+            let track = |url: &str| self.click_url(base_url, issue_id, subscriber_id, url);
+            email.html = track_html_links(&email.html, base_url, &track)?;
+            email.text = track_text_links(&email.text, base_url, &track);
+        }
+        if self.tracks_opens(issue) {
+            let pixel_url = self.open_pixel_url(base_url, issue_id, subscriber_id);
+            email.html = add_open_pixel(&email.html, &pixel_url);
+        }
+        Ok(email)
+    }
+
+    /// Opens are tracked only for issues that opted in, and only while open
+    /// tracking is enabled in the configuration.
+    pub fn tracks_opens(&self, issue: &NewsletterIssue) -> bool {
+        self.open_tracking && issue.track_opens
+    }
+
+    pub fn open_pixel_url(&self, base_url: &str, issue_id: Uuid, subscriber_id: Uuid) -> String {
+        let payload = [issue_id.as_bytes().as_slice(), subscriber_id.as_bytes()].concat();
+        format!("{}/t/open/{}", base_url, self.sign(OPEN_TOKEN, &payload))
+    }
+
+    /// Returns the issue and subscriber an open token was issued for, or
+    /// `None` if it was not signed by us.
+    pub fn verify_open_token(&self, token: &str) -> Option<(Uuid, Uuid)> {
+        let payload = self.verify(OPEN_TOKEN, token)?;
+        if payload.len() != 32 {
+            return None;
+        }
+        Some((
+            Uuid::from_slice(&payload[..16]).ok()?,
+            Uuid::from_slice(&payload[16..]).ok()?,
+        ))
+    }
+
+    pub fn click_url(&self, base_url: &str, issue_id: Uuid, subscriber_id: Uuid, url: &str) -> String {
+        let payload = [issue_id.as_bytes().as_slice(), subscriber_id.as_bytes(), url.as_bytes()].concat();
+        format!("{}/t/click/{}", base_url, self.sign(CLICK_TOKEN, &payload))
+    }
+
+    /// Returns the issue, subscriber and destination a click token was issued
+    /// for, or `None` if it was not signed by us.
+    pub fn verify_click_token(&self, token: &str) -> Option<(Uuid, Uuid, String)> {
+        let payload = self.verify(CLICK_TOKEN, token)?;
+        if payload.len() <= 32 {
+            return None;
+        }
+        Some((
+            Uuid::from_slice(&payload[..16]).ok()?,
+            Uuid::from_slice(&payload[16..32]).ok()?,
+            String::from_utf8(payload[32..].to_vec()).ok()?,
+        ))
+    }
+
+    fn sign(&self, kind: &str, payload: &[u8]) -> String {
+        let signature = self.mac(kind, payload).finalize().into_bytes();
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn verify(&self, kind: &str, token: &str) -> Option<Vec<u8>> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(kind, &payload).verify_slice(&signature).ok()?;
+        Some(payload)
+    }
+
+    fn mac(&self, kind: &str, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(kind.as_bytes());
+        mac.update(b":");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// Only absolute web links are tracked. Links back to us, like the
+/// unsubscribe link, are left alone.
+fn is_trackable(url: &str, base_url: &str) -> bool {
+    let lowercase = url.to_ascii_lowercase();
+    (lowercase.starts_with("http://") || lowercase.starts_with("https://"))
+        && !url.starts_with(base_url)
+}
+
+fn track_html_links(
+    html: &str,
+    base_url: &str,
+    track: &dyn Fn(&str) -> String,
+) -> Result<String, RewritingError> {
+    rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: vec![element!("a[href]", |el| {
+            let href = html_unescape(&el.get_attribute("href").unwrap_or_default());
+            let href = href.trim();
+            if is_trackable(href, base_url) {
+                el.set_attribute("href", &track(href))?;
+            }
+            Ok(())
+        })],
+        ..RewriteStrSettings::new()
+    })
+}
+
+fn track_text_links(text: &str, base_url: &str, track: &dyn Fn(&str) -> String) -> String {
+    let mut tracked = String::with_capacity(text.len());
+    let mut last = 0;
+    for link in LinkFinder::new().kinds(&[LinkKind::Url]).links(text) {
+        tracked.push_str(&text[last..link.start()]);
+        if is_trackable(link.as_str(), base_url) {
+            tracked.push_str(&track(link.as_str()));
+        } else {
+            tracked.push_str(link.as_str());
+        }
+        last = link.end();
+    }
+    tracked.push_str(&text[last..]);
+    tracked
+}
+
+/// Appends an invisible image loading `pixel_url` to the end of the body.
+pub fn add_open_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="" style="display: block; border: 0; width: 1px; height: 1px">"#,
+        pixel_url
+    );
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(i) => format!("{}{}{}", &html[..i], pixel, &html[i..]),
+        None => format!("{}{}", html, pixel),
+    }
+}
+
+#[derive(Debug)]
+pub enum EngagementEvent {
+    Open {
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        user_agent: Option<String>,
+        at: DateTime<Utc>,
+    },
+    Click {
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: String,
+        user_agent: Option<String>,
+        at: DateTime<Utc>,
+    },
+}
+
+/// Hands engagement events to a background thread that stores them, so the
+/// tracking endpoints can answer without waiting on the database.
+#[derive(Clone)]
+pub struct EngagementRecorder {
+    sender: SyncSender<EngagementEvent>,
+}
+
+impl EngagementRecorder {
+    /// Events beyond this many unsaved ones are dropped rather than slowing
+    /// down the endpoints.
+    const CAPACITY: usize = 10_000;
+
+    pub fn spawn(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        let (sender, receiver) = sync_channel(Self::CAPACITY);
+        std::thread::spawn(move || store_events(pool, receiver));
+        Self { sender }
+    }
+
+    pub fn record(&self, event: EngagementEvent) {
+        match self.sender.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => {
+                tracing::warn!(?event, "Too many engagement events waiting to be stored. Dropping one.");
+            }
+            Err(TrySendError::Disconnected(event)) => {
+                tracing::error!(?event, "The engagement recorder has stopped. Dropping an event.");
+            }
+        }
+    }
+}
+
+/// Runs until every `EngagementRecorder` handle has been dropped.
+fn store_events(
+    pool: Pool<ConnectionManager<PgConnection>>,
+    receiver: Receiver<EngagementEvent>,
+) {
+    for event in receiver {
+        if let Err(e) = store_event(&pool, event) {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to store an engagement event",
+            );
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+fn store_event(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    event: EngagementEvent,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{issue_clicks, issue_opens};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+
+    match event {
+        EngagementEvent::Open { issue_id, subscriber_id, user_agent, at } => {
+            // The user agent of the first open is kept; later opens only
+            // bump the count.
+            diesel::insert_into(issue_opens::table)
+                .values(IssueOpenAdd {
+                    newsletter_issue_id: issue_id,
+                    subscriber_id,
+                    first_opened_at: at,
+                    last_opened_at: at,
+                    open_count: 1,
+                    user_agent,
+                })
+                .on_conflict((issue_opens::newsletter_issue_id, issue_opens::subscriber_id))
+                .do_update()
+                .set((
+                    issue_opens::open_count.eq(issue_opens::open_count + 1),
+                    issue_opens::last_opened_at.eq(at),
+                ))
+                .execute(&mut conn)
+                .context("Failed to record an open")?;
+        }
+        EngagementEvent::Click { issue_id, subscriber_id, url, user_agent, at } => {
+            diesel::insert_into(issue_clicks::table)
+                .values(IssueClickAdd {
+                    issue_click_id: Uuid::new_v4(),
+                    newsletter_issue_id: issue_id,
+                    subscriber_id,
+                    url,
+                    user_agent,
+                    clicked_at: at,
+                })
+                .execute(&mut conn)
+                .context("Failed to record a click")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use crate::configuration::TrackingSettings;
+    use crate::tracking::{add_open_pixel, track_html_links, track_text_links, Tracking};
+
+    fn tracking(secret: &str) -> Tracking {
+        Tracking::new(
+            Secret::new(secret.to_string()),
+            &TrackingSettings { open_tracking: true, click_tracking: true },
+        )
+    }
+
+    fn token(url: &str) -> &str {
+        url.rsplit('/').next().unwrap()
+    }
+
+    #[test]
+    fn open_tokens_round_trip() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let url = tracking("secret").open_pixel_url("https://example.com", issue_id, subscriber_id);
+        assert!(url.starts_with("https://example.com/t/open/"));
+        assert_eq!(tracking("secret").verify_open_token(token(&url)), Some((issue_id, subscriber_id)));
+    }
+
+    #[test]
+    fn tampered_or_foreign_open_tokens_are_rejected() {
+        let url = tracking("secret").open_pixel_url("", Uuid::new_v4(), Uuid::new_v4());
+        let (payload, signature) = token(&url).split_once('.').unwrap();
+        let other = tracking("secret").open_pixel_url("", Uuid::new_v4(), Uuid::new_v4());
+        let (other_payload, _) = token(&other).split_once('.').unwrap();
+
+        assert_eq!(tracking("another secret").verify_open_token(token(&url)), None);
+        assert_eq!(tracking("secret").verify_open_token(&format!("{}.{}", other_payload, signature)), None);
+        assert_eq!(tracking("secret").verify_open_token(payload), None);
+    }
+
+    #[test]
+    fn the_pixel_goes_at_the_end_of_the_body() {
+        assert_eq!(
+            add_open_pixel("<html><body><p>Hi</p></BODY></html>", "https://example.com/t/open/x"),
+            r#"<html><body><p>Hi</p><img src="https://example.com/t/open/x" width="1" height="1" alt="" style="display: block; border: 0; width: 1px; height: 1px"></BODY></html>"#
+        );
+        assert!(add_open_pixel("<p>Hi</p>", "x").starts_with(r#"<p>Hi</p><img src="x""#));
+    }
+
+    #[test]
+    fn click_tokens_carry_the_destination() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let url = tracking("secret").click_url("", issue_id, subscriber_id, "https://example.com/?a=1&b=2");
+        assert_eq!(
+            tracking("secret").verify_click_token(token(&url)),
+            Some((issue_id, subscriber_id, "https://example.com/?a=1&b=2".to_string()))
+        );
+        // An open token is not a click token, even though both are signed.
+        let open_url = tracking("secret").open_pixel_url("", issue_id, subscriber_id);
+        assert_eq!(tracking("secret").verify_click_token(token(&open_url)), None);
+    }
+
+    #[test]
+    fn absolute_links_are_tracked_in_both_parts() {
+        // Stands in for the signed URL; the length shows `&amp;` was decoded.
+        let track = |url: &str| format!("https://us.test/t/click/{}", url.len());
+        let html = track_html_links(
+            r#"<a href="https://example.com/?a=1&amp;b=2">x</a><a href="mailto:me@example.com">y</a><a href="https://us.test/subscriptions/unsubscribe">z</a>"#,
+            "https://us.test",
+            &track,
+        )
+        .unwrap();
+        assert_eq!(
+            html,
+            r#"<a href="https://us.test/t/click/28">x</a><a href="mailto:me@example.com">y</a><a href="https://us.test/subscriptions/unsubscribe">z</a>"#
+        );
+
+        let text = track_text_links("Read https://example.com/post. Or not.", "https://us.test", &track);
+        assert_eq!(text, "Read https://us.test/t/click/24. Or not.");
+    }
+}
@@ -5,13 +5,18 @@ use crate::{domain::new_subscriber::NewSubscriber, models::SubscribeFormData, ro
 pub trait SubscriptionRepository {
     fn confirm_subscriber(&self, subscription_token: &str) -> impl Future<Output = Result<(), anyhow::Error>> + Send + Sync;
     fn insert_subscriber(&self, form: &NewSubscriber) -> impl Future<Output = Result<String, InsertSubscriberError>> + Send + Sync;
+    /// Returns `false` if the token does not belong to any subscriber.
+    fn unsubscribe_subscriber(&self, subscription_token: &str) -> impl Future<Output = Result<bool, anyhow::Error>> + Send + Sync;
+    /// Whether the address is on the suppression list.
+    fn is_suppressed(&self, email: &str) -> impl Future<Output = Result<bool, anyhow::Error>> + Send + Sync;
 }
 
 pub trait EmailSender {
-    fn send_confirmation(&self, subscriber: &NewSubscriber, confirmation_token: &String) -> impl Future<Output = Result<(), reqwest::Error>> + Send + Sync;
+    fn send_confirmation(&self, subscriber: &NewSubscriber, confirmation_token: &str) -> impl Future<Output = Result<(), anyhow::Error>> + Send + Sync;
 }
 
 pub trait SubscriptionService {
     fn create_subscription(&self, form: SubscribeFormData) -> impl Future<Output = Result<(), SubscribeError>> + Send + Sync;
     fn confirm_subscription(&self, subscription_token: &str) -> impl Future<Output = Result<(), String>> + Send + Sync;
+    fn unsubscribe(&self, subscription_token: &str) -> impl Future<Output = Result<bool, String>> + Send + Sync;
 }
@@ -1,4 +1,5 @@
-use actix_web::{http::header::LOCATION, HttpResponse};
+use actix_web::{http::header::LOCATION, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
 
 
 pub fn e500<T>(e: T) -> actix_web::Error 
@@ -14,9 +15,85 @@ pub fn see_other(location: &str) -> HttpResponse {
         .finish()
 }
 
-pub fn e400<T: std::fmt::Debug + std::fmt::Display>(e: T) -> actix_web::Error 
+pub fn client_ip(request: &HttpRequest) -> Option<String> {
+    request
+        .connection_info()
+        .realip_remote_addr()
+        .map(|ip| ip.to_string())
+}
+
+pub fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Decodes the character references that turn up in attribute values, such
+/// as `&amp;` in a link's query string. Anything unrecognised is kept as is.
+pub fn html_unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                reference => reference
+                    .strip_prefix("#x")
+                    .or_else(|| reference.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| reference.strip_prefix('#').map(|dec| dec.parse()))
+                    .and_then(|n| n.ok())
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+pub fn e400<T>(e: T) -> actix_web::Error 
 where
     T: std::fmt::Debug + std::fmt::Display + 'static
 {
     actix_web::error::ErrorBadRequest(e)
 }
+
+/// Accepts RFC 3339 timestamps and the `datetime-local` format browsers
+/// submit. Timestamps without an offset are taken to be UTC.
+pub fn parse_datetime(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(value) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(t.and_utc());
+        }
+    }
+    Err(format!("{} is not a valid timestamp.", value))
+}
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use diesel::{r2d2::ConnectionManager, PgConnection};
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::{
+    domain::subscriber_email::SubscriberEmail,
+    email_client::EmailClient,
+    email_html::prepare_html,
+    issue_delivery_worker::{DeliveryOutcome, ExecutionOutcome},
+    models::{SequenceDelivery, SequenceEnrollment, SequenceStep},
+    rendering::{check_merge_tags, load_recipient, render_sequence_step, uses_merge_tag},
+    suppressions::find_suppression,
+    utils::html_unescape,
+};
+
+/// Longest delay a step can have, in days.
+pub const MAX_DELAY_DAYS: i32 = 365;
+
+#[derive(Debug)]
+pub struct SequenceStepContent {
+    pub delay_days: i32,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl SequenceStepContent {
+    /// Checks a step as submitted by an editor. The HTML is prepared for
+    /// email clients here, as issues are when they are published. Steps are
+    /// sent without a template, so both bodies must carry the unsubscribe
+    /// link themselves.
+    pub fn parse(delay_days: &str, subject: String, html: String, text: String) -> Result<Self, String> {
+        let delay_days = delay_days
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .filter(|days| (0..=MAX_DELAY_DAYS).contains(days))
+            .ok_or_else(|| format!("The delay must be a whole number of days between 0 and {}.", MAX_DELAY_DAYS))?;
+        if subject.trim().is_empty() {
+            return Err("The subject cannot be empty.".to_string());
+        }
+        if html.trim().is_empty() || text.trim().is_empty() {
+            return Err("A step needs both an HTML and a plain-text body.".to_string());
+        }
+        check_merge_tags(&subject, &html, &text)?;
+
+        let prepared = prepare_html(&html);
+        if let Some(error) = prepared.errors.first() {
+            return Err(html_unescape(error));
+        }
+        if !uses_merge_tag(&prepared.html, "unsubscribe_url") || !uses_merge_tag(&text, "unsubscribe_url") {
+            return Err("Both bodies of a step need an unsubscribe link: {{unsubscribe_url}}.".to_string());
+        }
+
+        Ok(Self { delay_days, subject, html: prepared.html, text })
+    }
+}
+
+fn days(n: i32) -> Duration {
+    Duration::days(n.into())
+}
+
+/// Steps in the order subscribers receive them.
+#[tracing::instrument(name = "Get sequence steps", skip(pool))]
+pub async fn get_sequence_steps(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SequenceStep>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::sequence_steps::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let steps = web::block(move || {
+        current_span.in_scope(|| {
+            sequence_steps
+                .order((delay_days.asc(), created_at.asc()))
+                .load::<SequenceStep>(&mut conn)
+                .context("Failed to fetch sequence steps")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(steps)
+}
+
+#[tracing::instrument(name = "Get sequence step", skip(pool))]
+pub async fn get_sequence_step(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    step_id: Uuid,
+) -> Result<Option<SequenceStep>, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::sequence_steps::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let step = web::block(move || {
+        current_span.in_scope(|| {
+            sequence_steps
+                .find(step_id)
+                .first::<SequenceStep>(&mut conn)
+                .optional()
+                .context("Failed to fetch sequence step")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(step)
+}
+
+/// How many subscribers each step has been sent to.
+#[tracing::instrument(name = "Count sequence deliveries", skip(pool))]
+pub async fn get_sent_counts(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Result<HashMap<Uuid, i64>, anyhow::Error> {
+    use diesel::dsl::count_star;
+    use diesel::prelude::*;
+    use crate::schema::sequence_deliveries::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let counts = web::block(move || {
+        current_span.in_scope(|| {
+            sequence_deliveries
+                .filter(outcome.eq(DeliveryOutcome::Sent.as_str()))
+                .group_by(sequence_step_id)
+                .select((sequence_step_id, count_star()))
+                .load::<(Uuid, i64)>(&mut conn)
+                .context("Failed to count sequence deliveries")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(counts.into_iter().collect())
+}
+
+/// Adds a step. Subscribers already in the sequence get it too, unless the
+/// time it would have gone out has passed.
+#[tracing::instrument(name = "Create sequence step", skip(pool, content))]
+pub async fn create_sequence_step(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    content: SequenceStepContent,
+) -> Result<Uuid, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{sequence_deliveries, sequence_enrollments, sequence_steps};
+
+    let now = Utc::now();
+    let step = SequenceStep {
+        sequence_step_id: Uuid::new_v4(),
+        delay_days: content.delay_days,
+        subject: content.subject,
+        html: content.html,
+        text: content.text,
+        created_at: now,
+        updated_at: now,
+    };
+    let step_id = step.sequence_step_id;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let delay = days(step.delay_days);
+                diesel::insert_into(sequence_steps::table)
+                    .values(&step)
+                    .execute(conn)
+                    .context("Failed to insert sequence step")?;
+
+                let enrollments = sequence_enrollments::table
+                    .filter(sequence_enrollments::enrolled_at.gt(now - delay))
+                    .load::<SequenceEnrollment>(conn)
+                    .context("Failed to fetch sequence enrollments")?;
+                let deliveries: Vec<SequenceDelivery> = enrollments
+                    .into_iter()
+                    .map(|enrollment| pending_delivery(enrollment.subscriber_id, &step, enrollment.enrolled_at))
+                    .collect();
+                diesel::insert_into(sequence_deliveries::table)
+                    .values(&deliveries)
+                    .execute(conn)
+                    .context("Failed to queue sequence step")?;
+
+                Ok::<_, anyhow::Error>(())
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(step_id)
+}
+
+/// Saves an edited step. Subscribers who already received it do not get it
+/// again; for everybody else a new delay moves the step accordingly.
+/// Returns `false` if there is no such step.
+#[tracing::instrument(name = "Update sequence step", skip(pool, content))]
+pub async fn update_sequence_step(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    step_id: Uuid,
+    content: SequenceStepContent,
+) -> Result<bool, anyhow::Error> {
+    use diesel::dsl::IntervalDsl;
+    use diesel::prelude::*;
+    use crate::schema::{sequence_deliveries, sequence_steps};
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let updated = web::block(move || {
+        current_span.in_scope(|| {
+            conn.transaction(|conn| {
+                let old_delay = sequence_steps::table
+                    .find(step_id)
+                    .select(sequence_steps::delay_days)
+                    .for_update()
+                    .first::<i32>(conn)
+                    .optional()
+                    .context("Failed to fetch sequence step")?;
+                let Some(old_delay) = old_delay else {
+                    return Ok(false);
+                };
+
+                diesel::update(sequence_steps::table.find(step_id))
+                    .set((
+                        sequence_steps::delay_days.eq(content.delay_days),
+                        sequence_steps::subject.eq(content.subject),
+                        sequence_steps::html.eq(content.html),
+                        sequence_steps::text.eq(content.text),
+                        sequence_steps::updated_at.eq(Utc::now()),
+                    ))
+                    .execute(conn)
+                    .context("Failed to update sequence step")?;
+
+                if content.delay_days != old_delay {
+                    diesel::update(
+                        sequence_deliveries::table
+                            .filter(sequence_deliveries::sequence_step_id.eq(step_id))
+                            .filter(sequence_deliveries::outcome.is_null())
+                    )
+                    .set(sequence_deliveries::due_at.eq(
+                        sequence_deliveries::due_at + (content.delay_days - old_delay).days()
+                    ))
+                    .execute(conn)
+                    .context("Failed to reschedule sequence step")?;
+                }
+
+                Ok::<_, anyhow::Error>(true)
+            })
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(updated)
+}
+
+/// Removes a step along with its queued and past deliveries. Returns `false`
+/// if there is no such step.
+#[tracing::instrument(name = "Delete sequence step", skip(pool))]
+pub async fn delete_sequence_step(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    step_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::sequence_steps::dsl::*;
+
+    let mut conn = pool.get().context("Failed to get DB connection from pool")?;
+    let current_span = tracing::Span::current();
+
+    let rows_affected = web::block(move || {
+        current_span.in_scope(|| {
+            diesel::delete(sequence_steps.find(step_id))
+                .execute(&mut conn)
+                .context("Failed to delete sequence step")
+        })
+    })
+    .await
+    .context("Failed due to threadpool error")??;
+
+    Ok(rows_affected > 0)
+}
+
+fn pending_delivery(subscriber_id: Uuid, step: &SequenceStep, enrolled_at: DateTime<Utc>) -> SequenceDelivery {
+    SequenceDelivery {
+        subscriber_id,
+        sequence_step_id: step.sequence_step_id,
+        due_at: enrolled_at + days(step.delay_days),
+        outcome: None,
+        detail: None,
+        attempted_at: None,
+    }
+}
+
+/// Starts the welcome sequence for a subscriber who just confirmed, queueing
+/// every step relative to `now`. Confirming again changes nothing.
+#[tracing::instrument(skip(conn))]
+pub fn enroll_in_welcome_sequence(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    subscriber_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{sequence_deliveries, sequence_enrollments, sequence_steps};
+
+    let enrolled = diesel::insert_into(sequence_enrollments::table)
+        .values(SequenceEnrollment { subscriber_id, enrolled_at: now })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .context("Failed to enroll subscriber in the welcome sequence")?;
+    if enrolled == 0 {
+        return Ok(());
+    }
+
+    let steps = sequence_steps::table
+        .load::<SequenceStep>(conn)
+        .context("Failed to fetch sequence steps")?;
+    let deliveries: Vec<SequenceDelivery> = steps
+        .iter()
+        .map(|step| pending_delivery(subscriber_id, step, now))
+        .collect();
+    diesel::insert_into(sequence_deliveries::table)
+        .values(&deliveries)
+        .execute(conn)
+        .context("Failed to queue the welcome sequence")?;
+
+    Ok(())
+}
+
+/// Sends one step that was due by `now`, if there is one. Subscribers who
+/// have left or whose address is suppressed are skipped, which ends their
+/// sequence.
+#[tracing::instrument(
+    skip_all,
+    fields(sequence_step_id=tracing::field::Empty, subscriber_id=tracing::field::Empty)
+)]
+pub fn try_send_sequence_step(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    email_client: &EmailClient,
+    base_url: &str,
+    now: DateTime<Utc>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    use diesel::prelude::*;
+    use crate::schema::{sequence_deliveries, sequence_steps, subscriptions};
+
+    let due = sequence_deliveries::table
+        .filter(sequence_deliveries::outcome.is_null())
+        .filter(sequence_deliveries::due_at.le(now))
+        .order(sequence_deliveries::due_at.asc())
+        .select((sequence_deliveries::subscriber_id, sequence_deliveries::sequence_step_id))
+        .for_update()
+        .skip_locked()
+        .first::<(Uuid, Uuid)>(conn)
+        .optional()?;
+    let Some((subscriber_id, step_id)) = due else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    tracing::Span::current()
+        .record("sequence_step_id", tracing::field::display(step_id))
+        .record("subscriber_id", tracing::field::display(subscriber_id));
+
+    let step = sequence_steps::table.find(step_id).first::<SequenceStep>(conn)?;
+    let (email, status) = subscriptions::table
+        .find(subscriber_id)
+        .select((subscriptions::email, subscriptions::status))
+        .first::<(String, String)>(conn)?;
+
+    let (outcome, detail) = if status != "confirmed" {
+        (DeliveryOutcome::Skipped, Some("unsubscribed".to_string()))
+    } else if find_suppression(conn, &email)?.is_some() {
+        (DeliveryOutcome::Skipped, Some("suppressed".to_string()))
+    } else {
+        match send_step(conn, email_client, base_url, &step, &email) {
+            Ok(()) => (DeliveryOutcome::Sent, None),
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a welcome sequence step. Skipping.",
+                );
+                (DeliveryOutcome::Failed, Some(e.to_string()))
+            }
+        }
+    };
+
+    diesel::update(sequence_deliveries::table.find((subscriber_id, step_id)))
+        .set((
+            sequence_deliveries::outcome.eq(outcome.as_str()),
+            sequence_deliveries::detail.eq(detail),
+            sequence_deliveries::attempted_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+fn send_step(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    email_client: &EmailClient,
+    base_url: &str,
+    step: &SequenceStep,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let address = SubscriberEmail::parse(email.to_string()).map_err(anyhow::Error::msg)?;
+    let recipient = load_recipient(conn, email, base_url)?.context("The subscriber no longer exists")?;
+    let rendered = render_sequence_step(step, &recipient);
+
+    let rt = tokio::runtime::Handle::current();
+    rt.block_on(email_client.send_email(&address, &rendered.subject, &rendered.html, &rendered.text))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SequenceStepContent;
+
+    const HTML: &str = r#"<p>Hi</p><p><a href="{{unsubscribe_url}}">Unsubscribe</a></p>"#;
+    const TEXT: &str = "Hi\n\nUnsubscribe: {{ unsubscribe_url }}";
+
+    fn parse(delay_days: &str, subject: &str) -> Result<SequenceStepContent, String> {
+        SequenceStepContent::parse(delay_days, subject.to_string(), HTML.to_string(), TEXT.to_string())
+    }
+
+    #[test]
+    fn delays_are_whole_days_within_a_year() {
+        assert_eq!(parse(" 3 ", "Welcome").unwrap().delay_days, 3);
+        assert_eq!(parse("0", "Welcome").unwrap().delay_days, 0);
+        for invalid in ["", "-1", "1.5", "366", "soon"] {
+            assert!(parse(invalid, "Welcome").is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn steps_need_a_subject_and_valid_merge_tags() {
+        assert_eq!(parse("0", " ").unwrap_err(), "The subject cannot be empty.");
+        assert!(parse("0", "Hi {{ nickname }}").unwrap_err().contains("unknown merge tags: nickname"));
+        assert!(parse("0", "Hi {{name|default('there')}}").is_ok());
+    }
+
+    #[test]
+    fn steps_need_an_unsubscribe_link_in_both_bodies() {
+        let without_link = [
+            (HTML, "Hi"),
+            ("<p>Hi</p>", TEXT),
+            // Dropped along with the script link.
+            (r#"<p>Hi</p><a href="javascript:{{unsubscribe_url}}">x</a>"#, TEXT),
+        ];
+        for (html, text) in without_link {
+            let e = SequenceStepContent::parse("0", "Welcome".to_string(), html.to_string(), text.to_string()).unwrap_err();
+            assert_eq!(e, "Both bodies of a step need an unsubscribe link: {{unsubscribe_url}}.");
+        }
+    }
+}
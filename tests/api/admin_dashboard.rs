@@ -1,4 +1,6 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use std::time::Duration;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
 
 #[actix_web::test]
 async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
@@ -27,3 +29,54 @@ async fn logout_clears_session_state(){
     let response = app.get_admin_dashboard().await;
     assert_is_redirect_to(&response, "/login");
 }
+
+#[actix_web::test]
+async fn idle_sessions_are_logged_out() {
+    let app = spawn_app_with(|c| {
+        c.session.idle_timeout_seconds = 0.2;
+    })
+    .await;
+
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have been logged out due to inactivity. Please log in again.</i></p>"));
+}
+
+#[actix_web::test]
+async fn sessions_are_logged_out_after_their_absolute_lifetime() {
+    let app = spawn_app_with(|c| {
+        c.session.idle_timeout_seconds = 60.0;
+        c.session.absolute_timeout_seconds = 0.5;
+    })
+    .await;
+
+    let response = app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your session has reached its maximum lifetime. Please log in again.</i></p>"));
+}
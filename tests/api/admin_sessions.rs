@@ -0,0 +1,99 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn get_session_id_for_user_agent(app: &TestApp, agent: &str) -> Uuid {
+    use newsletter::schema::user_sessions::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    user_sessions
+        .select(session_id)
+        .filter(user_agent.eq(agent))
+        .first::<Uuid>(&mut conn)
+        .expect("Failed to fetch session id")
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_see_active_sessions() {
+    let app = spawn_app().await;
+    let response = app.get_sessions().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn active_sessions_are_listed_with_their_device() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    app.login_from_another_device("Other-Device/1.0").await;
+
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("Other-Device/1.0"));
+    assert!(html_page.contains("(this device)"));
+}
+
+#[actix_web::test]
+async fn revoking_a_session_logs_that_device_out() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let other_device = app.login_from_another_device("Other-Device/1.0").await;
+    let other_session_id = get_session_id_for_user_agent(&app, "Other-Device/1.0");
+
+    let response = app.post_revoke_session(&serde_json::json!({
+        "session_id": other_session_id.to_string()
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session has been revoked.</i></p>"));
+    assert!(!html_page.contains("Other-Device/1.0"));
+
+    let response = other_device
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn logging_out_everywhere_revokes_every_session() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let other_device = app.login_from_another_device("Other-Device/1.0").await;
+
+    let response = app.post_revoke_all_sessions().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have been logged out on every device.</i></p>"));
+
+    let response = other_device
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,130 @@
+use diesel::prelude::*;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_see_the_audit_log() {
+    let app = spawn_app().await;
+
+    let response = app.get_audit_log("").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_audit_log_export("").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn logins_and_password_changes_are_recorded() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    }))
+    .await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let response = app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_audit_log_html("").await;
+    assert!(html_page.contains("login_failed"));
+    assert!(html_page.contains("password_changed"));
+
+    let html_page = app.get_audit_log_html("action=login_failed").await;
+    assert!(html_page.contains("<td>login_failed</td>"));
+    assert!(!html_page.contains("<td>password_changed</td>"));
+}
+
+#[actix_web::test]
+async fn the_audit_log_can_be_exported_as_ndjson() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let response = app.get_audit_log_export("action=login").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "application/x-ndjson"
+    );
+
+    let body = response.text().await.unwrap();
+    let events: Vec<serde_json::Value> = body
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["action"], "login");
+    assert_eq!(events[0]["username"], app.test_user.username.as_str());
+}
+
+#[actix_web::test]
+async fn invalid_filters_are_rejected() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let response = app.get_audit_log("action=made_up").await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.get_audit_log("from=yesterday").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn audit_events_cannot_be_modified() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let mut conn = app.db_pool.get().unwrap();
+    let result = diesel::sql_query("UPDATE audit_events SET action = 'tampered'").execute(&mut conn);
+    assert!(result.is_err());
+
+    let result = diesel::sql_query("DELETE FROM audit_events").execute(&mut conn);
+    assert!(result.is_err());
+}
+
+#[actix_web::test]
+async fn template_changes_are_recorded() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let template_id = app.create_template("<div>{{content}}</div>", "{{content}}").await;
+
+    let response = app.get_audit_log_export("action=config_changed").await;
+    let body = response.text().await.unwrap();
+    let events: Vec<serde_json::Value> = body
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["target"], template_id.as_str());
+    assert_eq!(events[0]["metadata"], serde_json::json!({ "setting": "template", "change": "created" }));
+}
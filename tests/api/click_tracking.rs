@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diesel::prelude::*;
+use uuid::Uuid;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{spawn_app, TestApp}, newsletter_delivery::create_confirmed_subscriber};
+
+/// Sends an issue linking to an external page and returns the email as the
+/// email API received it.
+async fn send_issue_with_links(app: &TestApp) -> serde_json::Value {
+    create_confirmed_subscriber(app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Read it at https://example.com/post?a=1&b=2 or write to me@example.com.",
+        "html": r#"<p><a href="https://example.com/post?a=1&amp;b=2">Read it</a> or <a href="mailto:me@example.com">write</a>.</p>"#,
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+fn click_urls(body: &str) -> Vec<String> {
+    linkify::LinkFinder::new()
+        .links(body)
+        .map(|l| l.as_str().to_string())
+        .filter(|l| l.contains("/t/click/"))
+        .collect()
+}
+
+#[actix_web::test]
+async fn links_go_through_the_click_tracker() {
+    let app = spawn_app().await;
+    let body = send_issue_with_links(&app).await;
+
+    let html = body["HtmlBody"].as_str().unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(!html.contains("example.com/post"));
+    assert!(html.contains(r#"<a href="mailto:me@example.com">write</a>"#));
+    assert!(!text.contains("example.com/post"));
+    assert!(text.contains("me@example.com"));
+
+    let html_link = click_urls(html).pop().unwrap();
+    assert_eq!(click_urls(text), vec![html_link.clone()]);
+
+    let response = app.api_client
+        .get(&html_link)
+        .header("User-Agent", "Mail/1.0")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers()["Location"], "https://example.com/post?a=1&b=2");
+
+    // Clicks are stored in the background.
+    let mut stored = None;
+    for _ in 0..50 {
+        use newsletter::schema::issue_clicks::dsl::*;
+
+        let mut conn = app.db_pool.get().unwrap();
+        stored = issue_clicks
+            .select((url, user_agent))
+            .first::<(String, Option<String>)>(&mut conn)
+            .optional()
+            .unwrap();
+        if stored.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(stored, Some(("https://example.com/post?a=1&b=2".to_string(), Some("Mail/1.0".to_string()))));
+}
+
+#[actix_web::test]
+async fn the_click_tracker_does_not_redirect_to_unsigned_destinations() {
+    let app = spawn_app().await;
+    let body = send_issue_with_links(&app).await;
+    let link = click_urls(body["HtmlBody"].as_str().unwrap()).pop().unwrap();
+
+    // Keep the genuine signature but swap in another destination.
+    let (prefix, token) = link.rsplit_once('/').unwrap();
+    let (payload, signature) = token.split_once('.').unwrap();
+    let mut payload = URL_SAFE_NO_PAD.decode(payload).unwrap();
+    payload.truncate(32);
+    payload.extend_from_slice(b"https://evil.example.com");
+    let forged = format!("{}/{}.{}", prefix, URL_SAFE_NO_PAD.encode(payload), signature);
+
+    let response = app.api_client.get(&forged).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.headers().get("Location").is_none());
+
+    let response = app.api_client.get(format!("{}/t/click/not-a-token", app.address)).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
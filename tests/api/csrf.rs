@@ -0,0 +1,105 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[actix_web::test]
+async fn admin_forms_contain_a_csrf_token() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let token = app.get_csrf_token().await.expect("No CSRF token on the dashboard");
+    let field = format!(r#"name="csrf_token" value="{}""#, token);
+
+    assert!(app.get_change_password_html().await.contains(&field));
+    assert!(app.get_delivery_html().await.contains(&field));
+    assert!(app.get_sessions_html().await.contains(&field));
+}
+
+#[actix_web::test]
+async fn admin_posts_without_a_csrf_token_are_rejected() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app.api_client
+        .post(format!("{}/admin/password", &app.address))
+        .form(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn admin_posts_with_an_invalid_csrf_token_are_rejected() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let response = app.api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&serde_json::json!({
+            "csrf_token": "not-the-right-token"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[actix_web::test]
+async fn a_csrf_token_in_the_form_body_is_accepted() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+
+    let token = app.get_csrf_token().await.unwrap();
+    let new_password = Uuid::new_v4().to_string();
+    let response = app.api_client
+        .post(format!("{}/admin/password", &app.address))
+        .form(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+            "csrf_token": token
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+}
@@ -0,0 +1,191 @@
+use diesel::prelude::*;
+use newsletter::{configuration::{FeedPollerMode, FeedPollerSettings}, feed_poller::poll_feed, models::NewsletterIssue};
+use uuid::Uuid;
+use wiremock::{matchers::{any, method, path}, Mock, MockServer, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber_with_email, spawn_app, TestApp};
+
+struct Post {
+    guid: &'static str,
+    title: &'static str,
+    date: &'static str,
+}
+
+fn rss(posts: &[Post]) -> String {
+    let items: String = posts
+        .iter()
+        .map(|post| format!(
+            r#"<item>
+      <title>{title}</title>
+      <link>https://blog.example.com/{guid}</link>
+      <guid>{guid}</guid>
+      <pubDate>{date}</pubDate>
+      <description>&lt;p&gt;About {title}&lt;/p&gt;</description>
+    </item>"#,
+            title = post.title,
+            guid = post.guid,
+            date = post.date,
+        ))
+        .collect();
+    format!(r#"<?xml version="1.0"?>
+<rss version="2.0">
+  <channel>
+    <title>Blog</title>
+    <link>https://blog.example.com</link>
+    <description>Posts</description>
+    {items}
+  </channel>
+</rss>"#)
+}
+
+const FIRST: Post = Post { guid: "first", title: "First post", date: "Mon, 21 Oct 2024 09:00:00 GMT" };
+const SECOND: Post = Post { guid: "second", title: "Second post", date: "Tue, 22 Oct 2024 09:00:00 GMT" };
+const THIRD: Post = Post { guid: "third", title: "Third post", date: "Wed, 23 Oct 2024 09:00:00 GMT" };
+
+fn get_issue(app: &TestApp, issue_id: Uuid) -> NewsletterIssue {
+    use newsletter::schema::newsletter_issues::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    newsletter_issues
+        .filter(newsletter_issue_id.eq(issue_id))
+        .first(&mut conn)
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn new_entries_of_a_local_feed_become_drafts() {
+    let app = spawn_app().await;
+    let feed = std::env::temp_dir().join(format!("{}.xml", Uuid::new_v4()));
+    let source = feed.to_str().unwrap();
+
+    // Entries already in the feed when it is first polled are not imported.
+    std::fs::write(&feed, rss(&[FIRST])).unwrap();
+    assert!(app.poll_feed(source, FeedPollerMode::Draft).await.unwrap().is_empty());
+
+    std::fs::write(&feed, rss(&[THIRD, SECOND, FIRST])).unwrap();
+    let issue_ids = app.poll_feed(source, FeedPollerMode::Draft).await.unwrap();
+    assert_eq!(issue_ids.len(), 2);
+
+    let titles: Vec<_> = issue_ids.iter().map(|id| get_issue(&app, *id).title).collect();
+    assert_eq!(titles, ["Second post", "Third post"]);
+    let issue = get_issue(&app, issue_ids[0]);
+    assert_eq!(issue.status, "draft");
+    assert_eq!(issue.created_by, None);
+    assert_eq!(issue.published_at, None);
+    assert!(issue.html.contains("<p>About Second post</p>"));
+    assert!(issue.html.contains(r#"<a href="https://blog.example.com/second">Read it on the web</a>"#));
+    assert_eq!(issue.text, "About Second post\n\nRead it on the web: https://blog.example.com/second");
+
+    // Processed entries are remembered.
+    assert!(app.poll_feed(source, FeedPollerMode::Draft).await.unwrap().is_empty());
+
+    std::fs::remove_file(&feed).unwrap();
+}
+
+#[actix_web::test]
+async fn new_entries_of_a_remote_feed_can_be_sent_straight_away() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "reader@example.com").await;
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let feed_server = MockServer::start().await;
+    let source = format!("{}/feed.xml", feed_server.uri());
+    Mock::given(path("/feed.xml"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(rss(&[FIRST])))
+        .mount(&feed_server)
+        .await;
+    assert!(app.poll_feed(&source, FeedPollerMode::Publish).await.unwrap().is_empty());
+
+    feed_server.reset().await;
+    Mock::given(path("/feed.xml"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(rss(&[SECOND, FIRST])))
+        .mount(&feed_server)
+        .await;
+    let issue_ids = app.poll_feed(&source, FeedPollerMode::Publish).await.unwrap();
+    assert_eq!(issue_ids.len(), 1);
+    let issue = get_issue(&app, issue_ids[0]);
+    assert!(issue.published_at.is_some());
+    assert_eq!(issue.slug.as_deref(), Some("second-post"));
+
+    app.dispatch_all_pending_emails().await;
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Second post");
+    assert_eq!(body["To"], "reader@example.com");
+    assert!(body["TextBody"].as_str().unwrap().contains("About Second post\n\nRead it on the web: "));
+
+    assert!(app.poll_feed(&source, FeedPollerMode::Publish).await.unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn a_feed_that_cannot_be_fetched_is_tried_again_later() {
+    let app = spawn_app().await;
+    let feed_server = MockServer::start().await;
+    let source = format!("{}/feed.xml", feed_server.uri());
+
+    Mock::given(path("/feed.xml"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&feed_server)
+        .await;
+    assert!(app.poll_feed(&source, FeedPollerMode::Draft).await.is_err());
+
+    feed_server.reset().await;
+    Mock::given(path("/feed.xml"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>not a feed</html>"))
+        .mount(&feed_server)
+        .await;
+    assert!(app.poll_feed(&source, FeedPollerMode::Draft).await.is_err());
+
+    feed_server.reset().await;
+    Mock::given(path("/feed.xml"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(rss(&[FIRST])))
+        .mount(&feed_server)
+        .await;
+    assert!(app.poll_feed(&source, FeedPollerMode::Draft).await.unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn entries_that_cannot_become_issues_are_tried_again() {
+    let app = spawn_app().await;
+    let feed = std::env::temp_dir().join(format!("{}.xml", Uuid::new_v4()));
+    let source = feed.to_str().unwrap();
+    std::fs::write(&feed, rss(&[FIRST])).unwrap();
+    app.poll_feed(source, FeedPollerMode::Draft).await.unwrap();
+
+    std::fs::write(&feed, rss(&[SECOND, FIRST])).unwrap();
+    let misconfigured = FeedPollerSettings {
+        source: source.to_string(),
+        mode: FeedPollerMode::Draft,
+        interval_seconds: 60,
+        template_id: Some("not-a-template".to_string()),
+    };
+    assert!(poll_feed(&app.db_pool, &reqwest::Client::new(), &misconfigured).await.unwrap().is_empty());
+
+    let issue_ids = app.poll_feed(source, FeedPollerMode::Draft).await.unwrap();
+    assert_eq!(issue_ids.len(), 1);
+    assert_eq!(get_issue(&app, issue_ids[0]).title, "Second post");
+
+    std::fs::remove_file(&feed).unwrap();
+}
+
+#[actix_web::test]
+async fn the_first_post_of_a_feed_that_started_empty_is_imported() {
+    let app = spawn_app().await;
+    let feed = std::env::temp_dir().join(format!("{}.xml", Uuid::new_v4()));
+    let source = feed.to_str().unwrap();
+
+    std::fs::write(&feed, rss(&[])).unwrap();
+    assert!(app.poll_feed(source, FeedPollerMode::Draft).await.unwrap().is_empty());
+
+    std::fs::write(&feed, rss(&[FIRST])).unwrap();
+    let issue_ids = app.poll_feed(source, FeedPollerMode::Draft).await.unwrap();
+    assert_eq!(issue_ids.len(), 1);
+    assert_eq!(get_issue(&app, issue_ids[0]).title, "First post");
+
+    std::fs::remove_file(&feed).unwrap();
+}
@@ -0,0 +1,149 @@
+use chrono::{DateTime, SubsecRound, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn published_at(app: &TestApp, issue_id: &str) -> DateTime<Utc> {
+    use newsletter::schema::newsletter_issues::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    newsletter_issues
+        .filter(newsletter_issue_id.eq(Uuid::parse_str(issue_id).unwrap()))
+        .select(published_at)
+        .first::<Option<DateTime<Utc>>>(&mut conn)
+        .unwrap()
+        .unwrap()
+}
+
+fn between<'a>(s: &'a str, start: &str, end: &str) -> &'a str {
+    let s = &s[s.find(start).unwrap() + start.len()..];
+    &s[..s.find(end).unwrap()]
+}
+
+#[actix_web::test]
+async fn feeds_list_public_issues_with_their_publication_dates() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    let issue_id = app.publish_issue("Tom & Jerry <3", true).await;
+    app.publish_issue("Private", false).await;
+    let published = published_at(&app, &issue_id).trunc_subsecs(0);
+
+    let response = app.get_page("/feed.rss").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/rss+xml; charset=utf-8");
+    let rss = response.text().await.unwrap();
+    assert!(rss.starts_with(r#"<?xml version="1.0" encoding="utf-8"?>"#));
+    assert!(rss.contains("<title>Tom &amp; Jerry &lt;3</title>"));
+    assert!(rss.contains("/issues/tom-jerry-3</link>"));
+    assert!(rss.contains(&format!(r#"<guid isPermaLink="false">urn:uuid:{}</guid>"#, issue_id)));
+    assert!(rss.contains("<description>&lt;p&gt;Hi reader, read &lt;a href=&quot;https://example.com/post&quot;&gt;this&lt;/a&gt;.&lt;/p&gt;</description>"));
+    assert_eq!(DateTime::parse_from_rfc2822(between(&rss, "<pubDate>", "</pubDate>")).unwrap(), published);
+    assert!(!rss.contains("Private"));
+
+    let response = app.get_page("/feed.atom").await;
+    assert_eq!(response.headers()["Content-Type"], "application/atom+xml; charset=utf-8");
+    let atom = response.text().await.unwrap();
+    assert!(atom.contains("<title>Tom &amp; Jerry &lt;3</title>"));
+    assert!(atom.contains(&format!("<id>urn:uuid:{}</id>", issue_id)));
+    assert_eq!(DateTime::parse_from_rfc3339(between(&atom, "<published>", "</published>")).unwrap(), published);
+    assert!(!atom.contains("Private"));
+
+    let response = app.get_page("/feed.json").await;
+    assert_eq!(response.headers()["Content-Type"], "application/feed+json; charset=utf-8");
+    let json: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+    assert_eq!(json["items"].as_array().unwrap().len(), 1);
+    assert_eq!(json["items"][0]["title"], "Tom & Jerry <3");
+    assert!(json["items"][0]["url"].as_str().unwrap().ends_with("/issues/tom-jerry-3"));
+    assert_eq!(
+        DateTime::parse_from_rfc3339(json["items"][0]["date_published"].as_str().unwrap()).unwrap(),
+        published
+    );
+}
+
+#[actix_web::test]
+async fn feeds_are_served_when_nothing_has_been_published() {
+    let app = spawn_app().await;
+
+    for path in ["/feed.rss", "/feed.atom", "/feed.json"] {
+        let response = app.get_page(path).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers().contains_key("ETag"));
+        assert_eq!(response.headers()["Last-Modified"], "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+    let json: serde_json::Value = app.get_page("/feed.json").await.json().await.unwrap();
+    assert_eq!(json["items"], serde_json::json!([]));
+}
+
+#[actix_web::test]
+async fn unchanged_feeds_are_not_sent_again() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    let issue_id = app.publish_issue("First", true).await;
+
+    let response = app.get_page("/feed.rss").await;
+    let etag = response.headers()["ETag"].to_str().unwrap().to_string();
+    let last_modified = response.headers()["Last-Modified"].to_str().unwrap().to_string();
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+    let conditional_get = |header: &'static str, value: String| {
+        app.api_client
+            .get(format!("{}/feed.rss", app.address))
+            .header(header, value)
+            .send()
+    };
+
+    let response = conditional_get("If-None-Match", etag.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 304);
+    assert_eq!(response.headers()["ETag"].to_str().unwrap(), etag);
+    assert_eq!(response.text().await.unwrap(), "");
+
+    let response = conditional_get("If-Modified-Since", last_modified.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 304);
+
+    let response = conditional_get("If-None-Match", "\"stale\"".to_string()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Taking the only issue out of the archive changes the feed.
+    app.post_issue_action(&issue_id, "make_private").await;
+    let response = conditional_get("If-None-Match", etag.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(!response.text().await.unwrap().contains("First"));
+
+    app.publish_issue("Second", true).await;
+    let response = conditional_get("If-None-Match", etag).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("<title>Second</title>"));
+}
+
+#[actix_web::test]
+async fn taking_an_issue_out_of_the_archive_moves_last_modified_forward() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    app.publish_issue("First", true).await;
+    let issue_id = app.publish_issue("Second", true).await;
+    {
+        use newsletter::schema::newsletter_issues::dsl::*;
+
+        let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+        let mut conn = app.db_pool.get().unwrap();
+        diesel::update(newsletter_issues)
+            .set((published_at.eq(an_hour_ago), updated_at.eq(an_hour_ago)))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let last_modified = app.get_page("/feed.rss").await.headers()["Last-Modified"].to_str().unwrap().to_string();
+    app.post_issue_action(&issue_id, "make_private").await;
+
+    let response = app.api_client
+        .get(format!("{}/feed.rss", app.address))
+        .header("If-Modified-Since", last_modified.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let changed = response.headers()["Last-Modified"].to_str().unwrap();
+    assert!(DateTime::parse_from_rfc2822(changed).unwrap() > DateTime::parse_from_rfc2822(&last_modified).unwrap());
+}
@@ -7,17 +7,25 @@ use argon2::Version;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::{Connection, PgConnection, RunQueryDsl};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use newsletter::configuration::{get_configuration, DatabaseSettings};
+use newsletter::configuration::{get_configuration, DatabaseSettings, FeedPollerMode, FeedPollerSettings, PostmarkWebhookSettings, Settings};
+use newsletter::csrf::CSRF_HEADER;
 use newsletter::email_client::EmailClient;
+use newsletter::feed_poller::poll_feed;
 use newsletter::issue_delivery_worker::try_execute_task;
+use newsletter::issue_delivery_worker::try_execute_next_task;
 use newsletter::issue_delivery_worker::ExecutionOutcome;
+use newsletter::issue_scheduler::publish_due_issues;
+use newsletter::subject_tests::pick_subject_test_winners;
 use newsletter::startup::{get_connection_pool, Application};
 use newsletter::telemetry::{get_subscriber, init_subscriber};
+use newsletter::tracking::Tracking;
+use newsletter::welcome_sequence::try_send_sequence_step;
 use once_cell::sync::Lazy;
 use reqwest::Response;
 use secrecy::ExposeSecret;
 use uuid::Uuid;
-use wiremock::{MockServer, Request};
+use wiremock::matchers::{any, method};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
 
@@ -50,12 +58,20 @@ impl TestUser {
     }
 
     pub fn store(&self, pool: &Pool<ConnectionManager<PgConnection>>) {
+        self.store_with(
+            pool,
+            Algorithm::Argon2id,
+            Params::new(15000, 2, 1, None).unwrap()
+        );
+    }
+
+    pub fn store_with(&self, pool: &Pool<ConnectionManager<PgConnection>>, algorithm: Algorithm, params: Params) {
         let salt = SaltString::generate(&mut rand::thread_rng());
 
         let password_hash = Argon2::new(
-                Algorithm::Argon2id,
+                algorithm,
                 Version::V0x13,
-                Params::new(15000, 2, 1, None).unwrap()
+                params
             )
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
@@ -86,26 +102,44 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
-    pub email_client: EmailClient
+    pub email_client: EmailClient,
+    pub tracking: Tracking,
+    pub postmark_webhook: PostmarkWebhookSettings,
 }
 
 impl TestApp {
 
+    /// Scrapes the CSRF token the server embedded in the admin dashboard.
+    pub async fn get_csrf_token(&self) -> Option<String> {
+        let html = self.get_admin_dashboard_html().await;
+        let start = html.find(r#"name="csrf_token" value=""#)? + r#"name="csrf_token" value=""#.len();
+        let end = start + html[start..].find('"')?;
+        Some(html[start..end].to_string())
+    }
+
+    /// Starts a POST to an admin endpoint, carrying the session's CSRF token if there is one.
+    pub async fn admin_post(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.api_client.post(format!("{}{}", &self.address, path));
+        match self.get_csrf_token().await {
+            Some(token) => request.header(CSRF_HEADER, token),
+            None => request,
+        }
+    }
+
     pub async fn dispatch_all_pending_emails(&self) {
-        loop {
-            let mut conn = self.db_pool.get().unwrap();
-            let client_clone = self.email_client.clone();
+        while let ExecutionOutcome::TaskCompleted = self.dispatch_pending_email().await {}
+    }
 
-            let res = tokio::task::spawn_blocking(move ||{
-                try_execute_task(&mut conn, &client_clone)
-            }).await.unwrap();
+    /// Runs a single delivery task, if there is one the worker would pick up.
+    pub async fn dispatch_pending_email(&self) -> ExecutionOutcome {
+        let mut conn = self.db_pool.get().unwrap();
+        let client_clone = self.email_client.clone();
+        let base_url = self.address.clone();
+        let tracking = self.tracking.clone();
 
-            if let ExecutionOutcome::EmptyQueue =
-                res.unwrap()
-            {
-                break;
-            }
-        }
+        tokio::task::spawn_blocking(move ||{
+            try_execute_task(&mut conn, &client_clone, &base_url, &tracking)
+        }).await.unwrap().unwrap()
     }
 
 
@@ -126,14 +160,35 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
+    /// Follows the unsubscribe link of a stored subscriber.
+    pub async fn unsubscribe(&self, subscriber_email: &str) {
+        use diesel::{ExpressionMethods, QueryDsl};
+        use newsletter::schema::{subscription_tokens, subscriptions};
+
+        let token = {
+            let mut conn = self.db_pool.get().unwrap();
+            subscription_tokens::table
+                .inner_join(subscriptions::table)
+                .filter(subscriptions::email.eq(subscriber_email))
+                .select(subscription_tokens::subscription_token)
+                .first::<String>(&mut conn)
+                .unwrap()
+        };
+        reqwest::get(format!("{}/subscriptions/unsubscribe?subscription_token={}", self.address, token))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .body(body)
@@ -147,16 +202,39 @@ impl TestApp {
         Body: serde::Serialize, 
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    /// Logs in as the test user.
+    pub async fn login(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await;
+    }
+
+    /// Confirms a subscriber for each address, lets the email API accept
+    /// every request from then on and logs in as the test user.
+    pub async fn login_with_subscribers(&self, subscriber_emails: &[&str]) {
+        for email in subscriber_emails {
+            create_confirmed_subscriber_with_email(self, email).await;
+        }
+        Mock::given(any())
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&self.email_server)
+            .await;
+        self.login().await;
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -167,7 +245,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> Response{
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -183,7 +261,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -193,8 +271,8 @@ impl TestApp {
     where 
         Body: serde::Serialize
     {
-        self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+        self.admin_post("/admin/password")
+            .await
             .form(body)
             .send()
             .await
@@ -210,13 +288,396 @@ impl TestApp {
     }
 
     pub async fn post_logout(&self) -> reqwest::Response{
-        self.api_client
-            .post(format!("{}/admin/logout", &self.address))
+        self.admin_post("/admin/logout")
+            .await
             .send()
             .await
             .expect("Failed to execute request")
     }
 
+    pub async fn get_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        self.get_sessions()
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_revoke_session<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.admin_post("/admin/sessions/revoke")
+            .await
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_revoke_all_sessions(&self) -> reqwest::Response {
+        self.admin_post("/admin/sessions/revoke_all")
+            .await
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Logs the test user in from a separate cookie jar, as if from another device.
+    pub async fn login_from_another_device(&self, user_agent: &str) -> reqwest::Client {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .user_agent(user_agent)
+            .build()
+            .unwrap();
+
+        let response = client
+            .post(format!("{}/login", &self.address))
+            .form(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_is_redirect_to(&response, "/admin/dashboard");
+
+        client
+    }
+
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_log_html(&self, query: &str) -> String {
+        self.get_audit_log(query)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_audit_log_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit/export?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issues_html(&self) -> String {
+        self.get_issues("").await.text().await.unwrap()
+    }
+
+    pub async fn get_issues(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue(&self, issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues/{}", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_html(&self, issue_id: &str) -> String {
+        self.get_issue(issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_issue_preview(&self, issue_id: &str, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues/{}/preview?{}", &self.address, issue_id, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_issue<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.admin_post("/admin/issues")
+            .await
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Saves a new draft and returns its id.
+    pub async fn create_draft(&self, title: &str) -> String {
+        let response = self.post_issue(&serde_json::json!({
+            "title": title,
+            "text": "Newsletter text",
+            "html": "<p>Newsletter html</p>",
+        }))
+        .await;
+        assert_eq!(response.status().as_u16(), 303);
+
+        response.headers()["Location"]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/admin/issues/")
+            .to_string()
+    }
+
+    pub async fn post_update_issue<Body>(&self, issue_id: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.admin_post(&format!("/admin/issues/{}", issue_id))
+            .await
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_delete_issue(&self, issue_id: &str) -> reqwest::Response {
+        self.admin_post(&format!("/admin/issues/{}/delete", issue_id))
+            .await
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_publish_issue(&self, issue_id: &str, idempotency_key: &str) -> reqwest::Response {
+        self.admin_post(&format!("/admin/issues/{}/publish", issue_id))
+            .await
+            .form(&serde_json::json!({ "idempotency_key": idempotency_key }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Saves and publishes a draft, then delivers it. Returns the issue id.
+    pub async fn publish_issue(&self, title: &str, is_public: bool) -> String {
+        let mut form = serde_json::json!({
+            "title": title,
+            "text": "Hi {{name|default('reader')}}. Unsubscribe: {{unsubscribe_url}}",
+            "html": r#"<html><body><p>Hi {{name|default('reader')}}, read <a href="https://example.com/post">this</a>.</p><a href="{{unsubscribe_url}}">Unsubscribe</a></body></html>"#,
+            "track_opens": "true",
+        });
+        if is_public {
+            form["is_public"] = "true".into();
+        }
+        let response = self.post_issue(&form).await;
+        assert_eq!(response.status().as_u16(), 303);
+        let issue_id = response.headers()["Location"]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/admin/issues/")
+            .to_string();
+        self.post_publish_issue(&issue_id, &Uuid::new_v4().to_string()).await;
+        self.dispatch_all_pending_emails().await;
+        issue_id
+    }
+
+    pub async fn get_page(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", &self.address, path))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_schedule_issue(&self, issue_id: &str, scheduled_for: &str) -> reqwest::Response {
+        self.admin_post(&format!("/admin/issues/{}/schedule", issue_id))
+            .await
+            .form(&serde_json::json!({ "scheduled_for": scheduled_for }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unschedule_issue(&self, issue_id: &str) -> reqwest::Response {
+        self.admin_post(&format!("/admin/issues/{}/unschedule", issue_id))
+            .await
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Posts one of the `pause`, `resume` or `cancel` actions for a published issue.
+    pub async fn post_issue_action(&self, issue_id: &str, action: &str) -> reqwest::Response {
+        self.admin_post(&format!("/admin/issues/{}/{}", issue_id, action))
+            .await
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Runs one pass of the scheduler and returns the ids of the issues it published.
+    pub async fn run_scheduler(&self) -> Vec<Uuid> {
+        let mut conn = self.db_pool.get().unwrap();
+        tokio::task::spawn_blocking(move || publish_due_issues(&mut conn))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Runs one pass of the subject test judge and returns the ids of the issues it decided.
+    pub async fn pick_subject_test_winners(&self) -> Vec<Uuid> {
+        let mut conn = self.db_pool.get().unwrap();
+        tokio::task::spawn_blocking(move || pick_subject_test_winners(&mut conn))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Runs one iteration of the worker, with or without the welcome
+    /// sequence taking its turn first.
+    pub async fn run_worker_task(&self, sequence_turn: bool) -> ExecutionOutcome {
+        let mut conn = self.db_pool.get().unwrap();
+        let email_client = self.email_client.clone();
+        let base_url = self.address.clone();
+        let tracking = self.tracking.clone();
+
+        tokio::task::spawn_blocking(move || {
+            try_execute_next_task(&mut conn, &email_client, &base_url, &tracking, sequence_turn)
+        }).await.unwrap().unwrap()
+    }
+
+    /// Sends every welcome sequence step due by `now`, as the worker would.
+    pub async fn dispatch_sequence_emails(&self, now: chrono::DateTime<chrono::Utc>) {
+        loop {
+            let mut conn = self.db_pool.get().unwrap();
+            let email_client = self.email_client.clone();
+            let base_url = self.address.clone();
+            let outcome = tokio::task::spawn_blocking(move || {
+                try_send_sequence_step(&mut conn, &email_client, &base_url, now)
+            }).await.unwrap().unwrap();
+            if let ExecutionOutcome::EmptyQueue = outcome {
+                break;
+            }
+        }
+    }
+
+    /// Polls `source` once, as the feed poller would, and returns the ids of the issues it created.
+    pub async fn poll_feed(&self, source: &str, mode: FeedPollerMode) -> Result<Vec<Uuid>, anyhow::Error> {
+        let settings = FeedPollerSettings {
+            source: source.to_string(),
+            mode,
+            interval_seconds: 60,
+            template_id: None,
+        };
+        poll_feed(&self.db_pool, &reqwest::Client::new(), &settings).await
+    }
+
+    pub async fn post_test_issue<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.admin_post("/admin/newsletter/test")
+            .await
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_templates_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/templates", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_template(&self, template_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/templates/{}", &self.address, template_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_template_html(&self, template_id: &str) -> String {
+        self.get_template(template_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_template<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.admin_post("/admin/templates")
+            .await
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Saves a new issue template and returns its id.
+    pub async fn create_template(&self, html: &str, text: &str) -> String {
+        let response = self.post_template(&serde_json::json!({
+            "name": "House style",
+            "subject": "{{issue.title}}",
+            "html": html,
+            "text": text,
+        }))
+        .await;
+        assert_eq!(response.status().as_u16(), 303);
+
+        response.headers()["Location"]
+            .to_str()
+            .unwrap()
+            .trim_start_matches("/admin/templates/")
+            .to_string()
+    }
+
+    pub async fn post_update_template<Body>(&self, template_id: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.admin_post(&format!("/admin/templates/{}", template_id))
+            .await
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Calls the Postmark webhook with the configured credentials.
+    pub async fn post_postmark_webhook(&self, payload: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(&self.postmark_webhook.username, Some(self.postmark_webhook.password.expose_secret()))
+            .json(payload)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_delivery(&self) -> reqwest::Response{
         self.api_client
             .get(format!("{}/admin/newsletter", &self.address))
@@ -237,8 +698,8 @@ impl TestApp {
     where 
         Body: serde::Serialize
     {
-        self.api_client
-            .post(&format!("{}/admin/newsletter", &self.address))
+        self.admin_post("/admin/newsletter")
+            .await
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .form(&body)
             .send()
@@ -254,7 +715,7 @@ pub fn run_db_migrations(conn: &mut impl MigrationHarness<diesel::pg::Pg>) {
 
 fn configure_database(config: &DatabaseSettings) -> Pool<ConnectionManager<PgConnection>> {
     let mut connection =
-        PgConnection::establish(&*config.connection_string_without_db().expose_secret())
+        PgConnection::establish(config.connection_string_without_db().expose_secret())
             .expect("Failed to connect to postgres database (without DB URI used)");
 
     let query = format!(r#"CREATE DATABASE "{}";"#, config.database_name);
@@ -263,7 +724,7 @@ fn configure_database(config: &DatabaseSettings) -> Pool<ConnectionManager<PgCon
         .expect("Failed to create test database");
 
     let manager =
-        ConnectionManager::<PgConnection>::new(&*config.connection_string().expose_secret());
+        ConnectionManager::<PgConnection>::new(config.connection_string().expose_secret());
 
     let pool = Pool::builder()
         .test_on_check_out(true)
@@ -277,6 +738,14 @@ fn configure_database(config: &DatabaseSettings) -> Pool<ConnectionManager<PgCon
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the application after letting the caller tweak the test configuration.
+pub async fn spawn_app_with<F>(customise: F) -> TestApp
+where
+    F: FnOnce(&mut Settings)
+{
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -286,6 +755,7 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        customise(&mut c);
         c
     };
 
@@ -297,7 +767,7 @@ pub async fn spawn_app() -> TestApp {
 
     let address = format!("http://localhost:{}", application.port());
     let port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -312,14 +782,43 @@ pub async fn spawn_app() -> TestApp {
         port,
         test_user: TestUser::generate(),
         api_client: client,
-        email_client: configuration.email_client.client()
+        email_client: configuration.email_client.clone().client(),
+        tracking: Tracking::new(configuration.application.hmac_secret.clone(), &configuration.tracking),
+        postmark_webhook: configuration.postmark_webhook.clone(),
     };
 
     test_app.test_user.store(&test_app.db_pool);
     test_app
 }
 
+pub fn get_stored_password_hash(app: &TestApp, uid: Uuid) -> String {
+    use newsletter::schema::users::dsl::*;
+    use diesel::prelude::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    users.select(password)
+        .filter(user_id.eq(uid))
+        .first::<String>(&mut conn)
+        .expect("Failed to fetch stored password hash")
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location); 
 }
+
+pub async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    let _mock_guard = Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap();
+    app.post_subscriptions(body).await.error_for_status().unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+}
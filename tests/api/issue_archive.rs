@@ -0,0 +1,68 @@
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber_with_email, spawn_app};
+
+#[actix_web::test]
+async fn the_archive_shows_delivery_progress() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia@example.com").await;
+    app.login().await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    // Nothing has gone out yet: both recipients are still queued.
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains(r#"<meta http-equiv="refresh" content="10">"#));
+    assert!(html_page.contains("<td>sending</td>"));
+    assert!(html_page.contains("<td>2</td><td>0</td><td>0</td><td>2</td><td>calculating</td>"));
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_issues_html().await;
+    assert!(!html_page.contains(r#"http-equiv="refresh""#));
+    assert!(html_page.contains("<td>sent</td>"));
+    assert!(html_page.contains("<td>2</td><td>1</td><td>1</td><td>0</td><td>done</td>"));
+}
+
+#[actix_web::test]
+async fn the_archive_can_be_filtered_by_status() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    app.create_draft("Still a draft").await;
+    let issue_id = app.create_draft("Soon to be scheduled").await;
+    app.post_schedule_issue(&issue_id, "2099-01-01T09:00").await;
+
+    let html_page = app.get_issues("status=draft").await.text().await.unwrap();
+    assert!(html_page.contains("Still a draft"));
+    assert!(!html_page.contains("Soon to be scheduled"));
+
+    let html_page = app.get_issues("status=scheduled").await.text().await.unwrap();
+    assert!(!html_page.contains("Still a draft"));
+    assert!(html_page.contains("Soon to be scheduled"));
+
+    let response = app.get_issues("status=archived").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,87 @@
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{assert_is_redirect_to, spawn_app}, newsletter_delivery::create_confirmed_subscriber};
+
+#[actix_web::test]
+async fn published_html_is_sanitized_and_styles_are_inlined() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": r#"<style>p { color: red }</style><p onclick="steal()">Hi</p><script>alert(1)</script><img src="/logo.png"><a href="/archive">Archive</a>"#,
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/newsletter");
+
+    let html_page = app.get_delivery_html().await;
+    assert!(html_page.contains("<p><i>Successfully sent newsletter.</i></p>"));
+    assert!(html_page.contains("<p><i>Removed unsafe markup: &lt;script&gt;, onclick attributes.</i></p>"));
+    assert!(html_page.contains("<p><i>An image has no alt text: /logo.png.</i></p>"));
+    assert!(html_page.contains("<p><i>The link /archive is relative and will not work in an email.</i></p>"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["HtmlBody"],
+        r#"<p style="color: red">Hi</p><img src="/logo.png"><a href="/archive">Archive</a>"#
+    );
+}
+
+#[actix_web::test]
+async fn issues_with_nothing_left_to_send_cannot_be_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<script>alert(1)</script>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 400);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The HTML has no content left once unsafe markup is removed.</i></p>"));
+
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<form><input name=\"q\"></form>",
+    }))
+    .await;
+    let issue_id = response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/admin/issues/")
+        .to_string();
+
+    let response = app.post_publish_issue(&issue_id, &uuid::Uuid::new_v4().to_string()).await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issue_html(&issue_id).await;
+    assert!(html_page.contains("<p><i>The HTML has no content left once unsafe markup is removed.</i></p>"));
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<td>draft</td>"));
+
+    app.dispatch_all_pending_emails().await;
+}
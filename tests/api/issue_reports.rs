@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Publishes an issue with a link and open tracking, delivers it and returns
+/// its id along with the emails as the email API received them.
+async fn send_issue(app: &TestApp, issue_title: &str) -> (String, Vec<serde_json::Value>) {
+    let already_received = app.email_server.received_requests().await.unwrap().len();
+    let response = app.post_delivery(&serde_json::json!({
+        "title": issue_title,
+        "text": "Read it at https://example.com/post",
+        "html": r#"<html><body><a href="https://example.com/post">Read it</a></body></html>"#,
+        "track_opens": "true",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    app.dispatch_all_pending_emails().await;
+
+    let issue_id = {
+        use newsletter::schema::newsletter_issues::dsl::*;
+
+        let mut conn = app.db_pool.get().unwrap();
+        newsletter_issues
+            .filter(title.eq(issue_title))
+            .select(newsletter_issue_id)
+            .first::<Uuid>(&mut conn)
+            .unwrap()
+    };
+    let emails = app.email_server.received_requests().await.unwrap()[already_received..]
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    (issue_id.to_string(), emails)
+}
+
+fn email_to<'a>(emails: &'a [serde_json::Value], recipient: &str) -> &'a str {
+    emails
+        .iter()
+        .find(|e| e["To"] == recipient)
+        .unwrap()["HtmlBody"]
+        .as_str()
+        .unwrap()
+}
+
+fn tracking_url(html: &str, kind: &str) -> String {
+    let start = html.find(&format!("/t/{}/", kind)).unwrap();
+    let start = html[..start].rfind('"').unwrap() + 1;
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_string()
+}
+
+/// Opens and clicks are stored in the background.
+async fn wait_for_engagement(app: &TestApp, opens: i64, clicks: i64) {
+    use newsletter::schema::{issue_clicks, issue_opens};
+
+    for _ in 0..50 {
+        let mut conn = app.db_pool.get().unwrap();
+        let stored_opens = issue_opens::table.count().get_result::<i64>(&mut conn).unwrap();
+        let stored_clicks = issue_clicks::table.count().get_result::<i64>(&mut conn).unwrap();
+        if stored_opens >= opens && stored_clicks >= clicks {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The engagement was not recorded in time.");
+}
+
+#[actix_web::test]
+async fn the_report_sums_up_deliveries_opens_clicks_and_unsubscribes() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com", "lurker@example.com"]).await;
+    let (issue_id, emails) = send_issue(&app, "Report me").await;
+
+    let html = email_to(&emails, "reader@example.com");
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    client.get(tracking_url(html, "open")).send().await.unwrap();
+    client.get(tracking_url(html, "click")).send().await.unwrap();
+    client.get(tracking_url(html, "click")).send().await.unwrap();
+    wait_for_engagement(&app, 1, 2).await;
+    app.unsubscribe("reader@example.com").await;
+
+    let issues_html = app.get_issues_html().await;
+    assert!(issues_html.contains(&format!(r#"<a href="/admin/issues/{}/report">Report</a>"#, issue_id)));
+
+    let response = app.api_client
+        .get(format!("{}/admin/issues/{}/report", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let report = response.text().await.unwrap();
+    assert!(report.contains("<h1>Report: Report me</h1>"));
+    assert!(report.contains("<tr><th>Recipients</th><td>2</td></tr>"));
+    assert!(report.contains("<tr><th>Delivered</th><td>2</td></tr>"));
+    assert!(report.contains("<tr><th>Failed</th><td>0</td></tr>"));
+    assert!(report.contains("<tr><th>Bounced</th><td>0 (0.0%)</td></tr>"));
+    assert!(report.contains("<tr><th>Unique opens</th><td>1 (50.0%)</td></tr>"));
+    assert!(report.contains("<tr><th>Unique clicks</th><td>1 (50.0%)</td></tr>"));
+    assert!(report.contains("<tr><th>Unsubscribes</th><td>1 (50.0%)</td></tr>"));
+    assert!(report.contains("<tr><td>https://example.com/post</td><td>1</td><td>2</td></tr>"));
+    assert!(report.contains("<tr><td>0-1</td><td>1</td>"));
+    assert!(report.contains("<tr><td>71-72</td><td>0</td>"));
+
+    let response = app.api_client
+        .get(format!("{}/admin/issues/{}/report/export", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "text/csv; charset=utf-8");
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "email,delivery,attempted_at,bounced_at,first_opened_at,opens,clicks,unsubscribed");
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("lurker@example.com,sent,"));
+    assert!(lines[1].ends_with(",,,0,0,false"));
+    assert!(lines[2].starts_with("reader@example.com,sent,"));
+    assert!(lines[2].ends_with(",1,2,true"));
+}
+
+#[actix_web::test]
+async fn unsubscribes_are_attributed_to_the_last_issue_sent_before_leaving() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com", "lurker@example.com"]).await;
+    let (first_issue, _) = send_issue(&app, "First").await;
+    let (second_issue, _) = send_issue(&app, "Second").await;
+    app.unsubscribe("reader@example.com").await;
+
+    for (issue_id, unsubscribes) in [(first_issue, "0 (0.0%)"), (second_issue, "1 (50.0%)")] {
+        let report = app.api_client
+            .get(format!("{}/admin/issues/{}/report", app.address, issue_id))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(report.contains(&format!("<tr><th>Unsubscribes</th><td>{}</td></tr>", unsubscribes)));
+    }
+}
+
+#[actix_web::test]
+async fn reports_are_only_available_to_logged_in_users() {
+    let app = spawn_app().await;
+
+    for path in ["report", "report/export"] {
+        let response = app.api_client
+            .get(format!("{}/admin/issues/{}/{}", app.address, Uuid::new_v4(), path))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    app.login().await;
+    let response = app.api_client
+        .get(format!("{}/admin/issues/{}/report", app.address, Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,207 @@
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{assert_is_redirect_to, spawn_app}, newsletter_delivery::create_confirmed_subscriber};
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_issues() {
+    let app = spawn_app().await;
+
+    let response = app.get_issue("new").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "Newsletter html",
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers()["Location"], "/login");
+}
+
+#[actix_web::test]
+async fn saving_a_draft_does_not_send_anything() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = app.create_draft("My <first> draft").await;
+
+    let html_page = app.get_issue_html(&issue_id).await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains("My &lt;first&gt; draft"));
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<td>draft</td>"));
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_web::test]
+async fn drafts_can_be_edited_and_deleted() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let issue_id = app.create_draft("Original title").await;
+
+    let response = app.post_update_issue(&issue_id, &serde_json::json!({
+        "title": "Updated title",
+        "text": "Updated text",
+        "html": "<p>Updated html</p>",
+    }))
+    .await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+
+    let html_page = app.get_issue_html(&issue_id).await;
+    assert!(html_page.contains("Updated title"));
+    assert!(html_page.contains("&lt;p&gt;Updated html&lt;/p&gt;"));
+
+    let response = app.post_delete_issue(&issue_id).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The draft has been deleted.</i></p>"));
+    assert!(!html_page.contains("Updated title"));
+}
+
+#[actix_web::test]
+async fn publishing_a_draft_delivers_it_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = app.create_draft("Newsletter Title").await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    let response = app.post_publish_issue(&issue_id, &idempotency_key).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The issue has been published.</i></p>"));
+
+    // Resubmitting replays the saved response without a message of its own.
+    let response = app.post_publish_issue(&issue_id, &idempotency_key).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_issues_html().await;
+    assert!(!html_page.contains("<p><i>The issue has been published.</i></p>"));
+    assert!(html_page.contains("<td>sending</td>"));
+
+    // A published issue is no longer a draft.
+    let response = app.post_publish_issue(&issue_id, &uuid::Uuid::new_v4().to_string()).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>Only drafts can be published.</i></p>"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<td>sent</td>"));
+}
+
+#[actix_web::test]
+async fn previews_render_the_issue_in_a_sandboxed_frame() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let issue_id = app.create_draft("Preview <me>").await;
+
+    let response = app.get_issue_preview(&issue_id, "").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Subject: Preview &lt;me&gt;"));
+    assert!(html_page.contains(r#"<iframe sandbox="" srcdoc="&lt;p&gt;Newsletter html&lt;/p&gt;""#));
+    assert!(html_page.contains("<pre>Newsletter text</pre>"));
+    assert!(html_page.contains("Sample Subscriber"));
+}
+
+#[actix_web::test]
+async fn previews_can_be_rendered_for_a_chosen_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    let issue_id = app.create_draft("Newsletter Title").await;
+
+    let html_page = app
+        .get_issue_preview(&issue_id, "subscriber=ursula_le_guin%40gmail.com")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("To: le guin &lt;ursula_le_guin@gmail.com&gt;"));
+
+    let html_page = app
+        .get_issue_preview(&issue_id, "subscriber=nobody%40example.com")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("There is no subscriber with the address nobody@example.com."));
+    assert!(html_page.contains("Sample Subscriber"));
+
+    let response = app.get_issue_preview(&uuid::Uuid::new_v4().to_string(), "").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn previews_of_drafts_show_the_email_publishing_would_send() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Going Public",
+        "text": "Newsletter text",
+        "html": "<style>p { color: red }</style><p>Hi</p><script>alert(1)</script>",
+        "is_public": true,
+    }))
+    .await;
+    let issue_id = response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/admin/issues/")
+        .to_string();
+
+    let html_page = app.get_issue_preview(&issue_id, "").await.text().await.unwrap();
+    assert!(html_page.contains("Removed unsafe markup"));
+    assert!(html_page.contains("&lt;p style=&quot;color: red&quot;&gt;Hi&lt;/p&gt;"));
+    assert!(!html_page.contains("alert(1)"));
+    assert!(html_page.contains("/issues/going-public"));
+}
+
+#[actix_web::test]
+async fn markdown_drafts_keep_their_source_for_editing() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Newsletter Title",
+        "markdown": "Some **bold** news <script>alert(1)</script>",
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    let issue_id = response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/admin/issues/")
+        .to_string();
+
+    let html_page = app.get_issue_html(&issue_id).await;
+    // The Markdown source, escaped for the textarea.
+    assert!(html_page.contains("Some **bold** news &lt;script&gt;"));
+    // The generated, sanitized HTML.
+    assert!(html_page.contains("&lt;strong&gt;bold&lt;/strong&gt;"));
+    assert!(!html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt;&lt;/p&gt;"));
+}
@@ -1,6 +1,7 @@
+use argon2::{Algorithm, Params};
 use reqwest::Response;
 use std::fmt::Write;
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, get_stored_password_hash, spawn_app, TestUser};
 
 
 #[actix_web::test]
@@ -40,6 +41,58 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
 
+#[actix_web::test]
+async fn login_upgrades_a_hash_with_weaker_parameters() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store_with(&app.db_pool, Algorithm::Argon2id, Params::new(4096, 1, 1, None).unwrap());
+
+    let login_body = serde_json::json!({
+        "username": &user.username,
+        "password": &user.password
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let stored_hash = get_stored_password_hash(&app, user.user_id);
+    assert!(stored_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+}
+
+#[actix_web::test]
+async fn login_upgrades_a_hash_using_an_older_algorithm() {
+    let app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store_with(&app.db_pool, Algorithm::Argon2i, Params::new(15000, 2, 1, None).unwrap());
+
+    let login_body = serde_json::json!({
+        "username": &user.username,
+        "password": &user.password
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let stored_hash = get_stored_password_hash(&app, user.user_id);
+    assert!(stored_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+}
+
+#[actix_web::test]
+async fn login_keeps_a_hash_matching_the_current_parameters() {
+    let app = spawn_app().await;
+    let original_hash = get_stored_password_hash(&app, app.test_user.user_id);
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    assert_eq!(get_stored_password_hash(&app, app.test_user.user_id), original_hash);
+}
+
 async fn pretty_print_response(response: Response) -> String {
     let mut output = String::new();
 
@@ -6,3 +6,23 @@ mod newsletter_delivery;
 mod login;
 mod admin_dashboard;
 mod change_password;
+mod admin_sessions;
+mod csrf;
+mod audit;
+mod issues;
+mod scheduled_issues;
+mod templates;
+mod merge_tags;
+mod issue_html;
+mod issue_archive;
+mod sending_controls;
+mod open_tracking;
+mod click_tracking;
+mod issue_reports;
+mod postmark_webhook;
+mod suppressions;
+mod subject_tests;
+mod public_archive;
+mod feeds;
+mod feed_poller;
+mod welcome_sequence;
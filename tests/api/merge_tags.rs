@@ -0,0 +1,115 @@
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{assert_is_redirect_to, spawn_app}, templates::subscribe_and_confirm};
+
+#[actix_web::test]
+async fn merge_tags_are_filled_in_for_each_subscriber() {
+    let app = spawn_app().await;
+    subscribe_and_confirm(&app, "Ursula & Le Guin").await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "News for {{name}}",
+        "html": "<p>Hi {{name}}, this was sent to {{email}}.</p>",
+        "text": "Hi {{name}}. Unsubscribe: {{unsubscribe_url}}",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "News for Ursula & Le Guin");
+    assert_eq!(
+        body["HtmlBody"],
+        "<p>Hi Ursula &amp; Le Guin, this was sent to ursula_le_guin@gmail.com.</p>"
+    );
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.starts_with(&format!(
+        "Hi Ursula & Le Guin. Unsubscribe: {}/subscriptions/unsubscribe?subscription_token=",
+        app.address
+    )));
+}
+
+#[actix_web::test]
+async fn merge_tags_work_in_markdown_issues() {
+    let app = spawn_app().await;
+    subscribe_and_confirm(&app, "Ursula").await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "markdown": "Hello **{{name|default('there')}}**!",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"].as_str().unwrap().contains("Hello <strong>Ursula</strong>!"));
+    assert_eq!(body["TextBody"], "Hello Ursula!");
+}
+
+#[actix_web::test]
+async fn invalid_merge_tags_are_rejected_on_the_form() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let cases = [
+        ("Hi {{nmae}}", "unknown merge tags: nmae"),
+        ("Hi {{name", "invalid merge tag"),
+        ("Hi {{name|no_such_filter}}", "invalid merge tag"),
+    ];
+    for (text, error) in cases {
+        let response = app.post_delivery(&serde_json::json!({
+            "title": "Newsletter Title",
+            "text": text,
+            "html": "<p>Newsletter html</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+        assert_eq!(response.status().as_u16(), 400);
+
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.contains(error), "{} was not rejected", text);
+        // What was typed is kept on the form.
+        assert!(html_page.contains(&format!(">{}</textarea>", text)));
+    }
+
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Hello {{nmae}}",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/admin/issues/new");
+    let html_page = app.get_issue_html("new").await;
+    assert!(html_page.contains("The title uses unknown merge tags: nmae."));
+
+    app.dispatch_all_pending_emails().await;
+}
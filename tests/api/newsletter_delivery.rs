@@ -1,7 +1,7 @@
 
 use wiremock::{matchers::{any, method, path}, Mock, ResponseTemplate};
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{spawn_app, spawn_app_with, ConfirmationLinks, TestApp};
 
 #[actix_web::test]
 async fn newsletter_form_on_get_endpoint_works(){
@@ -121,7 +121,7 @@ async fn newsletter_delivery_returns_400_for_invalid_data(){
 }
 
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
     let _mock_guard = Mock::given(any())
@@ -145,10 +145,10 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
+pub async fn create_confirmed_subscriber(app: &TestApp) {
     let confirmation_link = create_unconfirmed_subscriber(app).await;
     reqwest::get(confirmation_link.html)
         .await
@@ -157,7 +157,6 @@ async fn create_confirmed_subscriber(app: &TestApp) {
         .unwrap();
 }
 
-
 #[actix_web::test]
 async fn newsletter_creation_is_idempotent(){
     let app = spawn_app().await;
@@ -224,3 +223,178 @@ async fn concurrent_form_submission_is_handled_gracefully() {
 
     app.dispatch_all_pending_emails().await;
 }
+
+#[actix_web::test]
+async fn test_issues_are_sent_only_to_the_chosen_addresses() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+
+    let test_guard = Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app.post_test_issue(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "Newsletter html",
+        "idempotency_key": &idempotency_key,
+        "test_recipients": "first@example.com, second@example.com"
+    })).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Test issue sent to first@example.com.</i></p>"));
+    assert!(html_page.contains("<p><i>Test issue sent to second@example.com.</i></p>"));
+    assert!(html_page.contains(&idempotency_key));
+
+    let received = test_guard.received_requests().await;
+    for request in &received {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["Subject"], "[TEST] Newsletter Title");
+    }
+    let recipients: Vec<_> = received
+        .iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"].clone())
+        .collect();
+    assert_eq!(recipients, vec!["first@example.com", "second@example.com"]);
+    drop(test_guard);
+
+    // Nothing was stored or queued.
+    let mut conn = app.db_pool.get().unwrap();
+    {
+        use diesel::prelude::*;
+        use newsletter::schema::newsletter_issues::dsl::*;
+        let issues: i64 = newsletter_issues.count().get_result(&mut conn).unwrap();
+        assert_eq!(issues, 0);
+    }
+
+    // The idempotency key is still available for the real submission.
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "Newsletter html",
+        "idempotency_key": &idempotency_key
+    })).await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_web::test]
+async fn test_issues_are_prepared_like_published_ones() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let test_guard = Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_test_issue(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<style>p { color: red }</style><p>Hi</p><script>alert(1)</script>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "test_recipients": "reviewer@example.com"
+    })).await;
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Removed unsafe markup"));
+
+    let received = test_guard.received_requests().await;
+    let body: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+    assert_eq!(body["HtmlBody"], r#"<p style="color: red">Hi</p>"#);
+}
+
+#[actix_web::test]
+async fn test_issues_require_valid_addresses() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (recipients, message) in [
+        ("", "Enter at least one address to send a test issue to."),
+        ("reviewer@example.com, not-an-email", "not-an-email is not a valid subscriber email."),
+    ] {
+        let response = app.post_test_issue(&serde_json::json!({
+            "title": "Newsletter Title",
+            "text": "Newsletter text",
+            "html": "Newsletter html",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "test_recipients": recipients
+        })).await;
+        assert_eq!(response.status().as_u16(), 200);
+
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.contains(&format!("<p><i>{}</i></p>", message)));
+    }
+}
+
+#[actix_web::test]
+async fn markdown_issues_are_delivered_as_html_and_plain_text() {
+    // Links are compared as written, not as rewritten by the click tracker.
+    let app = spawn_app_with(|c| c.tracking.click_tracking = false).await;
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    })).await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let markdown_body = "# Hello\n\nRead [the post](https://example.com/post).";
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "markdown": markdown_body,
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })).await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"].as_str().unwrap().contains("<h1>Hello</h1>"));
+    assert_eq!(
+        body["TextBody"],
+        "Hello\n=====\n\nRead the post [1].\n\nLinks:\n[1] https://example.com/post"
+    );
+
+    use diesel::prelude::*;
+    use newsletter::schema::newsletter_issues::dsl::*;
+    let mut conn = app.db_pool.get().unwrap();
+    let stored: Option<String> = newsletter_issues.select(markdown).first(&mut conn).unwrap();
+    assert_eq!(stored.as_deref(), Some(markdown_body));
+}
@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use uuid::Uuid;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{spawn_app, spawn_app_with, TestApp}, newsletter_delivery::create_confirmed_subscriber};
+
+/// Sends an issue to a single confirmed subscriber and returns its HTML part.
+async fn send_issue(app: &TestApp, track_opens: bool) -> String {
+    create_confirmed_subscriber(app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut body = serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<html><body><p>Newsletter html</p></body></html>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    if track_opens {
+        body["track_opens"] = "true".into();
+    }
+    let response = app.post_delivery(&body).await;
+    assert_eq!(response.status().as_u16(), 303);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_string()
+}
+
+fn pixel_url(html: &str) -> Option<String> {
+    let start = html.find("http://localhost")?;
+    let end = start + html[start..].find('"')?;
+    let url = &html[start..end];
+    url.contains("/t/open/").then(|| url.to_string())
+}
+
+/// Opens are stored in the background; wait until the expected count lands.
+async fn wait_for_open_count(app: &TestApp, expected: i32) -> (i32, Option<String>) {
+    use newsletter::schema::issue_opens::dsl::*;
+
+    for _ in 0..50 {
+        let mut conn = app.db_pool.get().unwrap();
+        let row = issue_opens
+            .select((open_count, user_agent))
+            .first::<(i32, Option<String>)>(&mut conn)
+            .optional()
+            .unwrap();
+        if let Some(row) = row {
+            if row.0 >= expected {
+                return row;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The open was not recorded in time.");
+}
+
+#[actix_web::test]
+async fn opens_are_recorded_for_issues_that_opt_in() {
+    let app = spawn_app().await;
+    let html = send_issue(&app, true).await;
+
+    let pixel_url = pixel_url(&html).expect("The email has no tracking pixel");
+    assert!(html.ends_with(r#"style="display: block; border: 0; width: 1px; height: 1px">"#));
+
+    let client = reqwest::Client::new();
+    let response = client.get(&pixel_url).header("User-Agent", "Mail/1.0").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    assert_eq!(response.bytes().await.unwrap().len(), 43);
+    assert_eq!(wait_for_open_count(&app, 1).await, (1, Some("Mail/1.0".to_string())));
+
+    client.get(&pixel_url).header("User-Agent", "Other/2.0").send().await.unwrap();
+    assert_eq!(wait_for_open_count(&app, 2).await, (2, Some("Mail/1.0".to_string())));
+}
+
+#[actix_web::test]
+async fn issues_do_not_carry_a_pixel_unless_they_opt_in() {
+    let app = spawn_app().await;
+    let html = send_issue(&app, false).await;
+    assert_eq!(html, "<p>Newsletter html</p>");
+}
+
+#[actix_web::test]
+async fn open_tracking_can_be_turned_off_in_the_configuration() {
+    let app = spawn_app_with(|c| c.tracking.open_tracking = false).await;
+    let html = send_issue(&app, true).await;
+    assert_eq!(pixel_url(&html), None);
+}
+
+#[actix_web::test]
+async fn forged_open_tokens_still_get_an_image_but_are_not_recorded() {
+    let app = spawn_app().await;
+    let html = send_issue(&app, true).await;
+    let pixel_url = pixel_url(&html).unwrap();
+    let forged = format!("{}x", pixel_url);
+
+    let response = reqwest::get(&forged).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+
+    // A genuine open queued after the forged one shows the recorder has
+    // caught up, and it is the only one stored.
+    reqwest::get(&pixel_url).await.unwrap();
+    assert_eq!(wait_for_open_count(&app, 1).await.0, 1);
+}
@@ -0,0 +1,208 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{spawn_app, TestApp}, newsletter_delivery::create_confirmed_subscriber};
+
+const SUBSCRIBER_EMAIL: &str = "ursula_le_guin@gmail.com";
+
+fn bounce(bounce_type: &str, metadata: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": if bounce_type == "SpamComplaint" { "SpamComplaint" } else { "Bounce" },
+        "ID": 42,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "MessageID": Uuid::new_v4().to_string(),
+        "Email": SUBSCRIBER_EMAIL,
+        "BouncedAt": "2024-10-20T09:00:00.1234567Z",
+        "Description": "The server was unable to deliver your message.",
+        "Metadata": metadata,
+        "MessageStream": "outbound"
+    })
+}
+
+/// Kind, subscriber, issue and bounce type of each stored event.
+type StoredEvent = (String, Option<Uuid>, Option<Uuid>, Option<String>);
+
+fn stored_events(app: &TestApp) -> Vec<StoredEvent> {
+    use newsletter::schema::delivery_events::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    delivery_events
+        .select((kind, subscriber_id, newsletter_issue_id, bounce_type))
+        .order(received_at.asc())
+        .load(&mut conn)
+        .unwrap()
+}
+
+fn subscriber_status(app: &TestApp) -> String {
+    use newsletter::schema::subscriptions::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    subscriptions
+        .filter(email.eq(SUBSCRIBER_EMAIL))
+        .select(status)
+        .first(&mut conn)
+        .unwrap()
+}
+
+fn suppression_reason(app: &TestApp) -> Option<String> {
+    use newsletter::schema::suppressions::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    suppressions
+        .filter(email.eq(SUBSCRIBER_EMAIL))
+        .select(reason)
+        .first(&mut conn)
+        .optional()
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    let url = format!("{}/webhooks/postmark", app.address);
+    let payload = bounce("HardBounce", serde_json::Value::Null);
+
+    let response = app.api_client.post(&url).json(&payload).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], r#"Basic realm="webhooks""#);
+
+    let response = app.api_client
+        .post(&url)
+        .basic_auth(&app.postmark_webhook.username, Some("wrong-password"))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    assert!(stored_events(&app).is_empty());
+}
+
+#[actix_web::test]
+async fn hard_bounces_are_recorded_against_the_issue_and_suppress_the_address() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    app.dispatch_all_pending_emails().await;
+
+    // The worker tags the email with the issue so the bounce can be traced back.
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let metadata = body["Metadata"].clone();
+    let issue_id: Uuid = metadata["newsletter_issue_id"].as_str().unwrap().parse().unwrap();
+
+    let response = app.post_postmark_webhook(&bounce("HardBounce", metadata)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = stored_events(&app);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "bounce");
+    assert!(events[0].1.is_some());
+    assert_eq!(events[0].2, Some(issue_id));
+    assert_eq!(events[0].3.as_deref(), Some("HardBounce"));
+    assert_eq!(subscriber_status(&app), "suppressed");
+    assert_eq!(suppression_reason(&app).as_deref(), Some("hard_bounce"));
+
+    let report = app.api_client
+        .get(format!("{}/admin/issues/{}/report", app.address, issue_id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(report.contains("<tr><th>Bounced</th><td>1 (100.0%)</td></tr>"));
+
+    // The next issue is not sent to the suppressed address; the mock expects
+    // a single email in total.
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Another Title",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_web::test]
+async fn soft_bounces_are_recorded_without_suppressing_the_address() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app.post_postmark_webhook(&bounce("SoftBounce", serde_json::json!({}))).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = stored_events(&app);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].2, None);
+    assert_eq!(subscriber_status(&app), "confirmed");
+    assert_eq!(suppression_reason(&app), None);
+}
+
+#[actix_web::test]
+async fn spam_complaints_suppress_the_address_and_retries_are_recorded_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let complaint = bounce("SpamComplaint", serde_json::Value::Null);
+
+    for _ in 0..2 {
+        let response = app.post_postmark_webhook(&complaint).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let events = stored_events(&app);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "spam_complaint");
+    assert_eq!(subscriber_status(&app), "suppressed");
+    assert_eq!(suppression_reason(&app).as_deref(), Some("spam_complaint"));
+}
+
+#[actix_web::test]
+async fn deliveries_are_recorded_and_other_record_types_acknowledged() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Delivery",
+        "MessageID": Uuid::new_v4().to_string(),
+        "Recipient": SUBSCRIBER_EMAIL,
+        "DeliveredAt": "2024-10-20T09:00:00-04:00",
+        "Details": "Test delivery webhook details"
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Open",
+        "Recipient": SUBSCRIBER_EMAIL
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = stored_events(&app);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "delivery");
+    assert_eq!(subscriber_status(&app), "confirmed");
+
+    let response = app.post_postmark_webhook(&serde_json::json!({ "RecordType": "Bounce" })).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,94 @@
+use chrono::Utc;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[actix_web::test]
+async fn public_issues_are_listed_and_readable_without_personal_content() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    app.publish_issue("Rust & Friends #3", true).await;
+
+    let today = Utc::now().format("%Y-%m-%d").to_string();
+    let archive = app.get_page("/issues").await.text().await.unwrap();
+    assert!(archive.contains(&format!(
+        r#"<li><a href="/issues/rust-friends-3">Rust &amp; Friends #3</a> <small>{}</small></li>"#,
+        today
+    )));
+
+    let response = app.get_page("/issues/rust-friends-3").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("<h1>Rust &amp; Friends #3</h1>"));
+    assert!(page.contains(&format!(r#"<time datetime="{0}">{0}</time>"#, today)));
+    assert!(page.contains(r#"<p>Hi reader, read <a href="https://example.com/post">this</a>.</p>"#));
+    assert!(!page.contains("Unsubscribe"));
+    assert!(!page.contains("/t/open/"));
+    assert!(!page.contains("/t/click/"));
+    assert!(!page.contains("<body><p>"));
+}
+
+#[actix_web::test]
+async fn emails_of_public_issues_link_to_the_public_copy() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    app.publish_issue("Weekly", true).await;
+    app.publish_issue("Private", false).await;
+
+    let emails: Vec<serde_json::Value> = app.email_server.received_requests().await.unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    let public = emails.iter().find(|e| e["Subject"] == "Weekly").unwrap();
+    let html = public["HtmlBody"].as_str().unwrap();
+    let text = public["TextBody"].as_str().unwrap();
+    assert!(html.starts_with(r#"<p style="text-align: center; font-size: 12px"><a href=""#));
+    assert!(html.contains(r#"/issues/weekly">View in browser</a></p>"#));
+    assert!(text.starts_with("View in browser: "));
+    assert!(text.lines().next().unwrap().ends_with("/issues/weekly"));
+
+    let private = emails.iter().find(|e| e["Subject"] == "Private").unwrap();
+    assert!(!private["HtmlBody"].as_str().unwrap().contains("View in browser"));
+    assert!(!private["TextBody"].as_str().unwrap().contains("View in browser"));
+}
+
+#[actix_web::test]
+async fn issues_can_be_made_public_or_private_after_publishing() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    let issue_id = app.publish_issue("Private", false).await;
+
+    assert!(!app.get_page("/issues").await.text().await.unwrap().contains("/issues/private"));
+    assert_eq!(app.get_page("/issues/private").await.status().as_u16(), 404);
+
+    let response = app.post_issue_action(&issue_id, "make_public").await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let issues_html = app.get_issues_html().await;
+    assert!(issues_html.contains("<p><i>The issue is now in the public archive.</i></p>"));
+    assert!(issues_html.contains(r#"<a href="/issues/private">Public page</a>"#));
+    assert_eq!(app.get_page("/issues/private").await.status().as_u16(), 200);
+
+    app.post_issue_action(&issue_id, "make_private").await;
+    assert_eq!(app.get_page("/issues/private").await.status().as_u16(), 404);
+
+    let audit_log = app.get_audit_log_html("").await;
+    assert!(audit_log.contains("newsletter_visibility_changed"));
+
+    // Drafts are made public with the checkbox instead.
+    let draft_id = app.create_draft("Draft").await;
+    app.post_issue_action(&draft_id, "make_public").await;
+    assert!(app.get_issues_html().await.contains("Only published issues can be added to the public archive."));
+}
+
+#[actix_web::test]
+async fn slugs_are_unique() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&["reader@example.com"]).await;
+    app.publish_issue("Weekly", true).await;
+    app.publish_issue("Weekly!", true).await;
+
+    let archive = app.get_page("/issues").await.text().await.unwrap();
+    assert!(archive.contains(r#"<a href="/issues/weekly">Weekly</a>"#));
+    assert!(archive.contains(r#"<a href="/issues/weekly-2">Weekly!</a>"#));
+    assert_eq!(app.get_page("/issues/weekly-2").await.status().as_u16(), 200);
+    assert_eq!(app.get_page("/issues/unknown").await.status().as_u16(), 404);
+}
@@ -0,0 +1,167 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use newsletter::issue_scheduler::publish_due_issues;
+use uuid::Uuid;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::{helpers::{assert_is_redirect_to, spawn_app, TestApp}, newsletter_delivery::create_confirmed_subscriber};
+
+fn in_one_hour() -> String {
+    (Utc::now() + Duration::hours(1)).format("%Y-%m-%dT%H:%M").to_string()
+}
+
+/// Pretends the scheduled time has already passed.
+fn make_due(app: &TestApp, issue_id: &str) {
+    use newsletter::schema::newsletter_issues::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    diesel::update(newsletter_issues.filter(newsletter_issue_id.eq(Uuid::parse_str(issue_id).unwrap())))
+        .set(scheduled_for.eq(Utc::now() - Duration::seconds(1)))
+        .execute(&mut conn)
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = app.create_draft("Newsletter Title").await;
+
+    let an_hour_ago = (Utc::now() - Duration::hours(1)).format("%Y-%m-%dT%H:%M").to_string();
+    let response = app.post_schedule_issue(&issue_id, &an_hour_ago).await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+
+    let html_page = app.get_issue_html(&issue_id).await;
+    assert!(html_page.contains("<p><i>The publish time must be in the future.</i></p>"));
+    assert!(app.get_issues_html().await.contains("<td>draft</td>"));
+}
+
+#[actix_web::test]
+async fn scheduled_issues_are_published_once_when_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = app.create_draft("Newsletter Title").await;
+    let response = app.post_schedule_issue(&issue_id, &in_one_hour()).await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    assert!(app.get_issues_html().await.contains("<td>scheduled</td>"));
+
+    assert!(app.run_scheduler().await.is_empty());
+
+    make_due(&app, &issue_id);
+    assert_eq!(app.run_scheduler().await, vec![Uuid::parse_str(&issue_id).unwrap()]);
+    assert!(app.run_scheduler().await.is_empty());
+
+    app.dispatch_all_pending_emails().await;
+    assert!(app.get_issues_html().await.contains("<td>sent</td>"));
+
+    // Once fired, the schedule can no longer be changed.
+    let response = app.post_unschedule_issue(&issue_id).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+}
+
+#[actix_web::test]
+async fn cancelled_schedules_do_not_fire() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = app.create_draft("Newsletter Title").await;
+    app.post_schedule_issue(&issue_id, &in_one_hour()).await;
+    make_due(&app, &issue_id);
+
+    let response = app.post_unschedule_issue(&issue_id).await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+
+    assert!(app.run_scheduler().await.is_empty());
+    app.dispatch_all_pending_emails().await;
+    assert!(app.get_issues_html().await.contains("<td>draft</td>"));
+}
+
+#[actix_web::test]
+async fn concurrent_schedulers_do_not_publish_an_issue_twice() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    let issue_id = app.create_draft("Newsletter Title").await;
+    app.post_schedule_issue(&issue_id, &in_one_hour()).await;
+    make_due(&app, &issue_id);
+
+    let runs: Vec<_> = (0..4)
+        .map(|_| {
+            let mut conn = app.db_pool.get().unwrap();
+            tokio::task::spawn_blocking(move || publish_due_issues(&mut conn))
+        })
+        .collect();
+
+    let mut published = 0;
+    for run in runs {
+        published += run.await.unwrap().unwrap().len();
+    }
+    assert_eq!(published, 1);
+
+    use newsletter::schema::issue_delivery_queue::dsl::*;
+    let mut conn = app.db_pool.get().unwrap();
+    let queued: i64 = issue_delivery_queue.count().get_result(&mut conn).unwrap();
+    assert_eq!(queued, 1);
+}
+
+#[actix_web::test]
+async fn an_issue_that_cannot_be_sent_does_not_hold_back_the_others() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+
+    let broken_id = app.create_draft("Broken").await;
+    app.post_schedule_issue(&broken_id, &in_one_hour()).await;
+    make_due(&app, &broken_id);
+    let working_id = app.create_draft("Working").await;
+    app.post_schedule_issue(&working_id, &in_one_hour()).await;
+    make_due(&app, &working_id);
+
+    // Edited behind the editor's back into something that cannot be sent.
+    {
+        use newsletter::schema::newsletter_issues::dsl::*;
+
+        let mut conn = app.db_pool.get().unwrap();
+        diesel::update(newsletter_issues.filter(newsletter_issue_id.eq(Uuid::parse_str(&broken_id).unwrap())))
+            .set(html.eq("<script>alert(1)</script>"))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    assert_eq!(app.run_scheduler().await, vec![Uuid::parse_str(&working_id).unwrap()]);
+
+    let html_page = app.get_issue_html(&broken_id).await;
+    assert!(html_page.contains("This issue was not published at its scheduled time."));
+    assert!(html_page.contains("The HTML has no content left once unsafe markup is removed."));
+
+    // Scheduling again clears the reason.
+    {
+        use newsletter::schema::newsletter_issues::dsl::*;
+
+        let mut conn = app.db_pool.get().unwrap();
+        diesel::update(newsletter_issues.filter(newsletter_issue_id.eq(Uuid::parse_str(&broken_id).unwrap())))
+            .set(html.eq("<p>Fixed</p>"))
+            .execute(&mut conn)
+            .unwrap();
+    }
+    app.post_schedule_issue(&broken_id, &in_one_hour()).await;
+    let html_page = app.get_issue_html(&broken_id).await;
+    assert!(!html_page.contains("This issue was not published at its scheduled time."));
+}
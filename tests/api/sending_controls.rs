@@ -0,0 +1,97 @@
+use newsletter::issue_delivery_worker::ExecutionOutcome;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber_with_email, spawn_app, TestApp};
+
+/// Publishes a fresh issue to the two subscribers and returns its id.
+async fn publish_to_two_subscribers(app: &TestApp) -> String {
+    create_confirmed_subscriber_with_email(app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(app, "octavia@example.com").await;
+    app.login().await;
+
+    let issue_id = app.create_draft("Newsletter Title").await;
+    let response = app.post_publish_issue(&issue_id, &uuid::Uuid::new_v4().to_string()).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    issue_id
+}
+
+#[actix_web::test]
+async fn a_paused_issue_is_not_sent_until_it_is_resumed() {
+    let app = spawn_app().await;
+    let issue_id = publish_to_two_subscribers(&app).await;
+
+    let response = app.post_issue_action(&issue_id, "pause").await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The issue has been paused. No more emails will go out until it is resumed.</i></p>"));
+    assert!(html_page.contains("<td>paused</td>"));
+    assert!(html_page.contains(&format!(r#"action="/admin/issues/{}/resume""#, issue_id)));
+
+    {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        assert!(matches!(app.dispatch_pending_email().await, ExecutionOutcome::EmptyQueue));
+    }
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_issue_action(&issue_id, "resume").await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The issue has been resumed.</i></p>"));
+    assert!(html_page.contains("<td>sent</td>"));
+}
+
+#[actix_web::test]
+async fn cancelling_an_issue_drops_the_remaining_deliveries() {
+    let app = spawn_app().await;
+    let issue_id = publish_to_two_subscribers(&app).await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    assert!(matches!(app.dispatch_pending_email().await, ExecutionOutcome::TaskCompleted));
+
+    let response = app.post_issue_action(&issue_id, "cancel").await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The issue has been cancelled. 1 recipient had already received it.</i></p>"));
+    assert!(html_page.contains("<td>cancelled</td>"));
+    assert!(html_page.contains("<td>1</td><td>1</td><td>0</td><td>0</td><td>cancelled</td>"));
+}
+
+#[actix_web::test]
+async fn only_issues_being_sent_can_be_paused_or_cancelled() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = app.create_draft("Still a draft").await;
+
+    app.post_issue_action(&issue_id, "pause").await;
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>Only issues that are being sent can be paused.</i></p>"));
+
+    app.post_issue_action(&issue_id, "resume").await;
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>Only paused issues can be resumed.</i></p>"));
+
+    app.post_issue_action(&issue_id, "cancel").await;
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>Only issues that are being sent can be cancelled.</i></p>"));
+    assert!(html_page.contains("<td>draft</td>"));
+}
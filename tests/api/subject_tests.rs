@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const READERS: [&str; 4] = [
+    "reader0@example.com",
+    "reader1@example.com",
+    "reader2@example.com",
+    "reader3@example.com",
+];
+
+/// Saves a draft with a link and two subject variants and returns its id.
+async fn create_tested_draft(app: &TestApp, sample_percent: &str, wait_minutes: &str) -> String {
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Subject test",
+        "text": "Read it at https://example.com/post",
+        "html": r#"<p><a href="https://example.com/post">Read it</a></p>"#,
+        "subject_variants": "Version A\nVersion B",
+        "sample_percent": sample_percent,
+        "wait_minutes": wait_minutes,
+        "winning_metric": "clicks",
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+    response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/admin/issues/")
+        .to_string()
+}
+
+/// Subjects and bodies of the emails received since `already_received`.
+async fn received_emails(app: &TestApp, already_received: usize) -> Vec<serde_json::Value> {
+    app.email_server.received_requests().await.unwrap()[already_received..]
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect()
+}
+
+/// The subject each recipient was recorded as being sent.
+fn recorded_subjects(app: &TestApp) -> Vec<(String, Option<String>)> {
+    use newsletter::schema::{issue_deliveries, subject_variants};
+
+    let mut conn = app.db_pool.get().unwrap();
+    issue_deliveries::table
+        .left_join(subject_variants::table)
+        .select((issue_deliveries::subscriber_email, subject_variants::subject.nullable()))
+        .order(issue_deliveries::subscriber_email.asc())
+        .load(&mut conn)
+        .unwrap()
+}
+
+fn issue_status(app: &TestApp, issue_id: &str) -> String {
+    use newsletter::schema::newsletter_issues::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    newsletter_issues
+        .filter(newsletter_issue_id.eq(Uuid::parse_str(issue_id).unwrap()))
+        .select(status)
+        .first(&mut conn)
+        .unwrap()
+}
+
+/// Clicks are stored in the background.
+async fn wait_for_clicks(app: &TestApp, clicks: i64) {
+    use newsletter::schema::issue_clicks;
+
+    for _ in 0..50 {
+        let mut conn = app.db_pool.get().unwrap();
+        if issue_clicks::table.count().get_result::<i64>(&mut conn).unwrap() >= clicks {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The click was not recorded in time.");
+}
+
+fn click_url(html: &str) -> String {
+    let start = html.find("/t/click/").unwrap();
+    let start = html[..start].rfind('"').unwrap() + 1;
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_string()
+}
+
+#[actix_web::test]
+async fn the_sample_is_split_between_variants_and_the_winner_goes_to_the_rest() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&READERS).await;
+    let issue_id = create_tested_draft(&app, "50", "0").await;
+
+    let already_received = app.email_server.received_requests().await.unwrap().len();
+    let response = app.post_publish_issue(&issue_id, &Uuid::new_v4().to_string()).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    app.dispatch_all_pending_emails().await;
+
+    // Only the sample goes out, one recipient per variant.
+    let sample = received_emails(&app, already_received).await;
+    let mut subjects: Vec<&str> = sample.iter().map(|e| e["Subject"].as_str().unwrap()).collect();
+    subjects.sort();
+    assert_eq!(subjects, vec!["Version A", "Version B"]);
+    assert_eq!(issue_status(&app, &issue_id), "sending");
+
+    let version_b = sample.iter().find(|e| e["Subject"] == "Version B").unwrap();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    client.get(click_url(version_b["HtmlBody"].as_str().unwrap())).send().await.unwrap();
+    wait_for_clicks(&app, 1).await;
+
+    assert_eq!(app.pick_subject_test_winners().await, vec![Uuid::parse_str(&issue_id).unwrap()]);
+    assert!(app.pick_subject_test_winners().await.is_empty());
+    app.dispatch_all_pending_emails().await;
+
+    let rest = &received_emails(&app, already_received).await[2..];
+    assert_eq!(rest.len(), 2);
+    assert!(rest.iter().all(|e| e["Subject"] == "Version B"));
+    assert_eq!(issue_status(&app, &issue_id), "sent");
+
+    let recorded = recorded_subjects(&app);
+    assert_eq!(recorded.len(), 4);
+    assert_eq!(recorded.iter().filter(|(_, s)| s.as_deref() == Some("Version A")).count(), 1);
+    assert_eq!(recorded.iter().filter(|(_, s)| s.as_deref() == Some("Version B")).count(), 3);
+
+    let report = app.api_client
+        .get(format!("{}/admin/issues/{}/report", app.address, issue_id))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(report.contains("<h2>Subject test</h2>"));
+    assert!(report.contains("<tr><td>Version A</td><td>1</td><td>0 (0.0%)</td><td>0 (0.0%)</td><td></td></tr>"));
+    assert!(report.contains("<tr><td>Version B</td><td>3</td><td>0 (0.0%)</td><td>1 (33.3%)</td><td>Winner</td></tr>"));
+}
+
+#[actix_web::test]
+async fn the_rest_of_the_list_waits_until_the_window_is_over() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&READERS).await;
+    let issue_id = create_tested_draft(&app, "25", "60").await;
+
+    let already_received = app.email_server.received_requests().await.unwrap().len();
+    app.post_publish_issue(&issue_id, &Uuid::new_v4().to_string()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(received_emails(&app, already_received).await.len(), 1);
+    assert!(app.pick_subject_test_winners().await.is_empty());
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(received_emails(&app, already_received).await.len(), 1);
+
+    let issues_html = app.get_issues_html().await;
+    assert!(issues_html.contains("<td>4</td><td>1</td><td>0</td><td>3</td>"));
+}
+
+#[actix_web::test]
+async fn subject_variants_are_kept_on_the_draft() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&[]).await;
+    let issue_id = create_tested_draft(&app, "30", "90").await;
+
+    let html = app.get_issue_html(&issue_id).await;
+    assert!(html.contains("Version A\nVersion B</textarea>"));
+    assert!(html.contains(r#"name="sample_percent" min="1" max="100" value="30""#));
+    assert!(html.contains(r#"name="wait_minutes" min="0" value="90""#));
+    assert!(html.contains(r#"<option value="clicks" selected>click rate</option>"#));
+
+    // Clearing the variants turns the test off.
+    let response = app.post_update_issue(&issue_id, &serde_json::json!({
+        "title": "Subject test",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+    }))
+    .await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html = app.get_issue_html(&issue_id).await;
+    assert!(html.contains(r#"name="subject_variants" rows="4" cols="80"></textarea>"#));
+}
+
+#[actix_web::test]
+async fn invalid_subject_tests_are_rejected() {
+    let app = spawn_app().await;
+    app.login_with_subscribers(&[]).await;
+
+    // Open tracking is left off.
+    for (variants, metric, error) in [
+        ("Only one", "clicks", "A subject test needs at least two subject variants."),
+        ("A\nB", "opens", "Picking the winner by open rate needs open tracking."),
+    ] {
+        let response = app.post_issue(&serde_json::json!({
+            "title": "Subject test",
+            "text": "Newsletter text",
+            "html": "<p>Newsletter html</p>",
+            "subject_variants": variants,
+            "winning_metric": metric,
+        }))
+        .await;
+        assert_is_redirect_to(&response, "/admin/issues/new");
+        let html = app.api_client
+            .get(format!("{}/admin/issues/new", app.address))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(html.contains(error));
+    }
+}
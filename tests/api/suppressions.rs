@@ -0,0 +1,183 @@
+use diesel::prelude::*;
+use uuid::Uuid;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber_with_email, spawn_app, TestApp};
+
+async fn get_suppressions_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/suppressions", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn post_suppression(app: &TestApp, path: &str, email: &str) -> reqwest::Response {
+    app.admin_post(path)
+        .await
+        .form(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn stored_suppression(app: &TestApp, address: &str) -> Option<(String, String)> {
+    use newsletter::schema::suppressions::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    suppressions
+        .filter(email.eq(address))
+        .select((reason, source))
+        .first(&mut conn)
+        .optional()
+        .unwrap()
+}
+
+/// Outcome and detail of each delivery, by recipient.
+fn deliveries(app: &TestApp) -> Vec<(String, String, Option<String>)> {
+    use newsletter::schema::issue_deliveries::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    issue_deliveries
+        .select((subscriber_email, outcome, detail))
+        .order(subscriber_email.asc())
+        .load(&mut conn)
+        .unwrap()
+}
+
+async fn publish_issue(app: &TestApp) {
+    let response = app.post_delivery(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(response.status().as_u16(), 303);
+}
+
+#[actix_web::test]
+async fn suppressed_addresses_are_not_queued_for_new_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "reader@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "Bounced@Example.com").await;
+    app.login().await;
+
+    let response = post_suppression(&app, "/admin/suppressions", "bounced@example.com").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html = get_suppressions_html(&app).await;
+    assert!(html.contains("bounced@example.com has been added to the suppression list."));
+    assert!(html.contains("<td>bounced@example.com</td>"));
+    assert_eq!(stored_suppression(&app, "bounced@example.com"), Some(("manual".to_string(), "admin".to_string())));
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let deliveries = deliveries(&app);
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].0, "reader@example.com");
+}
+
+#[actix_web::test]
+async fn addresses_suppressed_after_being_queued_are_skipped() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "reader@example.com").await;
+    app.login().await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    publish_issue(&app).await;
+    post_suppression(&app, "/admin/suppressions", "reader@example.com").await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        deliveries(&app),
+        vec![("reader@example.com".to_string(), "skipped".to_string(), Some("suppressed".to_string()))]
+    );
+}
+
+#[actix_web::test]
+async fn unsubscribing_suppresses_the_address() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "reader@example.com").await;
+
+    app.unsubscribe("reader@example.com").await;
+
+    assert_eq!(
+        stored_suppression(&app, "reader@example.com"),
+        Some(("unsubscribed".to_string(), "unsubscribe_link".to_string()))
+    );
+}
+
+#[actix_web::test]
+async fn suppressed_addresses_are_not_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.login().await;
+    post_suppression(&app, "/admin/suppressions", "reader@example.com").await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", "READER@example.com")]).unwrap();
+    let response = app.post_subscriptions(body).await;
+
+    // The response does not give away that the address is suppressed.
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn admins_can_remove_addresses_from_the_suppression_list() {
+    let app = spawn_app().await;
+    app.login().await;
+    post_suppression(&app, "/admin/suppressions", "reader@example.com").await;
+
+    let response = post_suppression(&app, "/admin/suppressions", "reader@example.com").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    assert!(get_suppressions_html(&app).await.contains("reader@example.com is already suppressed."));
+
+    let response = post_suppression(&app, "/admin/suppressions", "not an email").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+
+    let response = post_suppression(&app, "/admin/suppressions/remove", "reader@example.com").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html = get_suppressions_html(&app).await;
+    assert!(html.contains("reader@example.com can be mailed again."));
+    assert!(!html.contains("<td>reader@example.com</td>"));
+    assert_eq!(stored_suppression(&app, "reader@example.com"), None);
+
+    let audit_log = app.get_audit_log_html("").await;
+    assert!(audit_log.contains("suppression_added"));
+    assert!(audit_log.contains("suppression_removed"));
+}
+
+#[actix_web::test]
+async fn the_suppression_list_is_only_available_to_logged_in_users() {
+    let app = spawn_app().await;
+
+    let response = app.api_client
+        .get(format!("{}/admin/suppressions", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = post_suppression(&app, "/admin/suppressions", "reader@example.com").await;
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(stored_suppression(&app, "reader@example.com"), None);
+}
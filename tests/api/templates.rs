@@ -0,0 +1,167 @@
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const CONFIRMATION_TEMPLATE_ID: &str = "5d1c6c39-2b4e-4a8e-9a53-0f8d2f6f3a11";
+
+/// Subscribes and confirms an address, returning the confirmation email.
+pub async fn subscribe_and_confirm(app: &TestApp, name: &str) -> serde_json::Value {
+    let _mock_guard = Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = serde_urlencoded::to_string([("name", name), ("email", "ursula_le_guin@gmail.com")]).unwrap();
+    app.post_subscriptions(body).await.error_for_status().unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_templates() {
+    let app = spawn_app().await;
+
+    let response = app.get_template("new").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_template(&serde_json::json!({
+        "name": "House style",
+        "html": "{{content}}",
+        "text": "{{content}}",
+    }))
+    .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_web::test]
+async fn issues_are_wrapped_in_their_template() {
+    let app = spawn_app().await;
+    subscribe_and_confirm(&app, "Ursula & Le Guin").await;
+    app.login().await;
+
+    let template_id = app.create_template(
+        r#"<h1>Hi {{subscriber.name}}</h1>{{content}}<a href="{{unsubscribe_url}}">Unsubscribe</a>"#,
+        "Hi {{subscriber.name}}\n\n{{content}}\n\nUnsubscribe: {{unsubscribe_url}}",
+    )
+    .await;
+    let html_page = app.get_templates_html().await;
+    assert!(html_page.contains("House style"));
+
+    let response = app.post_issue(&serde_json::json!({
+        "title": "Newsletter Title",
+        "text": "Newsletter text",
+        "html": "<p>Newsletter html</p>",
+        "template_id": template_id,
+    }))
+    .await;
+    let issue_id = response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/admin/issues/")
+        .to_string();
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_publish_issue(&issue_id, &uuid::Uuid::new_v4().to_string()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+
+    assert_eq!(body["Subject"], "Newsletter Title");
+    // The issue body is inserted as HTML, the subscriber's name is escaped.
+    assert!(html.contains("<h1>Hi Ursula &amp; Le Guin</h1><p>Newsletter html</p>"));
+    assert!(text.starts_with("Hi Ursula & Le Guin\n\nNewsletter text"));
+
+    let unsubscribe_link = text.rsplit("Unsubscribe: ").next().unwrap();
+    let mut unsubscribe_link = reqwest::Url::parse(unsubscribe_link).unwrap();
+    unsubscribe_link.set_port(Some(app.port)).unwrap();
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("You have been unsubscribed."));
+}
+
+#[actix_web::test]
+async fn unknown_unsubscribe_tokens_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?subscription_token=preview",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn invalid_templates_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let cases = [
+        ("<p>No slot</p>", "{{content}}", "must include {{content}}"),
+        ("{{content}} {{subscriber.nmae}}", "{{content}}", "The template is invalid"),
+        ("{{content}", "{{content}}", "The template is invalid"),
+    ];
+
+    for (html, text, error) in cases {
+        let response = app.post_template(&serde_json::json!({
+            "name": "Broken",
+            "subject": "{{issue.title}}",
+            "html": html,
+            "text": text,
+        }))
+        .await;
+        assert_is_redirect_to(&response, "/admin/templates/new");
+
+        let html_page = app.get_template_html("new").await;
+        assert!(html_page.contains(error), "{} was not rejected", html);
+    }
+
+    let html_page = app.get_templates_html().await;
+    assert!(!html_page.contains("Broken"));
+}
+
+#[actix_web::test]
+async fn the_confirmation_email_uses_its_template() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_update_template(CONFIRMATION_TEMPLATE_ID, &serde_json::json!({
+        "name": "Subscription confirmation",
+        "subject": "Confirm your subscription, {{subscriber.name}}",
+        "html": r#"<a href="{{confirmation_url}}">Confirm</a>"#,
+        "text": "Confirm at {{confirmation_url}}",
+    }))
+    .await;
+    assert_is_redirect_to(&response, &format!("/admin/templates/{}", CONFIRMATION_TEMPLATE_ID));
+
+    // The confirmation template is required, so it cannot be deleted.
+    let response = app.admin_post(&format!("/admin/templates/{}/delete", CONFIRMATION_TEMPLATE_ID))
+        .await
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/templates");
+    let html_page = app.get_templates_html().await;
+    assert!(html_page.contains("<p><i>Only issue templates can be deleted.</i></p>"));
+
+    let email = subscribe_and_confirm(&app, "Ursula").await;
+    assert_eq!(email["Subject"], "Confirm your subscription, Ursula");
+    assert!(email["TextBody"].as_str().unwrap().starts_with("Confirm at "));
+}
@@ -0,0 +1,257 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+use wiremock::{matchers::{any, method}, Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const HTML: &str = r#"<p>Hi {{name}}</p><p><a href="{{unsubscribe_url}}">Unsubscribe</a></p>"#;
+const TEXT: &str = "Hi {{name}}\n\nUnsubscribe: {{unsubscribe_url}}";
+
+async fn add_step(app: &TestApp, delay_days: u32, subject: &str) -> reqwest::Response {
+    app.admin_post("/admin/sequence")
+        .await
+        .form(&serde_json::json!({
+            "delay_days": delay_days.to_string(),
+            "subject": subject,
+            "html": HTML,
+            "text": TEXT,
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn step_id(app: &TestApp, step_subject: &str) -> Uuid {
+    use newsletter::schema::sequence_steps::dsl::*;
+
+    let mut conn = app.db_pool.get().unwrap();
+    sequence_steps
+        .filter(subject.eq(step_subject))
+        .select(sequence_step_id)
+        .first(&mut conn)
+        .unwrap()
+}
+
+async fn get_sequence_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/sequence", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+/// Subjects of the emails sent since the mock was mounted, oldest first.
+async fn sent_subjects(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .filter(|body| !body["Subject"].as_str().unwrap().starts_with("Welcome!"))
+        .map(|body| body["Subject"].as_str().unwrap().to_string())
+        .collect()
+}
+
+async fn accept_emails(app: &TestApp) {
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// Subscribes and confirms `email`. Expects `accept_emails` to be mounted.
+async fn confirm_subscriber(app: &TestApp, email: &str) {
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap();
+    app.post_subscriptions(body).await.error_for_status().unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+}
+
+#[actix_web::test]
+async fn steps_are_sent_relative_to_confirmation() {
+    let app = spawn_app().await;
+    app.login().await;
+    assert_is_redirect_to(&add_step(&app, 0, "Welcome aboard, {{name}}").await, "/admin/sequence");
+    add_step(&app, 3, "Our best posts").await;
+    add_step(&app, 7, "How are we doing?").await;
+    assert!(get_sequence_html(&app).await.contains("<p><i>The step has been added.</i></p>"));
+
+    accept_emails(&app).await;
+    confirm_subscriber(&app, "reader@example.com").await;
+
+    app.dispatch_sequence_emails(Utc::now()).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome aboard, le guin"]);
+
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(2)).await;
+    assert_eq!(sent_subjects(&app).await.len(), 1);
+
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(3) + Duration::minutes(1)).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome aboard, le guin", "Our best posts"]);
+
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(30)).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome aboard, le guin", "Our best posts", "How are we doing?"]);
+
+    // Each step goes out once.
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(60)).await;
+    assert_eq!(sent_subjects(&app).await.len(), 3);
+
+    let email = &app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email.body).unwrap();
+    assert_eq!(body["To"], "reader@example.com");
+    assert!(body["TextBody"].as_str().unwrap().starts_with(&format!(
+        "Hi le guin\n\nUnsubscribe: {}/subscriptions/unsubscribe?subscription_token=",
+        app.address
+    )));
+
+    let sequence_html = get_sequence_html(&app).await;
+    assert!(sequence_html.contains("<td>Day 3</td>"));
+    assert!(sequence_html.contains(&format!(
+        "<td><a href=\"/admin/sequence/{}\">Welcome aboard, {{{{name}}}}</a></td>\n            <td>1</td>",
+        step_id(&app, "Welcome aboard, {{name}}")
+    )));
+}
+
+#[actix_web::test]
+async fn steps_get_a_turn_while_an_issue_is_being_delivered() {
+    let app = spawn_app().await;
+    app.login().await;
+    add_step(&app, 0, "First step").await;
+
+    accept_emails(&app).await;
+    confirm_subscriber(&app, "reader@example.com").await;
+    let issue_id = app.create_draft("Newsletter Title").await;
+    app.post_publish_issue(&issue_id, &Uuid::new_v4().to_string()).await;
+
+    app.run_worker_task(true).await;
+    assert_eq!(sent_subjects(&app).await, ["First step"]);
+    app.run_worker_task(false).await;
+    assert_eq!(sent_subjects(&app).await, ["First step", "Newsletter Title"]);
+}
+
+#[actix_web::test]
+async fn the_sequence_stops_when_a_subscriber_unsubscribes() {
+    let app = spawn_app().await;
+    app.login().await;
+    add_step(&app, 0, "Welcome").await;
+    add_step(&app, 3, "Later").await;
+
+    accept_emails(&app).await;
+    confirm_subscriber(&app, "reader@example.com").await;
+    app.dispatch_sequence_emails(Utc::now()).await;
+    app.unsubscribe("reader@example.com").await;
+
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(30)).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome"]);
+}
+
+#[actix_web::test]
+async fn editing_a_step_does_not_send_it_again() {
+    let app = spawn_app().await;
+    app.login().await;
+    add_step(&app, 0, "Welcome").await;
+    add_step(&app, 5, "Later").await;
+
+    accept_emails(&app).await;
+    confirm_subscriber(&app, "first@example.com").await;
+    app.dispatch_sequence_emails(Utc::now()).await;
+
+    let welcome_id = step_id(&app, "Welcome");
+    let response = app.admin_post(&format!("/admin/sequence/{}", welcome_id))
+        .await
+        .form(&serde_json::json!({
+            "delay_days": "0",
+            "subject": "Welcome, edited",
+            "html": HTML,
+            "text": TEXT,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, &format!("/admin/sequence/{}", welcome_id));
+    app.dispatch_sequence_emails(Utc::now()).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome"]);
+
+    // Moving a step applies to subscribers still waiting for it.
+    let later_id = step_id(&app, "Later");
+    app.admin_post(&format!("/admin/sequence/{}", later_id))
+        .await
+        .form(&serde_json::json!({ "delay_days": "1", "subject": "Later", "html": HTML, "text": TEXT }))
+        .send()
+        .await
+        .unwrap();
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(1) + Duration::minutes(1)).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome", "Later"]);
+
+    // New subscribers get the edited version.
+    confirm_subscriber(&app, "second@example.com").await;
+    app.dispatch_sequence_emails(Utc::now()).await;
+    assert_eq!(sent_subjects(&app).await, ["Welcome", "Later", "Welcome, edited"]);
+}
+
+#[actix_web::test]
+async fn steps_added_later_only_reach_subscribers_who_have_not_passed_them() {
+    let app = spawn_app().await;
+    accept_emails(&app).await;
+    confirm_subscriber(&app, "reader@example.com").await;
+
+    app.login().await;
+    add_step(&app, 0, "Too late").await;
+    add_step(&app, 3, "Still ahead").await;
+
+    app.dispatch_sequence_emails(Utc::now() + Duration::days(30)).await;
+    assert_eq!(sent_subjects(&app).await, ["Still ahead"]);
+}
+
+#[actix_web::test]
+async fn invalid_steps_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for (delay_days, subject, text, message) in [
+        ("-1", "Welcome", TEXT, "The delay must be a whole number of days between 0 and 365."),
+        ("0", "", TEXT, "The subject cannot be empty."),
+        ("0", "Hi {{nickname}}", TEXT, "The title uses unknown merge tags: nickname."),
+        ("0", "Welcome", "Hi", "Both bodies of a step need an unsubscribe link: {{unsubscribe_url}}."),
+    ] {
+        let response = app.admin_post("/admin/sequence")
+            .await
+            .form(&serde_json::json!({ "delay_days": delay_days, "subject": subject, "html": HTML, "text": text }))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/admin/sequence");
+        assert!(get_sequence_html(&app).await.contains(message), "{message}");
+    }
+    assert!(get_sequence_html(&app).await.contains("There are no steps, so new subscribers get no welcome emails."));
+
+    let response = app.api_client
+        .get(format!("{}/admin/sequence/{}", app.address, Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[actix_web::test]
+async fn you_must_be_logged_in_to_manage_the_sequence() {
+    let app = spawn_app().await;
+
+    let response = app.api_client
+        .get(format!("{}/admin/sequence", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = add_step(&app, 0, "Welcome").await;
+    assert_ne!(response.status().as_u16(), 200);
+    assert!(get_sequence_html(&app).await.is_empty());
+}